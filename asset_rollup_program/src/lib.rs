@@ -1,24 +1,27 @@
 use anchor_lang::prelude::*;
 use borsh::{BorshDeserialize, BorshSerialize};
-use compiler::Instruction;
-use sha2::{Digest, Sha256};
+use curvevm::{Instruction, bytecode};
 
 declare_id!("11111111111111111111111111111111");
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone)]
 pub struct Payload {
     pub root: [u8; 32],
-    pub program: Vec<Instruction>,
+    pub bytecode: Vec<u8>,
 }
 
 pub fn serialize_program(program: &[Instruction]) -> Payload {
-    let mut hasher = Sha256::new();
-    for ins in program {
-        hasher.update(&[ins.opcode as u8]);
-        hasher.update(ins.operand.to_le_bytes());
-    }
-    let root = hasher.finalize();
-    Payload { root: root.into(), program: program.to_vec() }
+    let bytecode = bytecode::encode(program);
+    let root = bytecode::decode_header(&bytecode).expect("encoder emits a valid header").code_hash;
+    Payload { root, bytecode }
+}
+
+#[error_code]
+pub enum RollupError {
+    #[msg("batch is not valid curvevm bytecode")]
+    MalformedBytecode,
+    #[msg("batch root does not match its code hash")]
+    RootMismatch,
 }
 
 #[account]
@@ -31,6 +34,8 @@ pub mod asset_rollup_program {
     use super::*;
 
     pub fn post_batch(ctx: Context<PostBatch>, payload: Payload) -> Result<()> {
+        let (header, _) = bytecode::decode(&payload.bytecode).map_err(|_| error!(RollupError::MalformedBytecode))?;
+        require!(header.code_hash == payload.root, RollupError::RootMismatch);
         let state = &mut ctx.accounts.state;
         state.last_root = payload.root;
         Ok(())
@@ -47,13 +52,15 @@ pub struct PostBatch<'info> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use curvevm::Opcode;
+    use curvevm::{CurveId, Opcode};
 
     #[test]
     fn payload_has_expected_root() {
-        let program = vec![Instruction { opcode: Opcode::Buy, operand: 1 }];
+        let program = vec![Instruction { opcode: Opcode::Buy, operand: 1, curve: CurveId::default(), limit: 0, expiry: 0 }];
         let p = serialize_program(&program);
         let expected = serialize_program(&program);
         assert_eq!(p, expected);
+        assert_eq!(p.root, bytecode::code_hash(&program));
+        assert_eq!(bytecode::decode(&p.bytecode).unwrap().1, program);
     }
 }
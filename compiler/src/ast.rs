@@ -0,0 +1,54 @@
+use crate::Command;
+use crate::lexer::Span;
+use curvevm::{CurveKind, FeeConfig, LaunchRules, MigrationThreshold, VestingSchedule};
+
+// A CurveScript file: at most one launch declaration and any number of trade
+// statements, which run against the launched curve.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script {
+    pub launch: Option<Launch>,
+    pub trades: Vec<Trade>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Launch {
+    pub name: String,
+    pub span: Span,
+    pub items: Vec<Item>,
+}
+
+impl Launch {
+    // The parser rejects launches without exactly one curve.
+    pub fn curve(&self) -> CurveKind {
+        self.items
+            .iter()
+            .find_map(|item| match item.kind {
+                ItemKind::Curve(curve) => Some(curve),
+                _ => None,
+            })
+            .expect("launch declares a curve")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ItemKind {
+    Curve(CurveKind),
+    Supply(i64),
+    MigrateAt(MigrationThreshold),
+    ReserveRatio(u16),
+    Fees(FeeConfig),
+    Vesting { beneficiary: String, schedule: VestingSchedule },
+    Window(LaunchRules),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trade {
+    pub command: Command,
+    pub span: Span,
+}
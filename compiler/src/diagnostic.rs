@@ -0,0 +1,175 @@
+use crate::lexer::Span;
+use std::fmt;
+
+// Codes are stable so tooling can key on them:
+//   E0001 unreadable text          E0002 unexpected token
+//   E0003 unknown statement        E0004 unknown name
+//   E0005 missing argument         E0006 given more than once
+//   E0007 bad value                E0008 inconsistent settings
+//   E0009 unclosed block
+//   W0001 bound with no effect     W0002 supply capped by the curve
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// Replacing `span` with `replacement` resolves the diagnostic; an empty span
+// inserts and an empty replacement deletes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fix {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub span: Span,
+    pub message: String,
+    pub fix: Option<Box<Fix>>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, span: Span, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Error, code, span, message: message.into(), fix: None }
+    }
+
+    pub fn warning(code: &'static str, span: Span, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, code, span, message: message.into(), fix: None }
+    }
+
+    pub fn with_fix(mut self, message: impl Into<String>, span: Span, replacement: impl Into<String>) -> Self {
+        self.fix = Some(Box::new(Fix { message: message.into(), span, replacement: replacement.into() }));
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // rustc-style: a header, the location, the source line with the span
+    // underlined, and the suggested fix if there is one. Spans that run past
+    // the end of their first line are underlined to the end of it.
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let line = self.span.line(source);
+        let column = source[line_start..start].chars().count() + 1;
+        let width = source[start..self.span.end.clamp(start, line_end)].chars().count().max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        let indent: String = source[line_start..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        out += &format!("{}--> {}:{}\n", gutter, line, column);
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", line, text);
+        out += &format!("{} | {}{}\n", gutter, indent, "^".repeat(width));
+        if let Some(fix) = &self.fix {
+            out += &format!("{} = help: {}\n", gutter, fix.message);
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {} at {}..{}", self.severity, self.code, self.message, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+pub fn render(source: &str, diagnostics: &[Diagnostic]) -> String {
+    diagnostics.iter().map(|d| d.render(source)).collect::<Vec<_>>().join("\n")
+}
+
+// Applies every fix that does not overlap an earlier one.
+pub fn apply_fixes(source: &str, diagnostics: &[Diagnostic]) -> String {
+    let mut fixes: Vec<&Fix> = diagnostics.iter().filter_map(|d| d.fix.as_deref()).collect();
+    fixes.sort_by_key(|fix| (fix.span.start, fix.span.end));
+    let mut out = String::new();
+    let mut cursor = 0;
+    for fix in fixes {
+        if fix.span.start < cursor || fix.span.end > source.len() {
+            continue;
+        }
+        out += &source[cursor..fix.span.start];
+        out += &fix.replacement;
+        cursor = fix.span.end;
+    }
+    out + &source[cursor..]
+}
+
+// The candidate closest to `word` by edit distance, if it is close enough to be
+// a plausible typo.
+pub(crate) fn closest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let word = word.to_ascii_lowercase();
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&word, &candidate.to_ascii_lowercase()), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_like_rustc() {
+        let source = "BUY 5\n\tSEL 2\n";
+        let d = Diagnostic::error("E0003", Span::new(7, 10), "unknown statement `SEL`").with_fix(
+            "did you mean `SELL`?",
+            Span::new(7, 10),
+            "SELL",
+        );
+        assert_eq!(
+            d.render(source),
+            "error[E0003]: unknown statement `SEL`\n --> 2:2\n  |\n2 | \tSEL 2\n  | \t^^^\n  \
+             = help: did you mean `SELL`?\n"
+        );
+        let eof = Diagnostic::warning("W0001", Span::new(source.len(), source.len()), "at the end");
+        assert!(eof.render(source).ends_with("3 | \n  | ^\n"), "{}", eof.render(source));
+        assert_eq!(apply_fixes(source, &[d.clone(), d, eof]), "BUY 5\n\tSELL 2\n");
+    }
+
+    #[test]
+    fn suggests_only_plausible_typos() {
+        let opcodes = ["BUY", "SELL", "ADD_LIQUIDITY", "MIGRATE_TO_AMM"];
+        assert_eq!(closest("byu", opcodes), None);
+        assert_eq!(closest("buyy", opcodes), Some("BUY"));
+        assert_eq!(closest("migrate_to_am", opcodes), Some("MIGRATE_TO_AMM"));
+        assert_eq!(closest("add_liquidty", opcodes), Some("ADD_LIQUIDITY"));
+        assert_eq!(closest("launch", opcodes), None);
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+// Byte offsets into the source, end exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start.min(other.start), end: self.end.max(other.end) }
+    }
+
+    // 1-based line of the span's start.
+    pub fn line(&self, source: &str) -> usize {
+        source.as_bytes()[..self.start.min(source.len())].iter().filter(|b| **b == b'\n').count() + 1
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident(String),
+    Int(i128),
+    Str(String),
+    LBrace,
+    RBrace,
+    Eq,
+    Slash,
+    Newline,
+    // Text the lexer could not make sense of, with the reason. The parser reports
+    // it, so lexing never stops early.
+    Invalid(String),
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Ident(name) => write!(f, "`{}`", name),
+            TokenKind::Int(n) => write!(f, "`{}`", n),
+            TokenKind::Str(s) => write!(f, "\"{}\"", s),
+            TokenKind::LBrace => write!(f, "`{{`"),
+            TokenKind::RBrace => write!(f, "`}}`"),
+            TokenKind::Eq => write!(f, "`=`"),
+            TokenKind::Slash => write!(f, "`/`"),
+            TokenKind::Newline => write!(f, "end of line"),
+            TokenKind::Invalid(reason) => write!(f, "{}", reason),
+            TokenKind::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+// Identifiers are `[A-Za-z_][A-Za-z0-9_]*`, integers may be negative and use `_`
+// as a separator, strings are double-quoted without escapes and `#` starts a
+// comment. Newlines are tokens because statements end at the end of a line.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+        let kind = match c {
+            b'\n' => {
+                i += 1;
+                TokenKind::Newline
+            }
+            b' ' | b'\t' | b'\r' => {
+                i += 1;
+                continue;
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'{' | b'}' | b'=' | b'/' => {
+                i += 1;
+                match c {
+                    b'{' => TokenKind::LBrace,
+                    b'}' => TokenKind::RBrace,
+                    b'=' => TokenKind::Eq,
+                    _ => TokenKind::Slash,
+                }
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                if i < bytes.len() && bytes[i] == b'"' {
+                    i += 1;
+                    TokenKind::Str(source[start + 1..i - 1].to_string())
+                } else {
+                    TokenKind::Invalid("unterminated string".into())
+                }
+            }
+            b'0'..=b'9' | b'-' if c != b'-' || bytes.get(i + 1).is_some_and(u8::is_ascii_digit) => {
+                i += 1;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let text = &source[start..i];
+                match text.replace('_', "").parse::<i128>() {
+                    Ok(n) => TokenKind::Int(n),
+                    Err(_) if text.bytes().skip(1).all(|b| b.is_ascii_digit() || b == b'_') => {
+                        TokenKind::Invalid(format!("number `{}` is too large", text))
+                    }
+                    Err(_) => TokenKind::Invalid(format!("invalid number `{}`", text)),
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                TokenKind::Ident(source[start..i].to_string())
+            }
+            _ => {
+                let ch = source[i..].chars().next().expect("index is on a char boundary");
+                i += ch.len_utf8();
+                TokenKind::Invalid(format!("unexpected character `{}`", ch))
+            }
+        };
+        tokens.push(Token { kind, span: Span::new(start, i) });
+    }
+    tokens.push(Token { kind: TokenKind::Eof, span: Span::new(source.len(), source.len()) });
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source).into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn tokens_carry_byte_spans() {
+        let tokens = tokenize("curve linear slope=1/1_000 # comment\n  name \"dog\"");
+        let spans: Vec<_> = tokens.iter().map(|t| (t.span.start, t.span.end)).collect();
+        assert_eq!(
+            spans,
+            [(0, 5), (6, 12), (13, 18), (18, 19), (19, 20), (20, 21), (21, 26), (36, 37), (39, 43), (44, 49), (49, 49)]
+        );
+        assert_eq!(tokens[6].kind, TokenKind::Int(1_000));
+        assert_eq!(tokens[9].kind, TokenKind::Str("dog".into()));
+    }
+
+    #[test]
+    fn bad_input_becomes_invalid_tokens() {
+        assert_eq!(
+            kinds("-5 - 12ab"),
+            [
+                TokenKind::Int(-5),
+                TokenKind::Invalid("unexpected character `-`".into()),
+                TokenKind::Invalid("invalid number `12ab`".into()),
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(
+            kinds("\"open\né"),
+            [
+                TokenKind::Invalid("unterminated string".into()),
+                TokenKind::Newline,
+                TokenKind::Invalid("unexpected character `é`".into()),
+                TokenKind::Eof,
+            ]
+        );
+        let huge = "9".repeat(40);
+        assert_eq!(kinds(&huge)[0], TokenKind::Invalid(format!("number `{}` is too large", huge)));
+    }
+}
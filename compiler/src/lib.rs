@@ -1,50 +1,97 @@
-use curvevm::{Opcode, Instruction as VmInstruction};
+use curvevm::{Curve, CurveId, CurveVM, Instruction as VmInstruction, Opcode, bytecode};
 pub type Instruction = VmInstruction;
 
+pub mod ast;
+pub mod diagnostic;
+pub mod lexer;
+mod parser;
+pub mod proof;
+#[cfg(feature = "smt")]
+pub mod smt;
+mod wasm;
+pub use diagnostic::{Diagnostic, Fix, Severity, apply_fixes, render};
+pub use parser::{check_script, parse_script};
+pub use proof::{Bounds, Certificate, ProofError, prove};
+pub use wasm::compile_wasm;
+
+use ast::{ItemKind, Launch};
+
 use borsh::{BorshDeserialize, BorshSerialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
 pub struct Command {
     pub opcode: String,
     pub operand: i64,
+    pub limit: i64,
+    pub expiry: u64,
 }
 
+// A statement is `<OPCODE> <amount>`, optionally followed by `MAX <sol>` on a
+// buy, `MIN <sol>` on a sell and `UNTIL <height>` on anything. Scripts that
+// declare a launch go through `parse_script` instead.
+pub fn parse(script: &str) -> Result<Vec<Command>, Vec<Diagnostic>> {
+    let parsed = parse_script(script)?;
+    if let Some(launch) = parsed.launch {
+        let message = format!("launch `{}` in a trade script; use `parse_script`", launch.name);
+        return Err(vec![Diagnostic::error("E0003", launch.span, message)]);
+    }
+    Ok(parsed.trades.into_iter().map(|trade| trade.command).collect())
+}
 
-
-pub fn parse(script: &str) -> Result<Vec<Command>, String> {
-    let mut commands = Vec::new();
-    let allowed = ["BUY", "SELL", "ADD_LIQUIDITY", "MIGRATE_TO_AMM"];
-    for line in script.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
-        let parts: Vec<_> = line.split_whitespace().collect();
-        if parts.len() != 2 || !allowed.contains(&parts[0].to_ascii_uppercase().as_str()) {
-            return Err(format!("Invalid statement: {}", line));
-        }
-        let amount: i64 = parts[1].parse().map_err(|_| format!("Invalid amount in: {}", line))?;
-        commands.push(Command { opcode: parts[0].to_ascii_uppercase(), operand: amount });
+// Builds the curve a launch declaration describes, ready for `CurveStore::create`.
+pub fn compile_launch(launch: &Launch) -> Result<CurveVM, String> {
+    let curve = launch.curve();
+    let max_supply = launch
+        .items
+        .iter()
+        .find_map(|item| match item.kind {
+            ItemKind::Supply(supply) => Some(supply),
+            _ => None,
+        })
+        .unwrap_or(curve.max_supply());
+    let mut vm =
+        CurveVM::with_curve(curve, max_supply).map_err(|err| format!("Invalid launch {}: {}", launch.name, err))?;
+    for item in &launch.items {
+        let applied = match &item.kind {
+            ItemKind::Curve(_) | ItemKind::Supply(_) => Ok(()),
+            ItemKind::MigrateAt(threshold) => {
+                vm.migration_threshold = Some(*threshold);
+                Ok(())
+            }
+            ItemKind::ReserveRatio(bps) => {
+                vm.reserve_ratio_bps = *bps;
+                Ok(())
+            }
+            ItemKind::Fees(fees) => vm.set_fees(fees.clone()),
+            ItemKind::Vesting { beneficiary, schedule } => vm.add_vesting(beneficiary, *schedule),
+            ItemKind::Window(rules) => vm.set_launch_rules(*rules),
+        };
+        applied.map_err(|err| format!("Invalid launch {}: {}", launch.name, err))?;
     }
-    Ok(commands)
+    Ok(vm)
 }
 
 pub fn compile_program(commands: &[Command]) -> Result<Vec<Instruction>, String> {
+    compile_for_curve(CurveId::default(), commands)
+}
+
+pub fn compile_for_curve(curve: CurveId, commands: &[Command]) -> Result<Vec<Instruction>, String> {
     commands
         .iter()
         .map(|cmd| {
-            let opcode = match cmd.opcode.as_str() {
-                "BUY" => Opcode::Buy,
-                "SELL" => Opcode::Sell,
-                "ADD_LIQUIDITY" => Opcode::AddLiquidity,
-                "MIGRATE_TO_AMM" => Opcode::MigrateToAmm,
-                other => return Err(format!("Unknown command: {}", other)),
-            };
-            Ok(Instruction { opcode, operand: cmd.operand })
+            let opcode = Opcode::ALL
+                .into_iter()
+                .find(|op| op.mnemonic() == cmd.opcode)
+                .ok_or_else(|| format!("Unknown command: {}", cmd.opcode))?;
+            Ok(Instruction { opcode, operand: cmd.operand, curve, limit: cmd.limit, expiry: cmd.expiry })
         })
         .collect()
 }
 
+pub fn compile_bytecode(curve: CurveId, commands: &[Command]) -> Result<Vec<u8>, String> {
+    compile_for_curve(curve, commands).map(|program| bytecode::encode(&program))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,4 +107,84 @@ mod tests {
         assert_eq!(program[2].opcode, Opcode::AddLiquidity);
         assert_eq!(program[3].opcode, Opcode::MigrateToAmm);
     }
+
+    #[test]
+    fn compile_targets_curve() {
+        let curve = CurveId::derive("alice", "dog");
+        let cmds = parse("BUY 5\nSELL 2").unwrap();
+        let program = compile_for_curve(curve, &cmds).unwrap();
+        assert!(program.iter().all(|ins| ins.curve == curve));
+        assert!(compile_program(&cmds).unwrap().iter().all(|ins| ins.curve == CurveId::default()));
+    }
+
+    #[test]
+    fn bytecode_disassembles_to_source() {
+        let cmds = parse(
+            "buy 5\n\
+             MIGRATE_TO_AMM 1",
+        )
+        .unwrap();
+        let bytes = compile_bytecode(CurveId::default(), &cmds).unwrap();
+        let text = bytecode::disassemble(&bytes).unwrap();
+        let code: Vec<&str> = text.lines().filter(|l| !l.starts_with(';')).collect();
+        assert_eq!(code, ["0000  BUY             5", "0001  MIGRATE_TO_AMM  1"]);
+    }
+
+    #[test]
+    fn parses_slippage_bounds_and_deadlines() {
+        let cmds = parse("BUY 5 max 900 UNTIL 12\nSELL 2 MIN 100\nMIGRATE_TO_AMM 0 UNTIL 3").unwrap();
+        let program = compile_program(&cmds).unwrap();
+        assert_eq!((program[0].limit, program[0].expiry), (900, 12));
+        assert_eq!((program[1].limit, program[1].expiry), (100, 0));
+        assert_eq!((program[2].limit, program[2].expiry), (0, 3));
+        let text = bytecode::disassemble(&compile_bytecode(CurveId::default(), &cmds).unwrap()).unwrap();
+        assert!(text.contains("0000  BUY             5  limit=900  until=12"));
+
+        assert!(parse("BUY 5 MIN 900").is_err());
+        assert!(parse("SELL 5 MAX 900").is_err());
+        assert!(parse("BUY 5 MAX 1 MAX 2").is_err());
+        assert!(parse("BUY 5 UNTIL").is_err());
+        assert!(parse("BUY 5 UNTIL -1").is_err());
+    }
+
+    #[test]
+    fn launch_scripts_compile_to_a_curve() {
+        let script = parse_script(
+            "launch dog {
+                curve constant_product virtual_reserve=30_000 virtual_supply=1_000_000
+                supply 800_000
+                migrate_at reserve=40_000
+                fees 100 protocol=10_000 treasury=treasury
+                vesting alice 50_000 cliff=500 duration=10_000
+                window 3 max_buy=20_000
+            }
+            BUY 20_000 MAX 2_000
+            BUY 1 UNTIL 2",
+        )
+        .unwrap();
+        let launch = script.launch.unwrap();
+        let mut vm = compile_launch(&launch).unwrap();
+        assert_eq!(vm.max_supply, 800_000);
+        assert_eq!(vm.locked_allocation(), 50_000);
+        assert_eq!(vm.launch.max_buy, 20_000);
+
+        let commands: Vec<Command> = script.trades.into_iter().map(|trade| trade.command).collect();
+        let program = compile_program(&commands).unwrap();
+        assert_eq!(vm.execute("bob", &program[..1]).unwrap().instructions, 1);
+        assert_eq!(vm.balance_of("bob"), 20_000);
+        assert!(vm.execute("bob", &program[1..]).is_err());
+
+        let declared = "BUY 1\nlaunch dog {\n curve linear base_price=1 slope=1\n supply 9\n vesting a 10 duration=1}";
+        assert_eq!(parse(declared).unwrap_err()[0].message, "launch `dog` in a trade script; use `parse_script`");
+        let errors = parse("BUY 5\nBUY 5 MAX 1 MAX 2\nSEL 1 UNTIL 3").unwrap_err();
+        assert_eq!(
+            render("BUY 5\nBUY 5 MAX 1 MAX 2\nSEL 1 UNTIL 3", &errors),
+            "error[E0006]: `MAX` is given twice\n --> 2:13\n  |\n2 | BUY 5 MAX 1 MAX 2\n  |             ^^^\n  \
+             = help: remove it\n\n\
+             error[E0003]: unknown statement `SEL`\n --> 3:1\n  |\n3 | SEL 1 UNTIL 3\n  | ^^^\n  \
+             = help: did you mean `SELL`?\n"
+        );
+        let err = compile_launch(&parse_script(declared).unwrap().launch.unwrap()).unwrap_err();
+        assert!(err.starts_with("Invalid launch dog: "), "{}", err);
+    }
 }
@@ -0,0 +1,835 @@
+use crate::Command;
+use crate::ast::{Item, ItemKind, Launch, Script, Trade};
+use crate::diagnostic::{Diagnostic, closest};
+use crate::lexer::{Span, Token, TokenKind, tokenize};
+use curvevm::{
+    ConstantProduct, Curve, CurveKind, Exponential, FeeConfig, LaunchRules, Lbp, Linear, MigrationThreshold, Opcode,
+    Sigmoid, VestingSchedule,
+};
+use std::mem;
+use std::ops::RangeInclusive;
+
+const BPS: RangeInclusive<i128> = 0..=10_000;
+const AMOUNT: RangeInclusive<i128> = 0..=i64::MAX as i128;
+const POSITIVE: RangeInclusive<i128> = 1..=i64::MAX as i128;
+const HEIGHT: RangeInclusive<i128> = 0..=u64::MAX as i128;
+
+const SETTINGS: [&str; 7] = ["curve", "supply", "migrate_at", "reserve_ratio", "fees", "vesting", "window"];
+const CURVES: [&str; 5] = ["linear", "constant_product", "exponential", "sigmoid", "lbp"];
+
+// script    = { launch | trade | NEWLINE }
+// launch    = "launch" name "{" { [ setting ] NEWLINE } "}"
+// setting   = keyword { value } { key "=" value }
+// trade     = OPCODE int { ("MAX" | "MIN" | "UNTIL") int } NEWLINE
+// value     = int [ "/" int ] | ident | string
+// Keywords are case-insensitive.
+//
+// A statement with an error is reported and skipped up to the end of its line,
+// so one pass finds every broken statement. The returned script leaves them
+// out, along with a launch that ends up without a curve.
+pub fn check_script(source: &str) -> (Script, Vec<Diagnostic>) {
+    let mut parser = Parser { tokens: tokenize(source), pos: 0, diagnostics: Vec::new() };
+    let script = parser.script();
+    (script, parser.diagnostics)
+}
+
+// Fails if `check_script` reports any error. Warnings are only available from
+// `check_script`.
+pub fn parse_script(source: &str) -> Result<Script, Vec<Diagnostic>> {
+    match check_script(source) {
+        (_, diagnostics) if diagnostics.iter().any(Diagnostic::is_error) => Err(diagnostics),
+        (script, _) => Ok(script),
+    }
+}
+
+fn opcode(word: &str) -> Option<Opcode> {
+    Opcode::ALL.into_iter().find(|op| op.mnemonic().eq_ignore_ascii_case(word))
+}
+
+fn suggest(diagnostic: Diagnostic, word: &str, span: Span, candidates: &[&str]) -> Diagnostic {
+    match closest(word, candidates.iter().copied()) {
+        Some(name) => diagnostic.with_fix(format!("did you mean `{}`?", name), span, name),
+        None => diagnostic,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Int(i128),
+    Ratio(i128, i128),
+    Text(String),
+}
+
+struct Named {
+    key: String,
+    key_span: Span,
+    value: Value,
+    span: Span,
+}
+
+// A setting's arguments as written. Lowering takes the ones its keyword expects
+// and `finish` rejects whatever is left over.
+struct Args {
+    keyword: String,
+    keyword_span: Span,
+    span: Span,
+    positional: Vec<(Value, Span)>,
+    named: Vec<Named>,
+    used: usize,
+    asked: Vec<&'static str>,
+}
+
+impl Args {
+    fn positional(&mut self, index: usize, what: &str) -> Result<(Value, Span), Diagnostic> {
+        self.used = self.used.max(index + 1);
+        match self.positional.get(index) {
+            Some(arg) => Ok(arg.clone()),
+            None => Err(Diagnostic::error("E0005", self.span, format!("`{}` needs {}", self.keyword, what))),
+        }
+    }
+
+    fn named(&mut self, key: &'static str) -> Option<(Value, Span)> {
+        self.asked.push(key);
+        let index = self.named.iter().position(|named| named.key == key)?;
+        let named = self.named.remove(index);
+        Some((named.value, named.span))
+    }
+
+    fn int<T: TryFrom<i128>>(&mut self, key: &'static str, range: RangeInclusive<i128>) -> Result<T, Diagnostic> {
+        match self.named(key) {
+            Some(arg) => int(key, arg, range),
+            None => Err(Diagnostic::error("E0005", self.span, format!("`{}` needs `{}=`", self.keyword, key))),
+        }
+    }
+
+    fn int_or<T: TryFrom<i128>>(
+        &mut self,
+        key: &'static str,
+        default: T,
+        range: RangeInclusive<i128>,
+    ) -> Result<T, Diagnostic> {
+        self.named(key).map_or(Ok(default), |arg| int(key, arg, range))
+    }
+
+    fn text_or_empty(&mut self, key: &'static str) -> Result<String, Diagnostic> {
+        self.named(key).map_or(Ok(String::new()), |arg| text(key, arg))
+    }
+
+    fn finish(self) -> Result<Span, Diagnostic> {
+        if let Some((_, span)) = self.positional.get(self.used) {
+            let message = format!("unexpected value for `{}`", self.keyword);
+            return Err(Diagnostic::error("E0002", *span, message).with_fix("remove it", *span, ""));
+        }
+        if let Some(named) = self.named.first() {
+            let message = format!("unknown parameter `{}` for `{}`", named.key, self.keyword);
+            let diagnostic = Diagnostic::error("E0004", named.span, message);
+            return Err(suggest(diagnostic, &named.key, named.key_span, &self.asked));
+        }
+        Ok(self.span)
+    }
+}
+
+fn out_of_range(name: &str, range: &RangeInclusive<i128>) -> String {
+    if *range.end() >= i64::MAX as i128 {
+        format!("`{}` must be at least {}", name, range.start())
+    } else {
+        format!("`{}` must be between {} and {}", name, range.start(), range.end())
+    }
+}
+
+fn int<T: TryFrom<i128>>(
+    name: &str,
+    (value, span): (Value, Span),
+    range: RangeInclusive<i128>,
+) -> Result<T, Diagnostic> {
+    match value {
+        Value::Int(n) if range.contains(&n) => Ok(T::try_from(n).ok().expect("range fits the field")),
+        Value::Int(_) => Err(Diagnostic::error("E0007", span, out_of_range(name, &range))),
+        _ => Err(Diagnostic::error("E0007", span, format!("`{}` must be an integer", name))),
+    }
+}
+
+fn text(name: &str, (value, span): (Value, Span)) -> Result<String, Diagnostic> {
+    match value {
+        Value::Text(s) => Ok(s),
+        _ => Err(Diagnostic::error("E0007", span, format!("`{}` must be a name", name))),
+    }
+}
+
+fn ratio(name: &str, (value, span): (Value, Span)) -> Result<(i64, i64), Diagnostic> {
+    let (num, den) = match value {
+        Value::Int(n) => (n, 1),
+        Value::Ratio(num, den) => (num, den),
+        Value::Text(_) => {
+            return Err(Diagnostic::error("E0007", span, format!("`{}` must be a number or a ratio", name)));
+        }
+    };
+    let num = int(name, (Value::Int(num), span), AMOUNT)?;
+    let den = int(name, (Value::Int(den), span), POSITIVE)
+        .map_err(|_| Diagnostic::error("E0007", span, format!("`{}` is too precise", name)))?;
+    Ok((num, den))
+}
+
+fn curve(args: &mut Args) -> Result<CurveKind, Diagnostic> {
+    let (kind, span) = args.positional(0, "a curve kind")?;
+    let kind = text("curve kind", (kind, span))?;
+    let curve = match kind.to_ascii_lowercase().as_str() {
+        "linear" => {
+            let base_price = args.int("base_price", AMOUNT)?;
+            let Some(slope) = args.named("slope") else {
+                return Err(Diagnostic::error("E0005", args.span, "`curve` needs `slope=`"));
+            };
+            let (slope_num, slope_den) = ratio("slope", slope)?;
+            CurveKind::Linear(Linear { base_price, slope_num, slope_den })
+        }
+        "constant_product" => CurveKind::ConstantProduct(ConstantProduct {
+            virtual_reserve: args.int("virtual_reserve", POSITIVE)?,
+            virtual_supply: args.int("virtual_supply", 2..=i64::MAX as i128)?,
+        }),
+        "exponential" => CurveKind::Exponential(Exponential {
+            base_price: args.int("base_price", POSITIVE)?,
+            scale: args.int("scale", POSITIVE)?,
+        }),
+        "sigmoid" => CurveKind::Sigmoid(Sigmoid {
+            max_price: args.int("max_price", POSITIVE)?,
+            midpoint: args.int("midpoint", AMOUNT)?,
+            width: args.int("width", POSITIVE)?,
+        }),
+        "lbp" => CurveKind::Lbp(Lbp {
+            virtual_reserve: args.int("virtual_reserve", POSITIVE)?,
+            token_balance: args.int("token_balance", POSITIVE)?,
+            start_weight_bps: args.int("start_weight", 1..=9_999)?,
+            end_weight_bps: args.int("end_weight", 1..=9_999)?,
+            duration: args.int_or("duration", 0, HEIGHT)?,
+            elapsed: 0,
+        }),
+        _ => {
+            let message = format!("unknown curve `{}`; expected {}", kind, CURVES.join(", "));
+            return Err(suggest(Diagnostic::error("E0004", span, message), &kind, span, &CURVES));
+        }
+    };
+    Ok(curve)
+}
+
+fn item(mut args: Args) -> Result<Item, Diagnostic> {
+    let kind = match args.keyword.as_str() {
+        "curve" => ItemKind::Curve(curve(&mut args)?),
+        "supply" => ItemKind::Supply(int("supply", args.positional(0, "an amount")?, POSITIVE)?),
+        "migrate_at" => match (args.named("reserve"), args.named("market_cap")) {
+            (Some(reserve), None) => {
+                ItemKind::MigrateAt(MigrationThreshold::Reserve(int("reserve", reserve, POSITIVE)?))
+            }
+            (None, Some(cap)) => {
+                ItemKind::MigrateAt(MigrationThreshold::MarketCap(int("market_cap", cap, 1..=i128::MAX)?))
+            }
+            (None, None) => {
+                return Err(Diagnostic::error("E0005", args.span, "`migrate_at` needs `reserve=` or `market_cap=`"));
+            }
+            (Some(_), Some((_, span))) => {
+                let message = "`migrate_at` takes only one of `reserve=` and `market_cap=`";
+                return Err(Diagnostic::error("E0008", span, message));
+            }
+        },
+        "reserve_ratio" => ItemKind::ReserveRatio(int("reserve_ratio", args.positional(0, "a ratio in bps")?, BPS)?),
+        "fees" => {
+            let fees = FeeConfig {
+                fee_bps: int("fees", args.positional(0, "a fee in bps")?, BPS)?,
+                protocol_share_bps: args.int_or("protocol", 0, BPS)?,
+                creator_share_bps: args.int_or("creator", 0, BPS)?,
+                lp_share_bps: args.int_or("lp", 0, BPS)?,
+                treasury: args.text_or_empty("treasury")?,
+                creator: args.text_or_empty("recipient")?,
+            };
+            if !fees.shares_add_up() {
+                return Err(Diagnostic::error("E0008", args.span, "fee shares must add up to 10000 bps"));
+            }
+            if fees.needs_treasury() && fees.treasury.is_empty() {
+                return Err(Diagnostic::error("E0005", args.span, "`fees` with a protocol share needs `treasury=`"));
+            }
+            if fees.needs_creator() && fees.creator.is_empty() {
+                return Err(Diagnostic::error("E0005", args.span, "`fees` paying the creator needs `recipient=`"));
+            }
+            ItemKind::Fees(fees)
+        }
+        "vesting" => {
+            let beneficiary = text("beneficiary", args.positional(0, "a beneficiary")?)?;
+            let total = int("vesting", args.positional(1, "an amount")?, POSITIVE)?;
+            let start = args.int_or("start", 0, HEIGHT)?;
+            let cliff = args.int_or("cliff", 0, HEIGHT)?;
+            let duration = args.int("duration", HEIGHT)?;
+            if cliff > duration {
+                return Err(Diagnostic::error("E0008", args.span, "vesting `cliff` is longer than its `duration`"));
+            }
+            ItemKind::Vesting { beneficiary, schedule: VestingSchedule::new(total, start, cliff, duration) }
+        }
+        "window" => ItemKind::Window(LaunchRules {
+            blocks: int("window", args.positional(0, "a number of blocks")?, HEIGHT)?,
+            max_buy: args.int_or("max_buy", 0, AMOUNT)?,
+            max_sell: args.int_or("max_sell", 0, AMOUNT)?,
+            max_holding_bps: args.int_or("max_holding", 0, BPS)?,
+        }),
+        other => {
+            let diagnostic = Diagnostic::error("E0004", args.keyword_span, format!("unknown setting `{}`", other));
+            return Err(suggest(diagnostic, other, args.keyword_span, &SETTINGS));
+        }
+    };
+    Ok(Item { kind, span: args.finish()? })
+}
+
+fn same_setting(a: &ItemKind, b: &ItemKind) -> bool {
+    match (a, b) {
+        (ItemKind::Vesting { beneficiary: x, .. }, ItemKind::Vesting { beneficiary: y, .. }) => x == y,
+        _ => mem::discriminant(a) == mem::discriminant(b),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_kind(&self, ahead: usize) -> &TokenKind {
+        &self.tokens[(self.pos + ahead).min(self.tokens.len() - 1)].kind
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: &str) -> Diagnostic {
+        let token = self.peek();
+        match &token.kind {
+            TokenKind::Invalid(reason) => Diagnostic::error("E0001", token.span, reason.clone()),
+            found => Diagnostic::error("E0002", token.span, format!("expected {}, found {}", expected, found)),
+        }
+    }
+
+    // Skips the rest of a broken statement: up to the end of the line, or to the
+    // closing brace inside a block. Unreadable text on the way is still reported.
+    fn recover(&mut self, diagnostic: Diagnostic, in_block: bool) {
+        let reported = diagnostic.span;
+        self.diagnostics.push(diagnostic);
+        loop {
+            let token = self.peek();
+            match &token.kind {
+                TokenKind::Newline | TokenKind::Eof => break,
+                TokenKind::RBrace if in_block => break,
+                TokenKind::Invalid(reason) if token.span != reported => {
+                    self.diagnostics.push(Diagnostic::error("E0001", token.span, reason.clone()));
+                }
+                _ => {}
+            }
+            self.bump();
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek().kind == TokenKind::Newline {
+            self.bump();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), Diagnostic> {
+        match self.peek().kind {
+            TokenKind::Newline => {
+                self.bump();
+                Ok(())
+            }
+            TokenKind::Eof => Ok(()),
+            _ => Err(self.unexpected("end of line")),
+        }
+    }
+
+    fn script(&mut self) -> Script {
+        let mut script = Script::default();
+        let mut declared = false;
+        loop {
+            self.skip_newlines();
+            let token = self.peek().clone();
+            match &token.kind {
+                TokenKind::Eof => return script,
+                TokenKind::Ident(word) if word.eq_ignore_ascii_case("launch") => {
+                    if declared {
+                        let diagnostic = Diagnostic::error("E0006", token.span, "a script declares at most one launch");
+                        self.diagnostics.push(diagnostic);
+                    }
+                    let launch = self.launch();
+                    if !declared {
+                        script.launch = launch;
+                    }
+                    declared = true;
+                }
+                TokenKind::Ident(word) if opcode(word).is_some() => match self.trade() {
+                    Ok(trade) => script.trades.push(trade),
+                    Err(diagnostic) => self.recover(diagnostic, false),
+                },
+                TokenKind::Ident(word) => {
+                    let diagnostic = Diagnostic::error("E0003", token.span, format!("unknown statement `{}`", word));
+                    let candidates: Vec<&str> = Opcode::ALL.iter().map(|op| op.mnemonic()).chain(["launch"]).collect();
+                    self.recover(suggest(diagnostic, word, token.span, &candidates), false);
+                }
+                _ => {
+                    let diagnostic = self.unexpected("`launch` or a trade statement");
+                    self.recover(diagnostic, false);
+                }
+            }
+        }
+    }
+
+    fn name(&mut self, what: &str) -> Result<(String, Span), Diagnostic> {
+        match &self.peek().kind {
+            TokenKind::Ident(name) | TokenKind::Str(name) => {
+                let name = name.clone();
+                Ok((name, self.bump().span))
+            }
+            _ => Err(self.unexpected(what)),
+        }
+    }
+
+    fn int<T: TryFrom<i128>>(&mut self, what: &str, range: RangeInclusive<i128>) -> Result<(T, Span), Diagnostic> {
+        match self.peek().kind {
+            TokenKind::Int(n) => {
+                let span = self.bump().span;
+                Ok((int(what, (Value::Int(n), span), range)?, span))
+            }
+            _ => Err(self.unexpected(&format!("`{}`", what))),
+        }
+    }
+
+    // A header without a name or `{` is reported and the block is parsed anyway,
+    // since the settings below it are usually fine.
+    fn launch(&mut self) -> Option<Launch> {
+        let keyword = self.bump().span;
+        let (name, head) = match self.name("a launch name") {
+            Ok((name, span)) => (name, keyword.to(span)),
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                (String::new(), keyword)
+            }
+        };
+        self.skip_newlines();
+        let open = match self.peek().kind {
+            TokenKind::LBrace => self.bump().span,
+            _ => {
+                let diagnostic = self.unexpected("`{`");
+                self.recover(diagnostic, false);
+                head
+            }
+        };
+        let mut items: Vec<Item> = Vec::new();
+        let mut curve_failed = false;
+        let mut end = head;
+        loop {
+            self.skip_newlines();
+            match self.peek().kind {
+                TokenKind::RBrace => {
+                    end = self.bump().span;
+                    if let Err(diagnostic) = self.end_of_line() {
+                        self.recover(diagnostic, false);
+                    }
+                    break;
+                }
+                TokenKind::Eof => {
+                    let eof = self.peek().span;
+                    let diagnostic = Diagnostic::error("E0009", open, "unclosed `{`").with_fix("close it", eof, "}");
+                    self.diagnostics.push(diagnostic);
+                    break;
+                }
+                _ => {}
+            }
+            let is_curve = matches!(&self.peek().kind, TokenKind::Ident(word) if word.eq_ignore_ascii_case("curve"));
+            let parsed =
+                self.setting().and_then(|args| {
+                    let keyword = args.keyword.clone();
+                    let item = item(args)?;
+                    match items.iter().any(|other| same_setting(&other.kind, &item.kind)) {
+                        true => Err(Diagnostic::error("E0006", item.span, format!("`{}` is set twice", keyword))
+                            .with_fix("remove it", item.span, "")),
+                        false => Ok(item),
+                    }
+                });
+            match parsed {
+                Ok(item) => {
+                    end = item.span;
+                    items.push(item);
+                    if self.peek().kind != TokenKind::RBrace
+                        && let Err(diagnostic) = self.end_of_line()
+                    {
+                        self.recover(diagnostic, true);
+                    }
+                }
+                Err(diagnostic) => {
+                    curve_failed |= is_curve;
+                    self.recover(diagnostic, true);
+                }
+            }
+        }
+
+        let Some(curve) = items.iter().find_map(|item| match item.kind {
+            ItemKind::Curve(curve) => Some(curve),
+            _ => None,
+        }) else {
+            if !curve_failed {
+                self.diagnostics.push(Diagnostic::error("E0005", head, format!("launch `{}` needs a `curve`", name)));
+            }
+            return None;
+        };
+        for item in &items {
+            if let ItemKind::Supply(supply) = item.kind
+                && supply > curve.max_supply()
+            {
+                let cap = curve.max_supply().to_string();
+                let message = format!("the curve sells at most {} tokens, so `supply` is capped there", cap);
+                let diagnostic = Diagnostic::warning("W0002", item.span, message);
+                self.diagnostics.push(diagnostic.with_fix(
+                    format!("use `supply {}`", cap),
+                    item.span,
+                    format!("supply {}", cap),
+                ));
+            }
+        }
+        Some(Launch { name, span: head.to(end), items })
+    }
+
+    fn setting(&mut self) -> Result<Args, Diagnostic> {
+        let TokenKind::Ident(keyword) = self.peek().kind.clone() else {
+            return Err(self.unexpected("a launch setting"));
+        };
+        let span = self.bump().span;
+        let mut args = Args {
+            keyword: keyword.to_ascii_lowercase(),
+            keyword_span: span,
+            span,
+            positional: Vec::new(),
+            named: Vec::new(),
+            used: 0,
+            asked: Vec::new(),
+        };
+        while !matches!(self.peek().kind, TokenKind::Newline | TokenKind::RBrace | TokenKind::Eof) {
+            if let (TokenKind::Ident(key), TokenKind::Eq) = (self.peek_kind(0), self.peek_kind(1)) {
+                let key = key.to_ascii_lowercase();
+                let key_span = self.bump().span;
+                self.bump();
+                let (value, value_span) = self.value()?;
+                let span = key_span.to(value_span);
+                if args.named.iter().any(|named| named.key == key) {
+                    let diagnostic = Diagnostic::error("E0006", key_span, format!("`{}` is given twice", key));
+                    return Err(diagnostic.with_fix("remove it", span, ""));
+                }
+                args.named.push(Named { key, key_span, value, span });
+                args.span = args.span.to(value_span);
+            } else {
+                let (value, value_span) = self.value()?;
+                if !args.named.is_empty() {
+                    let message = format!("`{}` takes its values before named parameters", args.keyword);
+                    return Err(Diagnostic::error("E0002", value_span, message));
+                }
+                args.positional.push((value, value_span));
+                args.span = args.span.to(value_span);
+            }
+        }
+        Ok(args)
+    }
+
+    fn value(&mut self) -> Result<(Value, Span), Diagnostic> {
+        let token = self.peek().clone();
+        let value = match token.kind {
+            TokenKind::Int(num) if *self.peek_kind(1) == TokenKind::Slash => {
+                self.bump();
+                self.bump();
+                let (den, span) = self.int::<i128>("denominator", 1..=i128::MAX)?;
+                return Ok((Value::Ratio(num, den), token.span.to(span)));
+            }
+            TokenKind::Int(n) => Value::Int(n),
+            TokenKind::Ident(s) | TokenKind::Str(s) => Value::Text(s),
+            _ => return Err(self.unexpected("a value")),
+        };
+        self.bump();
+        Ok((value, token.span))
+    }
+
+    fn trade(&mut self) -> Result<Trade, Diagnostic> {
+        let head = self.bump();
+        let TokenKind::Ident(word) = head.kind else { unreachable!("trade starts at an opcode") };
+        let opcode = word.to_ascii_uppercase();
+        let (operand, operand_span) = self.int("amount", AMOUNT)?;
+        let mut span = head.span.to(operand_span);
+        let clauses: &[&str] = match opcode.as_str() {
+            "BUY" => &["MAX", "UNTIL"],
+            "SELL" => &["MIN", "UNTIL"],
+            _ => &["UNTIL"],
+        };
+        let (mut limit, mut expiry) = (None, None);
+        while let TokenKind::Ident(word) = &self.peek().kind {
+            let word = word.clone();
+            let clause = word.to_ascii_uppercase();
+            let keyword = self.bump().span;
+            let slot = match (clause.as_str(), opcode.as_str()) {
+                ("MAX", "BUY") | ("MIN", "SELL") => &mut limit,
+                ("UNTIL", _) => &mut expiry,
+                ("MAX", "SELL") => {
+                    let diagnostic = Diagnostic::error("E0008", keyword, "`MAX` bounds a BUY; a SELL takes `MIN`");
+                    return Err(diagnostic.with_fix("use `MIN`", keyword, "MIN"));
+                }
+                ("MIN", "BUY") => {
+                    let diagnostic = Diagnostic::error("E0008", keyword, "`MIN` bounds a SELL; a BUY takes `MAX`");
+                    return Err(diagnostic.with_fix("use `MAX`", keyword, "MAX"));
+                }
+                ("MAX" | "MIN", _) => {
+                    return Err(Diagnostic::error("E0008", keyword, format!("{} takes no `{}`", opcode, clause)));
+                }
+                _ => {
+                    let diagnostic = Diagnostic::error("E0004", keyword, format!("unknown clause `{}`", word));
+                    return Err(suggest(diagnostic, &word, keyword, clauses));
+                }
+            };
+            let (value, value_span) = self.int::<i128>(&clause, i128::MIN..=i128::MAX)?;
+            if slot.is_some() {
+                let diagnostic = Diagnostic::error("E0006", keyword, format!("`{}` is given twice", clause));
+                return Err(diagnostic.with_fix("remove it", keyword.to(value_span), ""));
+            }
+            if value == 0 {
+                let diagnostic =
+                    Diagnostic::warning("W0001", keyword.to(value_span), format!("`{} 0` has no effect", clause));
+                self.diagnostics.push(diagnostic.with_fix("remove it", keyword.to(value_span), ""));
+            }
+            *slot = Some((value, value_span));
+            span = span.to(value_span);
+        }
+        let limit =
+            limit.map_or(Ok(0), |(n, s)| int("limit", (Value::Int(n), s), i64::MIN as i128..=i64::MAX as i128))?;
+        let expiry = expiry.map_or(Ok(0), |(n, s)| int("UNTIL", (Value::Int(n), s), HEIGHT))?;
+        self.end_of_line()?;
+        Ok(Trade { command: Command { opcode, operand, limit, expiry }, span })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAUNCH: &str = r#"
+# Raise on a linear curve that auto-migrates at 300 SOL of reserve.
+launch "dog" {
+    curve linear base_price=1_000 slope=1/1_000
+    supply 800_000_000
+    migrate_at reserve=300_000_000_000
+    reserve_ratio 2_000
+    fees 100 protocol=5_000 creator=3_000 lp=2_000 treasury=treasury recipient="alice"
+    vesting alice 50_000_000 cliff=2_592_000 duration=31_536_000
+    window 10 max_buy=1_000_000 max_holding=200
+}
+BUY 5_000 MAX 9_000_000
+"#;
+
+    fn first_error(source: &str) -> (&'static str, String, &str) {
+        let diagnostics = parse_script(source).unwrap_err();
+        let d = diagnostics.into_iter().find(Diagnostic::is_error).unwrap();
+        (d.code, d.message, &source[d.span.start..d.span.end])
+    }
+
+    #[test]
+    fn parses_a_launch_declaration() {
+        let script = parse_script(LAUNCH).unwrap();
+        let launch = script.launch.unwrap();
+        assert_eq!(launch.name, "dog");
+        assert!(LAUNCH[launch.span.start..launch.span.end].starts_with("launch \"dog\" {"));
+        assert_eq!(launch.curve(), CurveKind::Linear(Linear { base_price: 1_000, slope_num: 1, slope_den: 1_000 }));
+        let kinds: Vec<_> = launch.items.iter().map(|item| item.kind.clone()).collect();
+        assert_eq!(
+            kinds[1..],
+            [
+                ItemKind::Supply(800_000_000),
+                ItemKind::MigrateAt(MigrationThreshold::Reserve(300_000_000_000)),
+                ItemKind::ReserveRatio(2_000),
+                ItemKind::Fees(FeeConfig {
+                    fee_bps: 100,
+                    protocol_share_bps: 5_000,
+                    creator_share_bps: 3_000,
+                    lp_share_bps: 2_000,
+                    treasury: "treasury".into(),
+                    creator: "alice".into(),
+                }),
+                ItemKind::Vesting {
+                    beneficiary: "alice".into(),
+                    schedule: VestingSchedule::new(50_000_000, 0, 2_592_000, 31_536_000),
+                },
+                ItemKind::Window(LaunchRules { blocks: 10, max_buy: 1_000_000, max_sell: 0, max_holding_bps: 200 }),
+            ]
+        );
+        let fees = &launch.items[4];
+        assert!(LAUNCH[fees.span.start..fees.span.end].ends_with("recipient=\"alice\""));
+        assert_eq!(script.trades.len(), 1);
+        assert_eq!(
+            script.trades[0].command,
+            Command { opcode: "BUY".into(), operand: 5_000, limit: 9_000_000, expiry: 0 }
+        );
+    }
+
+    #[test]
+    fn parses_every_curve_kind() {
+        let cases = [
+            ("linear base_price=0 slope=3", CurveKind::Linear(Linear { base_price: 0, slope_num: 3, slope_den: 1 })),
+            (
+                "constant_product virtual_reserve=30 virtual_supply=1_000",
+                CurveKind::ConstantProduct(ConstantProduct { virtual_reserve: 30, virtual_supply: 1_000 }),
+            ),
+            ("EXPONENTIAL base_price=1 scale=100", CurveKind::Exponential(Exponential { base_price: 1, scale: 100 })),
+            (
+                "sigmoid max_price=1_000 midpoint=5_000 width=1_000",
+                CurveKind::Sigmoid(Sigmoid { max_price: 1_000, midpoint: 5_000, width: 1_000 }),
+            ),
+            (
+                "lbp virtual_reserve=10 token_balance=1_000 start_weight=9_000 end_weight=5_000 duration=50",
+                CurveKind::Lbp(Lbp {
+                    virtual_reserve: 10,
+                    token_balance: 1_000,
+                    start_weight_bps: 9_000,
+                    end_weight_bps: 5_000,
+                    duration: 50,
+                    elapsed: 0,
+                }),
+            ),
+        ];
+        for (curve, expected) in cases {
+            let script = parse_script(&format!("launch x {{ curve {} }}", curve)).unwrap();
+            assert_eq!(script.launch.unwrap().curve(), expected, "{}", curve);
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_text() {
+        let curve = "curve linear base_price=1 slope=1";
+        let cases = [
+            (
+                "launch x {\n  curve cubic a=1\n}",
+                "E0004",
+                "unknown curve `cubic`; expected linear, constant_product, exponential, sigmoid, lbp",
+                "cubic",
+            ),
+            ("launch x {\n  curve linear base_price=1 slope=1/0\n}", "E0007", "`denominator` must be at least 1", "0"),
+            ("launch x {\n  curve linear base_price=1 slope=1 slope=2\n}", "E0006", "`slope` is given twice", "slope"),
+            (
+                "launch x {\n  curve linear base_price=1 slope=1 colour=2\n}",
+                "E0004",
+                "unknown parameter `colour` for `curve`",
+                "colour=2",
+            ),
+            (
+                "launch x {\n  curve sigmoid max_price=1 midpoint=1\n}",
+                "E0005",
+                "`curve` needs `width=`",
+                "curve sigmoid max_price=1 midpoint=1",
+            ),
+            (&format!("launch x {{\n  {}\n  supply 0\n}}", curve), "E0007", "`supply` must be at least 1", "0"),
+            (
+                &format!("launch x {{\n  {}\n  fees 100 protocol=1\n}}", curve),
+                "E0008",
+                "fee shares must add up to 10000 bps",
+                "fees 100 protocol=1",
+            ),
+            (
+                &format!("launch x {{\n  {}\n  fees 100 protocol=10_000\n}}", curve),
+                "E0005",
+                "`fees` with a protocol share needs `treasury=`",
+                "fees 100 protocol=10_000",
+            ),
+            (
+                &format!("launch x {{\n  {}\n  window 5 max_holding=10_001\n}}", curve),
+                "E0007",
+                "`max_holding` must be between 0 and 10000",
+                "max_holding=10_001",
+            ),
+            (
+                &format!("launch x {{\n  {}\n  reserve_ratio 1 2\n}}", curve),
+                "E0002",
+                "unexpected value for `reserve_ratio`",
+                "2",
+            ),
+            (
+                &format!("launch x {{\n  {}\n  curve exponential base_price=1 scale=1\n}}", curve),
+                "E0006",
+                "`curve` is set twice",
+                "curve exponential base_price=1 scale=1",
+            ),
+            (
+                &format!("launch x {{\n  {}\n  migrate_at reserve=1 market_cap=2\n}}", curve),
+                "E0008",
+                "`migrate_at` takes only one of `reserve=` and `market_cap=`",
+                "market_cap=2",
+            ),
+            ("launch x {\n  supply 5\n}", "E0005", "launch `x` needs a `curve`", "launch x"),
+            (&format!("launch x {{\n  {}\n", curve), "E0009", "unclosed `{`", "{"),
+            (&format!("launch x {{\n  {} @\n}}", curve), "E0001", "unexpected character `@`", "@"),
+            (
+                &format!("BUY 5\nlaunch x {{ {} }}\nlaunch y {{ {} }}", curve, curve),
+                "E0006",
+                "a script declares at most one launch",
+                "launch",
+            ),
+            ("BUY 5\nFLY 2", "E0003", "unknown statement `FLY`", "FLY"),
+            ("BUY 5\n5 BUY", "E0002", "expected `launch` or a trade statement, found `5`", "5"),
+            ("SELL 5 MAX 900", "E0008", "`MAX` bounds a BUY; a SELL takes `MIN`", "MAX"),
+            ("BUY 5 UNTIL 2 UNTIL 3", "E0006", "`UNTIL` is given twice", "UNTIL"),
+            ("SELL -5", "E0007", "`amount` must be at least 0", "-5"),
+        ];
+        for (source, code, message, text) in cases {
+            assert_eq!(first_error(source), (code, message.to_string(), text), "{}", source);
+        }
+    }
+
+    #[test]
+    fn reports_every_error_in_one_pass() {
+        let source = "BUYY 5\nSELL 2 MAX 1\nlaunch dog {\n  curve linear base_price=1 slop=1 slope=1\n  \
+                      sypply 10\n  fees 100 protocol=10_000 @ #\n  curve sigmoid max_price=1 midpoint=1 width=1\n}\n\
+                      BUY 5 UNTIL 0 $\n";
+        let (script, diagnostics) = check_script(source);
+        let found: Vec<_> = diagnostics.iter().map(|d| (d.code, &source[d.span.start..d.span.end])).collect();
+        assert_eq!(
+            found,
+            [
+                ("E0003", "BUYY"),
+                ("E0008", "MAX"),
+                ("E0004", "slop=1"),
+                ("E0004", "sypply"),
+                ("E0001", "@"),
+                ("W0001", "UNTIL 0"),
+                ("E0001", "$"),
+            ]
+        );
+        // Broken statements are left out; the rest of the script survives.
+        let launch = script.launch.unwrap();
+        assert_eq!(launch.curve(), CurveKind::Sigmoid(Sigmoid { max_price: 1, midpoint: 1, width: 1 }));
+        assert_eq!(launch.items.len(), 1);
+        assert!(script.trades.is_empty());
+
+        let fixed = crate::diagnostic::apply_fixes(source, &diagnostics);
+        assert!(fixed.starts_with(
+            "BUY 5\nSELL 2 MIN 1\nlaunch dog {\n  curve linear base_price=1 slope=1 slope=1\n  supply 10\n"
+        ));
+        let (_, remaining) = check_script(&fixed);
+        let codes: Vec<_> = remaining.iter().map(|d| d.code).collect();
+        assert_eq!(codes, ["E0006", "E0001", "E0001"]);
+    }
+
+    #[test]
+    fn warnings_do_not_fail_a_parse() {
+        let source =
+            "launch x {\n  curve constant_product virtual_reserve=1 virtual_supply=100\n  supply 500\n}\nBUY 1 MAX 0\n";
+        let (script, diagnostics) = check_script(source);
+        let codes: Vec<_> =
+            diagnostics.iter().map(|d| (d.code, d.fix.as_ref().unwrap().replacement.as_str())).collect();
+        assert_eq!(codes, [("W0002", "supply 99"), ("W0001", "")]);
+        assert_eq!(parse_script(source).unwrap(), script);
+    }
+}
@@ -0,0 +1,501 @@
+use crate::Instruction;
+use curvevm::{Curve, CurveKind, CurveVM, MigrationThreshold, Opcode, PRICE_SCALE, bytecode, math};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// The proof engine interprets a program over intervals of curve state instead of
+// one concrete state, so a certificate holds for every state the program may
+// start from. It relies on two facts about the curve maths: `reserve_at` never
+// decreases with supply, and so the cost of a fixed amount never decreases
+// either (up to one lamport of rounding).
+//
+// The reserve is tracked as `reserve_at(supply) - deficit`. Trades leave the
+// deficit alone, so a sell is safe exactly when `reserve_at(supply - amount)`
+// covers it; only a weight-shifting curve opens one, since vesting is released
+// after migration. Trades that may run after a migration route to the AMM pool,
+// which the certificate does not model, so they are rejected.
+//
+// A curve whose weights still shift prices differently at every height the
+// program may run at, so it is only certified once its schedule has settled;
+// sync the VM to the execution clock first.
+pub const CERTIFICATE_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interval {
+    pub lo: i128,
+    pub hi: i128,
+}
+
+impl Interval {
+    pub fn new(lo: i128, hi: i128) -> Self {
+        Self { lo, hi }
+    }
+
+    pub fn exact(value: i128) -> Self {
+        Self { lo: value, hi: value }
+    }
+
+    pub fn contains(&self, other: Interval) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+
+    fn shift(self, by: i128) -> Self {
+        Self { lo: self.lo.saturating_add(by), hi: self.hi.saturating_add(by) }
+    }
+
+    fn add(self, other: Interval) -> Self {
+        Self { lo: self.lo.saturating_add(other.lo), hi: self.hi.saturating_add(other.hi) }
+    }
+
+    fn hull(self, other: Interval) -> Self {
+        Self { lo: self.lo.min(other.lo), hi: self.hi.max(other.hi) }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Curve,
+    MaybeMigrated,
+    Migrated,
+}
+
+// What is known about the curve and the sender's account between two instructions.
+// `balance` is the sender's token balance and `locked` the vesting not yet claimed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub phase: Phase,
+    pub supply: Interval,
+    pub reserve: Interval,
+    pub deficit: Interval,
+    pub locked: Interval,
+    pub liquidity: Interval,
+    pub balance: Interval,
+}
+
+impl Bounds {
+    // Every state `vm` can be in before it migrates, held by any sender: other
+    // traders may move the supply anywhere short of the threshold and the vested
+    // allocation, while vesting stays locked until migration. Pledged liquidity is
+    // taken as it stands, so the certificate assumes nobody else pledges first;
+    // `proofcheck::check_for` rejects it once the pledges have moved.
+    pub fn pre_migration(vm: &CurveVM) -> Self {
+        let curve = vm.curve;
+        let unclaimed = vm.locked_allocation() as i128;
+        let now = reserve_at(&curve, vm.supply as i128).unwrap_or(i128::MAX).saturating_sub(vm.reserve as i128);
+        let deficit = Interval::new(now.min(0), now.max(0));
+        let ceiling = match vm.migration_threshold {
+            Some(MigrationThreshold::Reserve(target)) => (target as i128 - 1).saturating_add(deficit.hi),
+            _ => (i64::MAX as i128).saturating_add(deficit.hi),
+        };
+        let top = last_supply(vm.max_supply - unclaimed as i64, |s| {
+            reserve_at(&curve, s as i128).is_some_and(|r| r <= ceiling)
+                && match vm.migration_threshold {
+                    Some(MigrationThreshold::MarketCap(target)) => market_cap(&curve, s as i128) < target,
+                    _ => true,
+                }
+        }) as i128;
+        let reserve = reserve_at(&curve, top).map_or(i128::MAX, |r| r.saturating_sub(deficit.lo));
+        Bounds {
+            phase: if vm.migrated_to_amm { Phase::Migrated } else { Phase::Curve },
+            supply: Interval::new(0, top),
+            reserve: Interval::new(0, reserve.min(i64::MAX as i128)),
+            deficit,
+            locked: Interval::exact(unclaimed),
+            liquidity: Interval::exact(vm.liquidity as i128),
+            balance: Interval::new(0, top),
+        }
+    }
+
+    // Exactly the state `vm` is in now, as seen by `sender`.
+    pub fn at(vm: &CurveVM, sender: &str) -> Self {
+        let deficit = reserve_at(&vm.curve, vm.supply as i128).unwrap_or(i128::MAX) - vm.reserve as i128;
+        Bounds {
+            phase: if vm.migrated_to_amm { Phase::Migrated } else { Phase::Curve },
+            supply: Interval::exact(vm.supply as i128),
+            reserve: Interval::exact(vm.reserve as i128),
+            deficit: Interval::exact(if vm.migrated_to_amm { 0 } else { deficit }),
+            locked: Interval::exact(vm.locked_allocation() as i128),
+            liquidity: Interval::exact(vm.liquidity as i128),
+            balance: Interval::exact(vm.balance_of(sender) as i128),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Step {
+    pub index: usize,
+    pub opcode: String,
+    pub operand: i64,
+    // SOL the instruction moves: a trade's cost or proceeds before fees, pledged
+    // liquidity, or what seeds the pool.
+    pub sol: Interval,
+    pub after: Bounds,
+}
+
+// The `proof.json` a program ships with: the curve it was proved against, the
+// bounds it starts from and the bounds established after every instruction.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Certificate {
+    pub version: u8,
+    pub code_hash: String,
+    pub curve: CurveKind,
+    pub max_supply: i64,
+    pub migration_threshold: Option<MigrationThreshold>,
+    pub initial: Bounds,
+    pub steps: Vec<Step>,
+}
+
+impl Certificate {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("certificates serialize")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    Overflow { step: usize, quantity: &'static str },
+    NegativeBalance { step: usize, requested: i64, held: i128 },
+    NegativeReserve { step: usize, requested: i128, available: i128 },
+    TradeAfterMigration { step: usize, opcode: Opcode },
+    AlwaysFails { step: usize, reason: &'static str },
+    OtherCurve { step: usize },
+    ShiftingWeights,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::Overflow { step, quantity } => write!(f, "Step {}: the {} can overflow", step, quantity),
+            ProofError::NegativeBalance { step, requested, held } => {
+                write!(f, "Step {}: sells {} tokens but the sender may hold only {}", step, requested, held)
+            }
+            ProofError::NegativeReserve { step, requested, available } => {
+                write!(f, "Step {}: may pay out {} from a reserve of {}", step, requested, available)
+            }
+            ProofError::TradeAfterMigration { step, opcode } => {
+                write!(f, "Step {}: {} may run after the curve migrates", step, opcode.mnemonic())
+            }
+            ProofError::AlwaysFails { step, reason } => write!(f, "Step {}: always fails: {}", step, reason),
+            ProofError::OtherCurve { step } => write!(f, "Step {}: targets a different curve", step),
+            ProofError::ShiftingWeights => write!(f, "the curve's weights still shift with the block height"),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+pub fn prove(vm: &CurveVM, initial: &Bounds, program: &[Instruction]) -> Result<Certificate, ProofError> {
+    if !vm.curve.is_settled() {
+        return Err(ProofError::ShiftingWeights);
+    }
+    let mut bounds = *initial;
+    let mut steps = Vec::with_capacity(program.len());
+    for (index, ins) in program.iter().enumerate() {
+        if ins.curve != program[0].curve {
+            return Err(ProofError::OtherCurve { step: index });
+        }
+        let (sol, after) = step(vm, &bounds, ins, index)?;
+        steps.push(Step { index, opcode: ins.opcode.mnemonic().to_string(), operand: ins.operand, sol, after });
+        bounds = after;
+    }
+    let code_hash = bytecode::code_hash(program).iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(Certificate {
+        version: CERTIFICATE_VERSION,
+        code_hash,
+        curve: vm.curve,
+        max_supply: vm.max_supply,
+        migration_threshold: vm.migration_threshold,
+        initial: *initial,
+        steps,
+    })
+}
+
+fn step(vm: &CurveVM, b: &Bounds, ins: &Instruction, index: usize) -> Result<(Interval, Bounds), ProofError> {
+    let fails = |reason| ProofError::AlwaysFails { step: index, reason };
+    let overflow = |quantity| ProofError::Overflow { step: index, quantity };
+    if ins.operand < 0 {
+        return Err(fails("the operand is negative"));
+    }
+    let amount = ins.operand as i128;
+    let curve = &vm.curve;
+    let trades = matches!(ins.opcode, Opcode::Buy | Opcode::Sell | Opcode::AddLiquidity | Opcode::MigrateToAmm);
+    if b.phase != Phase::Curve && (trades || ins.opcode == Opcode::RemoveLiquidity) {
+        return Err(ProofError::TradeAfterMigration { step: index, opcode: ins.opcode });
+    }
+    let mut after = *b;
+    let sol = match ins.opcode {
+        Opcode::Buy => {
+            let room = vm.max_supply as i128 - b.locked.lo;
+            if b.supply.lo + amount > room {
+                return Err(fails("the buy exceeds the unallocated supply"));
+            }
+            let from = Interval::new(b.supply.lo, b.supply.hi.min(room - amount));
+            after.supply = from.shift(amount);
+            after.balance = b.balance.shift(amount);
+            after.reserve = derive_reserve(curve, after.supply, b.deficit).ok_or(overflow("reserve"))?;
+            let cost = cost_bounds(curve, from, amount).ok_or(overflow("cost"))?;
+            if let Some(migrated) = crosses_threshold(vm, &after) {
+                if after.reserve.hi.saturating_add(b.liquidity.hi) > i64::MAX as i128 {
+                    return Err(overflow("pool reserve"));
+                }
+                let emptied = Interval::exact(0);
+                after.phase = if migrated { Phase::Migrated } else { Phase::MaybeMigrated };
+                after.reserve = if migrated { emptied } else { after.reserve.hull(emptied) };
+                after.liquidity = if migrated { emptied } else { after.liquidity.hull(emptied) };
+            }
+            cost
+        }
+        Opcode::Sell => {
+            if b.balance.lo < amount || b.supply.lo < amount {
+                return Err(ProofError::NegativeBalance { step: index, requested: ins.operand, held: b.balance.lo });
+            }
+            after.supply = b.supply.shift(-amount);
+            after.balance = b.balance.shift(-amount);
+            let proceeds = cost_bounds(curve, after.supply, amount).ok_or(overflow("proceeds"))?;
+            let floor = reserve_at(curve, after.supply.lo).ok_or(overflow("reserve"))?;
+            if floor < b.deficit.hi {
+                return Err(ProofError::NegativeReserve {
+                    step: index,
+                    requested: proceeds.hi,
+                    available: b.reserve.lo,
+                });
+            }
+            after.reserve = derive_reserve(curve, after.supply, b.deficit).ok_or(overflow("reserve"))?;
+            proceeds
+        }
+        Opcode::AddLiquidity => {
+            after.liquidity = b.liquidity.shift(amount);
+            if after.liquidity.hi > i64::MAX as i128 {
+                return Err(overflow("liquidity"));
+            }
+            Interval::exact(amount)
+        }
+        Opcode::MigrateToAmm => {
+            if vm.max_supply as i128 - b.supply.lo - b.locked.lo <= 0 {
+                return Err(fails("no unallocated supply is left to seed the pool"));
+            }
+            let seed = b.reserve.add(b.liquidity);
+            if seed.hi > i64::MAX as i128 {
+                return Err(overflow("pool reserve"));
+            }
+            after.phase = Phase::Migrated;
+            after.reserve = Interval::exact(0);
+            after.liquidity = Interval::exact(0);
+            seed
+        }
+        // Claimed tokens come out of the allocation, not the curve, so the deficit
+        // is left alone.
+        Opcode::Claim => {
+            if b.phase == Phase::Curve {
+                return Err(fails("vesting is only released after migration"));
+            }
+            if b.locked.hi < amount {
+                return Err(fails("less vesting than the claim is left"));
+            }
+            after.supply = b.supply.shift(amount);
+            after.balance = b.balance.shift(amount);
+            after.locked = Interval::new((b.locked.lo - amount).max(0), b.locked.hi - amount);
+            if after.supply.hi > i64::MAX as i128 {
+                return Err(overflow("supply"));
+            }
+            Interval::exact(0)
+        }
+        Opcode::RemoveLiquidity => return Err(fails("liquidity can only be removed after migration")),
+        // Fees are paid out of their own buckets and never touch the reserve.
+        Opcode::ClaimFees => Interval::exact(amount),
+    };
+    // Circulating and still-vesting tokens share `max_supply`, and nobody holds
+    // more than the whole supply.
+    after.supply.hi = after.supply.hi.min(vm.max_supply as i128 - after.locked.lo);
+    after.balance.hi = after.balance.hi.min(after.supply.hi);
+    Ok((sol, after))
+}
+
+// `Some(true)` when the threshold is reached in every state, `Some(false)` when
+// only in some.
+fn crosses_threshold(vm: &CurveVM, b: &Bounds) -> Option<bool> {
+    let (lo, hi) = match vm.migration_threshold? {
+        MigrationThreshold::Reserve(target) => (b.reserve.lo >= target as i128, b.reserve.hi >= target as i128),
+        MigrationThreshold::MarketCap(target) => {
+            (market_cap(&vm.curve, b.supply.lo) >= target, market_cap(&vm.curve, b.supply.hi) >= target)
+        }
+    };
+    hi.then_some(lo)
+}
+
+fn reserve_at(curve: &CurveKind, supply: i128) -> Option<i128> {
+    curve.reserve_at(i64::try_from(supply).ok()?)
+}
+
+fn market_cap(curve: &CurveKind, supply: i128) -> i128 {
+    let Ok(s) = i64::try_from(supply) else { return i128::MAX };
+    math::mul_div(curve.spot_price(s), supply, PRICE_SCALE).unwrap_or(i128::MAX)
+}
+
+// The reserve never goes negative, and a reserve beyond `i64` is an overflow.
+fn derive_reserve(curve: &CurveKind, supply: Interval, deficit: Interval) -> Option<Interval> {
+    let lo = reserve_at(curve, supply.lo)?.saturating_sub(deficit.hi).max(0);
+    let hi = reserve_at(curve, supply.hi)?.checked_sub(deficit.lo)?;
+    (hi <= i64::MAX as i128).then_some(Interval::new(lo, hi))
+}
+
+// Cost of `amount` tokens bought from any supply in `from`.
+fn cost_bounds(curve: &CurveKind, from: Interval, amount: i128) -> Option<Interval> {
+    let lo = reserve_at(curve, from.lo + amount)?.checked_sub(reserve_at(curve, from.lo)?)?;
+    let hi = reserve_at(curve, from.hi + amount)?.checked_sub(reserve_at(curve, from.hi)?)?;
+    Some(Interval::new(lo.min(hi), lo.max(hi)))
+}
+
+// The largest supply in `0..=max` for which `fits` holds, given that it holds for
+// every smaller one.
+fn last_supply(max: i64, fits: impl Fn(i64) -> bool) -> i64 {
+    let (mut lo, mut hi) = (0, max.max(0));
+    if !fits(lo) {
+        return 0;
+    }
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2 + (hi - lo) % 2;
+        if fits(mid) { lo = mid } else { hi = mid - 1 }
+    }
+    lo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_launch, compile_program, parse, parse_script};
+    use curvevm::{Clock, Lbp, VmError};
+
+    fn launch(source: &str) -> CurveVM {
+        compile_launch(&parse_script(source).unwrap().launch.unwrap()).unwrap()
+    }
+
+    fn program(source: &str) -> Vec<Instruction> {
+        compile_program(&parse(source).unwrap()).unwrap()
+    }
+
+    const DOG: &str = "launch dog {\n  curve linear base_price=10 slope=1/100\n  supply 1_000_000\n}\n";
+
+    #[test]
+    fn proves_a_round_trip_from_any_pre_migration_state() {
+        let vm = launch(DOG);
+        let initial = Bounds::pre_migration(&vm);
+        assert_eq!(initial.supply, Interval::new(0, 1_000_000));
+        assert_eq!(initial.reserve, Interval::new(0, reserve_at(&vm.curve, 1_000_000).unwrap()));
+
+        let code = program("BUY 500\nSELL 200\nADD_LIQUIDITY 1000\nMIGRATE_TO_AMM 0\nCLAIM_FEES 5");
+        let cert = prove(&vm, &initial, &code).unwrap();
+        assert_eq!(cert.steps.len(), 5);
+        let sell = &cert.steps[1];
+        assert_eq!(sell.after.balance, Interval::new(300, 999_800));
+        assert_eq!(
+            sell.sol,
+            Interval::new(vm.curve.cost(300, 500).unwrap(), vm.curve.cost(999_800, 1_000_000).unwrap())
+        );
+        assert_eq!(cert.steps[3].after.phase, Phase::Migrated);
+        assert_eq!(cert.code_hash.len(), 64);
+
+        let json = cert.to_json();
+        assert!(json.contains("\"linear\": {"), "{}", json);
+        assert_eq!(serde_json::from_str::<Certificate>(&json).unwrap(), cert);
+    }
+
+    #[test]
+    fn rejects_unsafe_programs() {
+        let vm = launch(DOG);
+        let initial = Bounds::pre_migration(&vm);
+        let rejected = |source: &str| prove(&vm, &initial, &program(source)).unwrap_err();
+        assert_eq!(rejected("BUY 5\nSELL 6"), ProofError::NegativeBalance { step: 1, requested: 6, held: 5 });
+        assert_eq!(
+            rejected("MIGRATE_TO_AMM 0\nBUY 1"),
+            ProofError::TradeAfterMigration { step: 1, opcode: Opcode::Buy }
+        );
+        assert_eq!(
+            rejected("ADD_LIQUIDITY 9223372036854775807\nADD_LIQUIDITY 1"),
+            ProofError::Overflow { step: 1, quantity: "liquidity" }
+        );
+        assert_eq!(
+            rejected("REMOVE_LIQUIDITY 1"),
+            ProofError::AlwaysFails { step: 0, reason: "liquidity can only be removed after migration" }
+        );
+
+        // An unbounded curve can be bought past what a reserve can hold.
+        let unbounded = CurveVM::new();
+        let err = prove(&unbounded, &Bounds::pre_migration(&unbounded), &program("BUY 1")).unwrap_err();
+        assert_eq!(err, ProofError::Overflow { step: 0, quantity: "reserve" });
+        assert!(prove(&unbounded, &Bounds::at(&unbounded, "alice"), &program("BUY 1")).is_ok());
+    }
+
+    #[test]
+    fn thresholds_and_vesting_are_accounted_for() {
+        let vm = launch(&DOG.replace("}", "  migrate_at reserve=50_000\n}"));
+        let initial = Bounds::pre_migration(&vm);
+        assert!(reserve_at(&vm.curve, initial.supply.hi).unwrap() < 50_000);
+        assert!(reserve_at(&vm.curve, initial.supply.hi + 1).unwrap() >= 50_000);
+        let cert = prove(&vm, &initial, &program("BUY 10")).unwrap();
+        assert_eq!(cert.steps[0].after.phase, Phase::MaybeMigrated);
+        let err = prove(&vm, &initial, &program("BUY 10\nSELL 10")).unwrap_err();
+        assert_eq!(err, ProofError::TradeAfterMigration { step: 1, opcode: Opcode::Sell });
+
+        // Vesting stays out of the curve until the pool opens.
+        let vested = launch(&DOG.replace("}", "  vesting alice 1_000 duration=10\n}"));
+        let initial = Bounds::pre_migration(&vested);
+        assert_eq!(initial.locked, Interval::exact(1_000));
+        let err = prove(&vested, &initial, &program("BUY 100\nCLAIM 1000\nSELL 1100")).unwrap_err();
+        assert_eq!(err, ProofError::AlwaysFails { step: 1, reason: "vesting is only released after migration" });
+        let mut run = vested.clone();
+        let vm_err = run.execute_at("alice", &program("BUY 100\nCLAIM 1000"), Clock { height: 0, timestamp: 10 });
+        assert_eq!(vm_err, Err(VmError::NotMigrated));
+        let cert =
+            prove(&vested, &Bounds::at(&vested, "alice"), &program("BUY 100\nMIGRATE_TO_AMM 0\nCLAIM 1000")).unwrap();
+        assert_eq!(cert.steps[2].after.balance, Interval::exact(1_100));
+        assert_eq!(cert.steps[2].after.locked, Interval::exact(0));
+    }
+
+    #[test]
+    fn shifting_weights_are_certified_only_once_settled() {
+        let lbp = Lbp {
+            virtual_reserve: 1_000_000,
+            token_balance: 10_000_000,
+            start_weight_bps: 5_000,
+            end_weight_bps: 9_000,
+            duration: 10,
+            elapsed: 0,
+        };
+        let mut vm = CurveVM::with_curve(CurveKind::Lbp(lbp), 5_000_000).unwrap();
+        vm.execute_at("alice", &program("BUY 1_000_000"), Clock::default()).unwrap();
+        let sell = program("SELL 1_000_000");
+        assert_eq!(prove(&vm, &Bounds::at(&vm, "alice"), &sell), Err(ProofError::ShiftingWeights));
+
+        // By height 10 the weight has risen and the sell pays out more than the reserve.
+        let later = Clock { height: 10, timestamp: 0 };
+        let mut settled = vm.clone();
+        settled.sync_clock(later);
+        let err = prove(&settled, &Bounds::at(&settled, "alice"), &sell).unwrap_err();
+        assert!(matches!(err, ProofError::NegativeReserve { step: 0, .. }), "{}", err);
+        assert!(matches!(vm.execute_at("alice", &sell, later), Err(VmError::NegativeReserve { .. })));
+        assert!(prove(&settled, &Bounds::at(&settled, "alice"), &program("SELL 1_000")).is_ok());
+    }
+
+    #[test]
+    fn supply_and_vesting_share_the_max_supply() {
+        let vm =
+            launch("launch x {\n  curve exponential base_price=1 scale=1000\n  vesting alice 10_000 duration=10\n}\n");
+        assert_eq!(vm.max_supply, 40_000);
+        let initial = Bounds::pre_migration(&vm);
+        assert_eq!((initial.supply.hi, initial.locked), (30_000, Interval::exact(10_000)));
+
+        // However the pool opens, the claim stays within the curve's domain.
+        let migrated = Bounds { phase: Phase::Migrated, locked: Interval::new(0, 10_000), ..initial };
+        let cert = prove(&vm, &migrated, &program("CLAIM 10000\nCLAIM_FEES 1")).unwrap();
+        assert_eq!(cert.steps[0].after.supply, Interval::new(10_000, 40_000));
+        assert_eq!(cert.steps[1].after.supply.hi, 40_000);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::Command;
+use curvevm::{CurveKind, CurveVM, MigrationThreshold};
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Command as Process, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Translates a curve's definition into SMT-LIB2 queries, one per invariant. Each
+// query asks the solver for a trade sequence that breaks the invariant, so `unsat`
+// means it holds; migration reachability asks for a sequence that reaches the
+// threshold instead. The curve is modelled over the reals rather than the VM's
+// fixed-point maths, and prices are in lamports per token rather than scaled by
+// `PRICE_SCALE`. `exp`, `ln` and the LBP power are uninterpreted functions
+// constrained only by the axioms the invariants rely on, so an `unsat` over them
+// still holds for the real functions but a model may not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Invariant {
+    BoundedSlope,
+    MonotonicPrice,
+    ReserveNonNegative,
+    MigrationReachable,
+}
+
+impl Invariant {
+    pub fn name(self) -> &'static str {
+        match self {
+            Invariant::BoundedSlope => "bounded-slope",
+            Invariant::MonotonicPrice => "monotonic-price",
+            Invariant::ReserveNonNegative => "reserve-non-negative",
+            Invariant::MigrationReachable => "migration-reachable",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query {
+    pub invariant: Invariant,
+    pub text: String,
+    // The most a trader can buy, which is what fails to reach an unreachable threshold.
+    cap: i64,
+    // The curve goes through `curve_exp`, `curve_ln` or `curve_pow`.
+    uninterpreted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Holds,
+    Violated(Vec<Command>),
+    Unknown(String),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Holds => write!(f, "holds"),
+            Verdict::Violated(trades) => {
+                let trades: Vec<_> = trades.iter().map(|t| format!("{} {}", t.opcode, t.operand)).collect();
+                write!(f, "violated by: {}", trades.join("; "))
+            }
+            Verdict::Unknown(reason) => write!(f, "unknown: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmtError {
+    SolverMissing(String),
+    UnsupportedSolver(String),
+    Solver(String),
+}
+
+impl fmt::Display for SmtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmtError::SolverMissing(solver) => write!(f, "SMT solver `{}` is not installed", solver),
+            SmtError::UnsupportedSolver(solver) => {
+                write!(f, "no argument profile for SMT solver `{}`; use z3 or cvc5", solver)
+            }
+            SmtError::Solver(err) => write!(f, "SMT solver failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SmtError {}
+
+// `max_price_step` bounds how far one token may move the price, in lamports.
+pub fn queries(vm: &CurveVM, max_price_step: i64) -> Vec<Query> {
+    let cap = vm.max_supply - vm.locked_allocation();
+    let uninterpreted = !matches!(vm.curve, CurveKind::Linear(_) | CurveKind::ConstantProduct(_));
+    let query = |invariant: Invariant, vars: &[&str], bounds: &str, goal: String| {
+        let mut text = format!("; {}\n(set-logic ALL)\n{}", invariant.name(), definitions(&vm.curve));
+        for var in vars {
+            text += &format!("(declare-const {} Int)\n", var);
+        }
+        text += &format!("(assert {})\n(assert {})\n(check-sat)\n(get-value ({}))\n", bounds, goal, vars.join(" "));
+        Query { invariant, text, cap, uninterpreted }
+    };
+    let mut out = vec![
+        query(
+            Invariant::BoundedSlope,
+            &["s"],
+            &format!("(and (<= 0 s) (< s {}))", cap),
+            format!("(> (- (price (to_real (+ s 1))) (price (to_real s))) {})", real(max_price_step as i128)),
+        ),
+        query(
+            Invariant::MonotonicPrice,
+            &["s1", "s2"],
+            &format!("(and (<= 0 s1) (< s1 s2) (<= s2 {}))", cap),
+            "(< (price (to_real s2)) (price (to_real s1)))".into(),
+        ),
+        // Buying `s` and selling `b` back leaves `reserve(s - b)` in the curve.
+        query(
+            Invariant::ReserveNonNegative,
+            &["s", "b"],
+            &format!("(and (<= 0 b) (<= b s) (<= s {}))", cap),
+            "(< (reserve (to_real (- s b))) 0.0)".into(),
+        ),
+    ];
+    if let Some(threshold) = vm.migration_threshold {
+        let reached = match threshold {
+            MigrationThreshold::Reserve(target) => format!("(>= (reserve (to_real s)) {})", real(target as i128)),
+            MigrationThreshold::MarketCap(target) => {
+                format!("(>= (* (price (to_real s)) (to_real s)) {})", real(target))
+            }
+        };
+        out.push(query(Invariant::MigrationReachable, &["s"], &format!("(and (<= 0 s) (<= s {}))", cap), reached));
+    }
+    out
+}
+
+// Reads a solver's answer to `query`.
+pub fn interpret(query: &Query, output: &str) -> Verdict {
+    let cap = query.cap;
+    let answer = output.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("");
+    let value = |name: &str| model_value(output, name);
+    let buy = |amount: i64| Command { opcode: "BUY".into(), operand: amount, limit: 0, expiry: 0 };
+    let sell = |amount: i64| Command { opcode: "SELL".into(), operand: amount, limit: 0, expiry: 0 };
+    let trades = match (query.invariant, answer) {
+        (_, "sat") if query.uninterpreted => {
+            return Verdict::Unknown("the model rests on an uninterpreted exp, ln or pow".into());
+        }
+        (Invariant::MigrationReachable, "sat") => return Verdict::Holds,
+        (Invariant::MigrationReachable, "unsat") => return Verdict::Violated(vec![buy(query.cap)]),
+        (_, "unsat") => return Verdict::Holds,
+        // Each model is checked against the bounds its query asserted, so a solver
+        // that answers wrongly cannot produce a trade the VM would never see.
+        (Invariant::BoundedSlope, "sat") => value("s").map(|s| (0 <= s && s < cap, vec![buy(s), buy(1)])),
+        (Invariant::MonotonicPrice, "sat") => {
+            value("s1").zip(value("s2")).map(|(a, b)| (0 <= a && a < b && b <= cap, vec![buy(a), buy(b - a)]))
+        }
+        (Invariant::ReserveNonNegative, "sat") => {
+            value("s").zip(value("b")).map(|(s, b)| (0 <= b && b <= s && s <= cap, vec![buy(s), sell(b)]))
+        }
+        (_, answer) => return Verdict::Unknown(format!("solver answered `{}`", answer)),
+    };
+    match trades {
+        // Buying nothing first is the same as starting there.
+        Some((true, trades)) => Verdict::Violated(trades.into_iter().filter(|t| t.operand != 0).collect()),
+        Some((false, _)) => Verdict::Unknown("the model lies outside the asserted bounds".into()),
+        None => Verdict::Unknown("the solver gave no model".into()),
+    }
+}
+
+// Runs every query through `solver`, a path to z3 or cvc5, giving each one
+// `timeout` before it counts as unknown.
+pub fn verify(
+    vm: &CurveVM,
+    max_price_step: i64,
+    solver: &str,
+    timeout: Duration,
+) -> Result<Vec<(Invariant, Verdict)>, SmtError> {
+    let args = profile(solver, timeout)?;
+    queries(vm, max_price_step)
+        .into_iter()
+        .map(|query| {
+            let verdict = match solve(solver, &args, &query.text, timeout)? {
+                Some(output) => interpret(&query, &output),
+                None => Verdict::Unknown(format!("the solver ran past its {:?} limit", timeout)),
+            };
+            Ok((query.invariant, verdict))
+        })
+        .collect()
+}
+
+// Flags that make `solver` read SMT-LIB2 from standard input and give up on its
+// own once `timeout` has passed.
+fn profile(solver: &str, timeout: Duration) -> Result<Vec<String>, SmtError> {
+    match Path::new(solver).file_stem().and_then(|name| name.to_str()) {
+        Some("z3") => Ok(vec!["-in".into(), format!("-T:{}", timeout.as_secs().max(1))]),
+        Some("cvc5") => Ok(vec![
+            "--lang".into(),
+            "smt2".into(),
+            "--produce-models".into(),
+            format!("--tlimit={}", timeout.as_millis().max(1)),
+        ]),
+        _ => Err(SmtError::UnsupportedSolver(solver.to_string())),
+    }
+}
+
+// A solver that ignores its own limit is killed a second after it, and `None`
+// stands in for the answer it never gave.
+fn solve(solver: &str, args: &[String], text: &str, timeout: Duration) -> Result<Option<String>, SmtError> {
+    let spawned = Process::new(solver).args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Err(SmtError::SolverMissing(solver.to_string())),
+        Err(err) => return Err(SmtError::Solver(err.to_string())),
+    };
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin.write_all(text.as_bytes()).map_err(|err| SmtError::Solver(err.to_string()))?;
+    drop(stdin);
+    // Reading on the side keeps a chatty solver from blocking on a full pipe.
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut bytes = Vec::new();
+        stdout.read_to_end(&mut bytes).map(|_| bytes)
+    });
+    let deadline = Instant::now() + timeout + Duration::from_secs(1);
+    while child.try_wait().map_err(|err| SmtError::Solver(err.to_string()))?.is_none() {
+        if Instant::now() >= deadline {
+            child.kill().and_then(|_| child.wait()).map_err(|err| SmtError::Solver(err.to_string()))?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let bytes = reader.join().expect("the reader does not panic").map_err(|err| SmtError::Solver(err.to_string()))?;
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+// Picks `name` out of a `(get-value ...)` answer such as `((s1 0) (s2 (- 5)))`.
+fn model_value(output: &str, name: &str) -> Option<i64> {
+    let spaced = output.replace(['(', ')'], " ");
+    let mut tokens = spaced.split_whitespace().skip_while(|token| *token != name).skip(1);
+    match tokens.next()? {
+        "-" => tokens.next()?.parse::<i64>().ok().map(|n| -n),
+        n => n.parse().ok(),
+    }
+}
+
+fn real(n: i128) -> String {
+    if n < 0 { format!("(- {}.0)", -n) } else { format!("{}.0", n) }
+}
+
+// `price` and `reserve` as functions of a real supply.
+fn definitions(curve: &CurveKind) -> String {
+    let fun = |name: &str, body: String| format!("(define-fun {} ((s Real)) Real {})\n", name, body);
+    let increasing = |f: &str, from: &str| {
+        format!(
+            "(declare-fun {f} (Real) Real)\n\
+             (assert (forall ((x Real) (y Real)) (=> (and {from} (< x y)) (< ({f} x) ({f} y)))))\n"
+        )
+    };
+    match *curve {
+        CurveKind::Linear(c) => {
+            let (base, num, den) = (real(c.base_price as i128), real(c.slope_num as i128), real(c.slope_den as i128));
+            fun("price", format!("(+ {} (/ (* {} s) {}))", base, num, den))
+                + &fun("reserve", format!("(+ (* {} s) (/ (* {} s s) (* 2.0 {})))", base, num, den))
+        }
+        CurveKind::ConstantProduct(c) => {
+            let (vr, vs) = (real(c.virtual_reserve as i128), real(c.virtual_supply as i128));
+            fun("price", format!("(/ (* {} {}) (* (- {} s) (- {} s)))", vr, vs, vs, vs))
+                + &fun("reserve", format!("(- (/ (* {} {}) (- {} s)) {})", vr, vs, vs, vr))
+        }
+        CurveKind::Exponential(c) => {
+            let (base, scale) = (real(c.base_price as i128), real(c.scale as i128));
+            increasing("curve_exp", "true")
+                + "(assert (= (curve_exp 0.0) 1.0))\n(assert (forall ((x Real)) (> (curve_exp x) 0.0)))\n"
+                + &fun("price", format!("(* {} (curve_exp (/ s {})))", base, scale))
+                + &fun("reserve", format!("(* {} {} (- (curve_exp (/ s {})) 1.0))", base, scale, scale))
+        }
+        CurveKind::Sigmoid(c) => {
+            let (top, mid, width) = (real(c.max_price as i128), real(c.midpoint as i128), real(c.width as i128));
+            let x = |s: &str| format!("(/ (- {} {}) {})", s, mid, width);
+            increasing("curve_exp", "true")
+                + &increasing("curve_ln", "(< 0.0 x)")
+                + "(assert (forall ((x Real)) (> (curve_exp x) 0.0)))\n"
+                + &fun("price", format!("(/ {} (+ 1.0 (curve_exp (- {}))))", top, x("s")))
+                + &fun(
+                    "reserve",
+                    format!(
+                        "(* {} {} (- (curve_ln (+ 1.0 (curve_exp {}))) (curve_ln (+ 1.0 (curve_exp {})))))",
+                        top,
+                        width,
+                        x("s"),
+                        x("0.0")
+                    ),
+                )
+        }
+        CurveKind::Lbp(c) => {
+            // growth(s) = (T / (T - s))^(wt / (1 - wt)). The trades may run at any later
+            // height, so `wt` is any weight between the current one and the final one.
+            let (vr, t) = (real(c.virtual_reserve as i128), real(c.token_balance as i128));
+            let (now, end) = (c.token_weight_bps(), c.end_weight_bps as i128);
+            let weight = |bps: i128| format!("(/ {} 10000.0)", real(bps));
+            format!(
+                "(declare-const wt Real)\n(assert (and (<= {} wt) (<= wt {})))\n",
+                weight(now.min(end)),
+                weight(now.max(end))
+            ) + "(declare-fun curve_pow (Real Real) Real)\n\
+                 (assert (forall ((x Real) (y Real) (e Real)) \
+                 (=> (and (<= 1.0 x) (< x y) (< 0.0 e)) (< (curve_pow x e) (curve_pow y e)))))\n\
+                 (assert (forall ((e Real)) (= (curve_pow 1.0 e) 1.0)))\n"
+                + &fun("growth", format!("(curve_pow (/ {} (- {} s)) (/ wt (- 1.0 wt)))", t, t))
+                + &fun("price", format!("(/ (* {} (growth s) wt) (* (- 1.0 wt) (- {} s)))", vr, t))
+                + &fun("reserve", format!("(* {} (- (growth s) 1.0))", vr))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curvevm::{ConstantProduct, Lbp, Linear, Sigmoid};
+
+    fn linear() -> CurveVM {
+        let mut vm =
+            CurveVM::with_curve(CurveKind::Linear(Linear { base_price: 10, slope_num: 1, slope_den: 100 }), 1_000)
+                .unwrap();
+        vm.migration_threshold = Some(MigrationThreshold::Reserve(20_000));
+        vm
+    }
+
+    #[test]
+    fn emits_one_query_per_invariant() {
+        let linear = queries(&linear(), 1);
+        let names: Vec<_> = linear.iter().map(|q| q.invariant.name()).collect();
+        assert_eq!(names, ["bounded-slope", "monotonic-price", "reserve-non-negative", "migration-reachable"]);
+        assert_eq!(
+            linear[1].text,
+            "; monotonic-price\n\
+             (set-logic ALL)\n\
+             (define-fun price ((s Real)) Real (+ 10.0 (/ (* 1.0 s) 100.0)))\n\
+             (define-fun reserve ((s Real)) Real (+ (* 10.0 s) (/ (* 1.0 s s) (* 2.0 100.0))))\n\
+             (declare-const s1 Int)\n\
+             (declare-const s2 Int)\n\
+             (assert (and (<= 0 s1) (< s1 s2) (<= s2 1000)))\n\
+             (assert (< (price (to_real s2)) (price (to_real s1))))\n\
+             (check-sat)\n\
+             (get-value (s1 s2))\n"
+        );
+        assert!(linear[3].text.contains("(assert (>= (reserve (to_real s)) 20000.0))"), "{}", linear[3].text);
+
+        let cp = CurveVM::with_curve(
+            CurveKind::ConstantProduct(ConstantProduct { virtual_reserve: 30, virtual_supply: 1_000 }),
+            800,
+        )
+        .unwrap();
+        let text = &queries(&cp, 1)[0].text;
+        assert!(text.contains("(define-fun price ((s Real)) Real (/ (* 30.0 1000.0) (* (- 1000.0 s) (- 1000.0 s))))"));
+        assert!(text.contains("(assert (and (<= 0 s) (< s 800)))"), "{}", text);
+
+        let sigmoid = CurveKind::Sigmoid(Sigmoid { max_price: 100, midpoint: 500, width: 100 });
+        let text = &queries(&CurveVM::with_curve(sigmoid, 1_000).unwrap(), 1)[2].text;
+        assert!(
+            text.contains("(declare-fun curve_exp (Real) Real)\n(assert (forall ((x Real) (y Real)) (=> (and true")
+        );
+        assert!(text.contains("(=> (and (< 0.0 x) (< x y)) (< (curve_ln x) (curve_ln y)))"), "{}", text);
+        assert!(text.contains("(curve_ln (+ 1.0 (curve_exp (/ (- 0.0 500.0) 100.0))))"), "{}", text);
+
+        let lbp = Lbp {
+            virtual_reserve: 1_000,
+            token_balance: 10_000,
+            start_weight_bps: 5_000,
+            end_weight_bps: 9_000,
+            duration: 10,
+            elapsed: 5,
+        };
+        let text = &queries(&CurveVM::with_curve(CurveKind::Lbp(lbp), 5_000).unwrap(), 1)[2].text;
+        assert!(text.contains("(assert (and (<= (/ 7000.0 10000.0) wt) (<= wt (/ 9000.0 10000.0))))"), "{}", text);
+        assert!(text.contains("(curve_pow (/ 10000.0 (- 10000.0 s)) (/ wt (- 1.0 wt)))"), "{}", text);
+    }
+
+    #[test]
+    fn turns_models_into_trade_sequences() {
+        let linear = queries(&linear(), 1);
+        let trades = |verdict: Verdict| verdict.to_string();
+        assert_eq!(trades(interpret(&linear[0], "unsat\n(error \"model is not available\")\n")), "holds");
+        assert_eq!(trades(interpret(&linear[0], "sat\n((s 41))\n")), "violated by: BUY 41; BUY 1");
+        assert_eq!(trades(interpret(&linear[1], "sat\n((s1 0)\n (s2 7))\n")), "violated by: BUY 7");
+        assert_eq!(trades(interpret(&linear[2], "sat\n((s 9) (b 4))")), "violated by: BUY 9; SELL 4");
+        assert_eq!(
+            trades(interpret(&linear[2], "sat\n((s (- 9)) (b 4))")),
+            "unknown: the model lies outside the asserted bounds"
+        );
+        assert_eq!(
+            trades(interpret(&linear[0], "sat\n((s 1000))")),
+            "unknown: the model lies outside the asserted bounds"
+        );
+        assert_eq!(trades(interpret(&linear[3], "sat\n((s 1000))")), "holds");
+        assert_eq!(trades(interpret(&linear[3], "unsat")), "violated by: BUY 1000");
+        assert_eq!(trades(interpret(&linear[1], "timeout")), "unknown: solver answered `timeout`");
+        assert_eq!(trades(interpret(&linear[1], "sat\n")), "unknown: the solver gave no model");
+
+        let sigmoid = CurveKind::Sigmoid(Sigmoid { max_price: 100, midpoint: 500, width: 100 });
+        let sigmoid = queries(&CurveVM::with_curve(sigmoid, 1_000).unwrap(), 1);
+        assert_eq!(trades(interpret(&sigmoid[2], "unsat")), "holds");
+        assert_eq!(
+            trades(interpret(&sigmoid[2], "sat\n((s 9) (b 4))")),
+            "unknown: the model rests on an uninterpreted exp, ln or pow"
+        );
+    }
+
+    #[test]
+    fn a_missing_solver_is_reported() {
+        let err = verify(&linear(), 1, "/no/such/dir/z3", Duration::from_secs(5)).unwrap_err();
+        assert_eq!(err, SmtError::SolverMissing("/no/such/dir/z3".into()));
+        let err = verify(&linear(), 1, "no-such-smt-solver", Duration::from_secs(5)).unwrap_err();
+        assert_eq!(err, SmtError::UnsupportedSolver("no-such-smt-solver".into()));
+    }
+
+    #[test]
+    fn solvers_get_their_own_time_limit() {
+        let timeout = Duration::from_millis(2_500);
+        assert_eq!(profile("/usr/bin/z3", timeout).unwrap(), ["-in", "-T:2"]);
+        assert_eq!(profile("cvc5", timeout).unwrap(), ["--lang", "smt2", "--produce-models", "--tlimit=2500"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_solver_past_its_limit_is_killed() {
+        let started = Instant::now();
+        assert_eq!(solve("sleep", &["30".into()], "(check-sat)\n", Duration::from_millis(50)), Ok(None));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::Instruction;
+use curvevm::{CurveId, Opcode, wasm};
+use wasm_encoder::{
+    CodeSection, ConstExpr, DataSection, EntityType, ExportKind, ExportSection, Function, FunctionSection,
+    ImportSection, Instruction as Op, MemorySection, MemoryType, Module, TypeSection, ValType,
+};
+
+const PAGE: usize = 65_536;
+
+// The module imports one host function per opcode, keeps the 32-byte ID of
+// every curve the program names in a data segment, and exports a `run`
+// function that calls the imports in program order.
+pub fn compile_wasm(program: &[Instruction]) -> Vec<u8> {
+    let mut curves: Vec<CurveId> = Vec::new();
+    for ins in program {
+        if !curves.contains(&ins.curve) {
+            curves.push(ins.curve);
+        }
+    }
+
+    let mut types = TypeSection::new();
+    types.function([ValType::I32, ValType::I64, ValType::I64, ValType::I64], []);
+    types.function([], []);
+
+    let mut imports = ImportSection::new();
+    for opcode in Opcode::ALL {
+        imports.import(wasm::IMPORT_MODULE, &wasm::import_name(opcode), EntityType::Function(0));
+    }
+
+    let mut functions = FunctionSection::new();
+    functions.function(1);
+
+    let table: Vec<u8> = curves.iter().flat_map(|id| id.0).collect();
+    let mut memories = MemorySection::new();
+    memories.memory(MemoryType {
+        minimum: table.len().div_ceil(PAGE).max(1) as u64,
+        maximum: None,
+        memory64: false,
+        shared: false,
+    });
+
+    let run = Opcode::ALL.len() as u32;
+    let mut exports = ExportSection::new();
+    exports.export(wasm::RUN_EXPORT, ExportKind::Func, run);
+    exports.export(wasm::MEMORY_EXPORT, ExportKind::Memory, 0);
+
+    let mut body = Function::new([]);
+    for ins in program {
+        let slot = curves.iter().position(|id| *id == ins.curve).expect("curve was collected above");
+        body.instruction(&Op::I32Const((slot * 32) as i32));
+        body.instruction(&Op::I64Const(ins.operand));
+        body.instruction(&Op::I64Const(ins.limit));
+        body.instruction(&Op::I64Const(ins.expiry as i64));
+        body.instruction(&Op::Call(ins.opcode as u32));
+    }
+    body.instruction(&Op::End);
+    let mut code = CodeSection::new();
+    code.function(&body);
+
+    let mut data = DataSection::new();
+    data.active(0, &ConstExpr::i32_const(0), table);
+
+    let mut module = Module::new();
+    module
+        .section(&types)
+        .section(&imports)
+        .section(&functions)
+        .section(&memories)
+        .section(&exports)
+        .section(&code)
+        .section(&data);
+    module.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curvevm::{Clock, ConstantProduct, CurveKind, CurveStore, CurveVM, MigrationThreshold, VestingSchedule};
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    fn genesis(budget: u64) -> (CurveStore, Vec<CurveId>) {
+        let dog = CurveId::derive("alice", "dog");
+        let curve = CurveKind::ConstantProduct(ConstantProduct { virtual_reserve: 30_000, virtual_supply: 1_000_000 });
+        let mut launch = CurveVM::with_curve(curve, 800_000).unwrap();
+        launch.migration_threshold = Some(MigrationThreshold::Reserve(40_000));
+        launch.add_vesting("alice", VestingSchedule::new(50_000, 0, 500, 10_000)).unwrap();
+        let mut store = CurveStore::new();
+        store.compute_budget = budget;
+        store.clock = Clock { height: 5, timestamp: 1_000 };
+        store.create(CurveId::default(), CurveVM::new()).unwrap();
+        store.create(dog, launch).unwrap();
+        (store, vec![CurveId::default(), dog, CurveId::derive("bob", "ghost")])
+    }
+
+    const OPCODES: [Opcode; 8] = [
+        Opcode::Buy,
+        Opcode::Buy,
+        Opcode::Buy,
+        Opcode::Sell,
+        Opcode::Sell,
+        Opcode::AddLiquidity,
+        Opcode::MigrateToAmm,
+        Opcode::Claim,
+    ];
+
+    #[test]
+    fn wasm_matches_native_execution() {
+        let senders = ["alice", "bob", "carol"];
+        let mut outcomes = [0; 2];
+        for seed in 1..=64u64 {
+            let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let budget = [60_000, 300_000][rng.below(2) as usize];
+            let (mut native, curves) = genesis(budget);
+            let mut hosted = native.clone();
+            for _ in 0..12 {
+                let sender = senders[rng.below(3) as usize];
+                let program: Vec<Instruction> = (0..1 + rng.below(3))
+                    .map(|_| Instruction {
+                        opcode: OPCODES[rng.below(OPCODES.len() as u64) as usize],
+                        operand: rng.below(400) as i64 * [1, 1, 1, 1, 1, 100, -1][rng.below(7) as usize],
+                        curve: curves[[0, 1, 1, 0, 1, 0, 1, 2][rng.below(8) as usize]],
+                        limit: [0, 0, 0, 0, 0, 0, 1, 5_000][rng.below(8) as usize],
+                        expiry: [0, 0, 0, 0, 0, 0, 4, 9][rng.below(8) as usize],
+                    })
+                    .collect();
+                let expected = native.execute(sender, &program);
+                let actual = hosted.execute_wasm(sender, &compile_wasm(&program));
+                outcomes[expected.is_ok() as usize] += 1;
+                assert_eq!(actual, expected, "seed {} program {:?}", seed, program);
+                assert_eq!(hosted.root(), native.root());
+            }
+        }
+        assert!(outcomes[0] > 100 && outcomes[1] > 100, "{:?}", outcomes);
+    }
+
+    #[test]
+    fn empty_program_is_a_no_op() {
+        let (mut store, _) = genesis(300_000);
+        let before = store.clone();
+        let receipt = store.execute_wasm("alice", &compile_wasm(&[])).unwrap();
+        assert_eq!(receipt.instructions, 0);
+        assert_eq!(store, before);
+    }
+}
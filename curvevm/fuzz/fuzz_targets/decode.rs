@@ -0,0 +1,16 @@
+#![no_main]
+
+use curvevm::{CurveVM, bytecode};
+use libfuzzer_sys::fuzz_target;
+
+// Anything the decoder accepts must re-encode to the same bytes, disassemble,
+// and run against a fresh curve without panicking.
+fuzz_target!(|data: &[u8]| {
+    let Ok((header, program)) = bytecode::decode(data) else {
+        return;
+    };
+    assert_eq!(bytecode::encode(&program), data);
+    assert_eq!(bytecode::decode_header(data), Ok(header));
+    bytecode::disassemble(data).expect("decoded programs disassemble");
+    let _ = CurveVM::new().execute("fuzzer", &program);
+});
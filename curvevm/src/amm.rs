@@ -0,0 +1,100 @@
+use crate::math::{self, Rounding};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+pub const DEFAULT_LP_FEE_BPS: u16 = 25;
+
+const BPS: u128 = 10_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct AmmPool {
+    pub token_reserve: i64,
+    pub sol_reserve: i64,
+    pub lp_supply: i64,
+    pub fee_bps: u16,
+}
+
+impl AmmPool {
+    // The pool mints the initial LP shares without crediting anyone; the VM hands
+    // pledgers their slice at migration and the rest stays locked in the pool.
+    pub fn open(token_reserve: i64, sol_reserve: i64, fee_bps: u16) -> Option<Self> {
+        if token_reserve <= 0 || sol_reserve <= 0 || fee_bps as u128 >= BPS {
+            return None;
+        }
+        let lp_supply = math::sqrt(token_reserve as u128 * sol_reserve as u128, Rounding::Down) as i64;
+        Some(Self { token_reserve, sol_reserve, lp_supply, fee_bps })
+    }
+
+    pub fn spot_price(&self) -> i128 {
+        math::mul_div(self.sol_reserve as i128, crate::PRICE_SCALE, self.token_reserve as i128).unwrap_or(i128::MAX)
+    }
+
+    pub fn buy_cost(&self, amount_out: i64) -> Option<i64> {
+        if amount_out >= self.token_reserve {
+            return None;
+        }
+        let x = self.sol_reserve as u128;
+        let y = self.token_reserve as u128;
+        let dy = u128::try_from(amount_out).ok()?;
+        let raw = math::mul_div_u(x, dy, y - dy, Rounding::Up)?;
+        let gross = math::mul_div_u(raw, BPS, BPS - self.fee_bps as u128, Rounding::Up)?;
+        i64::try_from(gross).ok()
+    }
+
+    pub fn sell_proceeds(&self, amount_in: i64) -> Option<i64> {
+        let x = self.sol_reserve as u128;
+        let y = self.token_reserve as u128;
+        let net = math::mul_div_u(u128::try_from(amount_in).ok()?, BPS - self.fee_bps as u128, BPS, Rounding::Down)?;
+        let out = math::mul_div_u(x, net, y + net, Rounding::Down)?;
+        i64::try_from(out).ok()
+    }
+
+    // Tokens and SOL paid out for burning `shares`, both rounded down.
+    pub fn withdraw_quote(&self, shares: i64) -> Option<(i64, i64)> {
+        let shares = u128::try_from(shares).ok()?;
+        let lp = self.lp_supply as u128;
+        let tokens = math::mul_div_u(self.token_reserve as u128, shares, lp, Rounding::Down)?;
+        let sol = math::mul_div_u(self.sol_reserve as u128, shares, lp, Rounding::Down)?;
+        Some((i64::try_from(tokens).ok()?, i64::try_from(sol).ok()?))
+    }
+
+    // Tokens a depositor must add alongside `sol` and the LP shares minted for it.
+    pub fn deposit_quote(&self, sol: i64) -> Option<(i64, i64)> {
+        let x = self.sol_reserve as u128;
+        let sol = u128::try_from(sol).ok()?;
+        let tokens = math::mul_div_u(sol, self.token_reserve as u128, x, Rounding::Up)?;
+        let shares = math::mul_div_u(sol, self.lp_supply as u128, x, Rounding::Down)?;
+        Some((i64::try_from(tokens).ok()?, i64::try_from(shares).ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swaps_never_decrease_k() {
+        let mut pool = AmmPool::open(1_000_000, 50_000, 30).unwrap();
+        let k = |p: &AmmPool| p.token_reserve as i128 * p.sol_reserve as i128;
+        let start = k(&pool);
+        let cost = pool.buy_cost(10_000).unwrap();
+        pool.sol_reserve += cost;
+        pool.token_reserve -= 10_000;
+        let after_buy = k(&pool);
+        assert!(after_buy > start);
+        let out = pool.sell_proceeds(10_000).unwrap();
+        pool.sol_reserve -= out;
+        pool.token_reserve += 10_000;
+        assert!(k(&pool) > after_buy);
+        assert!(out < cost);
+    }
+
+    #[test]
+    fn deposits_are_proportional() {
+        let pool = AmmPool::open(1_000_000, 50_000, 25).unwrap();
+        let (tokens, shares) = pool.deposit_quote(5_000).unwrap();
+        assert_eq!(tokens, 100_000);
+        assert_eq!(shares, pool.lp_supply / 10);
+        assert!(pool.buy_cost(1_000_000).is_none());
+        assert!(AmmPool::open(0, 1, 25).is_none());
+    }
+}
@@ -0,0 +1,398 @@
+use crate::{CurveId, DecodeError, Instruction, Opcode};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+pub const MAGIC: [u8; 3] = *b"CVM";
+pub const VERSION: u8 = 2;
+pub const HEADER_LEN: usize = MAGIC.len() + 1 + 32 + 32;
+
+const HAS_LIMIT: u8 = 0x80;
+const HAS_EXPIRY: u8 = 0x40;
+
+// Layout:
+//   header  magic "CVM" | version u8 | curve id [32] | code hash [32]
+//   body    varint n | n extra curve ids [32] | varint count |
+//           count x (opcode u8 | varint curve index | zigzag varint operand
+//                    | [zigzag varint limit] | [varint expiry])
+// Curve index 0 is the header curve and index i > 0 is the i-th extra curve.
+// The header curve is the first instruction's (the default ID for an empty
+// program) and extra curves are listed in order of first use.
+// The top two bits of the opcode byte flag a non-zero limit and expiry; a
+// cleared flag means the field is zero and absent.
+// The code hash is sha256(curve id | body). Integers must be minimally encoded,
+// so every program has exactly one encoding and one hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub curve: CurveId,
+    pub code_hash: [u8; 32],
+}
+
+// The header curve is the first instruction's, and extra curves follow in order
+// of first use.
+fn curve_table(program: &[Instruction]) -> Vec<CurveId> {
+    let mut curves = vec![program.first().map_or_else(CurveId::default, |ins| ins.curve)];
+    for ins in program {
+        if !curves.contains(&ins.curve) {
+            curves.push(ins.curve);
+        }
+    }
+    curves
+}
+
+pub fn encode(program: &[Instruction]) -> Vec<u8> {
+    let curves = curve_table(program);
+    let curve = curves[0];
+    let mut body = Vec::new();
+    let mut code = Vec::new();
+    for ins in program {
+        let index = curves.iter().position(|c| *c == ins.curve).expect("table lists every curve");
+        let mut byte = ins.opcode as u8;
+        if ins.limit != 0 {
+            byte |= HAS_LIMIT;
+        }
+        if ins.expiry != 0 {
+            byte |= HAS_EXPIRY;
+        }
+        code.push(byte);
+        put_varint(&mut code, index as u64);
+        put_varint(&mut code, zigzag(ins.operand));
+        if ins.limit != 0 {
+            put_varint(&mut code, zigzag(ins.limit));
+        }
+        if ins.expiry != 0 {
+            put_varint(&mut code, ins.expiry);
+        }
+    }
+    put_varint(&mut body, curves.len() as u64 - 1);
+    for extra in &curves[1..] {
+        body.extend_from_slice(&extra.0);
+    }
+    put_varint(&mut body, program.len() as u64);
+    body.extend_from_slice(&code);
+
+    let mut out = Vec::with_capacity(HEADER_LEN + body.len());
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&curve.0);
+    out.extend_from_slice(&hash(&curve, &body));
+    out.extend_from_slice(&body);
+    out
+}
+
+pub fn decode(bytes: &[u8]) -> Result<(Header, Vec<Instruction>), DecodeError> {
+    let header = decode_header(bytes)?;
+    let mut reader = Reader { bytes: &bytes[HEADER_LEN..] };
+    if hash(&header.curve, reader.bytes) != header.code_hash {
+        return Err(DecodeError::HashMismatch);
+    }
+    let mut curves = vec![header.curve];
+    for _ in 0..reader.varint()? {
+        let id = CurveId(reader.take(32)?.try_into().expect("took 32 bytes"));
+        if curves.contains(&id) {
+            return Err(DecodeError::DuplicateCurve(id));
+        }
+        curves.push(id);
+    }
+    let count = reader.varint()?;
+    // Each instruction takes at least three bytes, which bounds the allocation.
+    if count > reader.bytes.len() as u64 / 3 {
+        return Err(DecodeError::Truncated);
+    }
+    let mut program = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let byte = reader.take(1)?[0];
+        let opcode = Opcode::from_byte(byte & !(HAS_LIMIT | HAS_EXPIRY)).ok_or(DecodeError::UnknownOpcode(byte))?;
+        let index = reader.varint()?;
+        let curve = *curves.get(index as usize).ok_or(DecodeError::UnknownCurveIndex(index))?;
+        let operand = unzigzag(reader.varint()?);
+        let limit = if byte & HAS_LIMIT != 0 { unzigzag(reader.nonzero()?) } else { 0 };
+        let expiry = if byte & HAS_EXPIRY != 0 { reader.nonzero()? } else { 0 };
+        program.push(Instruction { opcode, operand, curve, limit, expiry });
+    }
+    if !reader.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes(reader.bytes.len()));
+    }
+    if curves.len() > 1 && !curves[1..].iter().all(|id| program.iter().any(|ins| ins.curve == *id)) {
+        return Err(DecodeError::UnusedCurve);
+    }
+    if curve_table(&program) != curves {
+        return Err(DecodeError::NonCanonical);
+    }
+    Ok((header, program))
+}
+
+pub fn decode_header(bytes: &[u8]) -> Result<Header, DecodeError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(DecodeError::Truncated);
+    }
+    if bytes[..3] != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    if bytes[3] != VERSION {
+        return Err(DecodeError::UnsupportedVersion(bytes[3]));
+    }
+    Ok(Header {
+        version: bytes[3],
+        curve: CurveId(bytes[4..36].try_into().expect("slice is 32 bytes")),
+        code_hash: bytes[36..HEADER_LEN].try_into().expect("slice is 32 bytes"),
+    })
+}
+
+pub fn code_hash(program: &[Instruction]) -> [u8; 32] {
+    decode_header(&encode(program)).expect("encoder emits a valid header").code_hash
+}
+
+pub fn disassemble(bytes: &[u8]) -> Result<String, DecodeError> {
+    let (header, program) = decode(bytes)?;
+    let mut out = String::new();
+    writeln!(out, "; curvevm bytecode v{}, {} bytes", header.version, bytes.len()).unwrap();
+    writeln!(out, "; curve {}", header.curve).unwrap();
+    writeln!(out, "; hash  {}", hex(&header.code_hash)).unwrap();
+    for (i, ins) in program.iter().enumerate() {
+        write!(out, "{:04}  {:<16}{}", i, ins.opcode.mnemonic(), ins.operand).unwrap();
+        if ins.limit != 0 {
+            write!(out, "  limit={}", ins.limit).unwrap();
+        }
+        if ins.expiry != 0 {
+            write!(out, "  until={}", ins.expiry).unwrap();
+        }
+        if ins.curve != header.curve {
+            write!(out, "  @{}", ins.curve).unwrap();
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn hash(curve: &CurveId, body: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(curve.0);
+    hasher.update(body);
+    hasher.finalize().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            let bits = (byte & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(DecodeError::VarintOverflow);
+            }
+            v |= bits << shift;
+            if byte & 0x80 == 0 {
+                if byte == 0 && shift > 0 {
+                    return Err(DecodeError::NonCanonical);
+                }
+                return Ok(v);
+            }
+        }
+        Err(DecodeError::VarintOverflow)
+    }
+
+    // Flagged fields are never zero, or one program would have two encodings.
+    fn nonzero(&mut self) -> Result<u64, DecodeError> {
+        match self.varint()? {
+            0 => Err(DecodeError::NonCanonical),
+            v => Ok(v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::any_instruction;
+    use proptest::prelude::*;
+
+    fn ins(opcode: Opcode, operand: i64, curve: CurveId) -> Instruction {
+        Instruction { opcode, operand, curve, limit: 0, expiry: 0 }
+    }
+
+    fn sample() -> Vec<Instruction> {
+        let dog = CurveId::derive("alice", "dog");
+        vec![
+            ins(Opcode::Buy, 5, CurveId::default()),
+            ins(Opcode::Sell, 300, dog),
+            ins(Opcode::AddLiquidity, i64::MAX, CurveId::default()),
+            ins(Opcode::MigrateToAmm, -1, dog),
+        ]
+    }
+
+    #[test]
+    fn round_trips_programs() {
+        let program = sample();
+        let bytes = encode(&program);
+        let (header, decoded) = decode(&bytes).unwrap();
+        assert_eq!(decoded, program);
+        assert_eq!(header.curve, CurveId::default());
+        assert_eq!(header.code_hash, code_hash(&program));
+        assert_eq!(decode(&encode(&[])).unwrap().1, []);
+        // One curve table entry plus a few bytes per instruction.
+        assert_eq!(bytes.len(), HEADER_LEN + 1 + 32 + 1 + (3 + 4 + 12 + 3));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let bytes = encode(&sample());
+        assert_eq!(decode(&bytes[..10]), Err(DecodeError::Truncated));
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert_eq!(decode(&bad), Err(DecodeError::BadMagic));
+        let mut bad = bytes.clone();
+        bad[3] = 1;
+        assert_eq!(decode(&bad), Err(DecodeError::UnsupportedVersion(1)));
+        let mut bad = bytes.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(decode(&bad), Err(DecodeError::HashMismatch));
+        let mut long = bytes.clone();
+        long.push(0);
+        assert_eq!(decode(&long), Err(DecodeError::HashMismatch));
+    }
+
+    fn rehash(mut bytes: Vec<u8>) -> Vec<u8> {
+        let curve = CurveId(bytes[4..36].try_into().unwrap());
+        let digest = hash(&curve, &bytes[HEADER_LEN..]);
+        bytes[36..HEADER_LEN].copy_from_slice(&digest);
+        bytes
+    }
+
+    #[test]
+    fn rejects_well_hashed_garbage() {
+        let body = |tail: &[u8]| {
+            let mut bytes = encode(&[]);
+            bytes.truncate(HEADER_LEN);
+            bytes.extend_from_slice(tail);
+            rehash(bytes)
+        };
+        assert_eq!(decode(&body(&[0, 1, 9, 0, 0])), Err(DecodeError::UnknownOpcode(9)));
+        assert_eq!(decode(&body(&[0, 1, 0, 1, 0])), Err(DecodeError::UnknownCurveIndex(1)));
+        assert_eq!(decode(&body(&[0, 1, 0x80, 0, 0, 0])), Err(DecodeError::NonCanonical));
+        assert_eq!(decode(&body(&[0, 1, 0x40, 0, 0])), Err(DecodeError::Truncated));
+        assert_eq!(decode(&body(&[0, 1, 0x49, 0, 0, 1])), Err(DecodeError::UnknownOpcode(0x49)));
+        assert_eq!(decode(&body(&[0, 1, 0, 0, 0x80, 0])), Err(DecodeError::NonCanonical));
+        assert_eq!(decode(&body(&[0, 1, 0, 0, 2, 7])), Err(DecodeError::TrailingBytes(1)));
+        assert_eq!(decode(&body(&[0, 200, 1])), Err(DecodeError::Truncated));
+        let mut overflow = vec![0, 1, 0, 0];
+        overflow.extend_from_slice(&[0xff; 10]);
+        assert_eq!(decode(&body(&overflow)), Err(DecodeError::VarintOverflow));
+        let mut unused = vec![1];
+        unused.extend_from_slice(&[7; 32]);
+        unused.extend_from_slice(&[1, 0, 0, 0]);
+        assert_eq!(decode(&body(&unused)), Err(DecodeError::UnusedCurve));
+        let mut twice = vec![1];
+        twice.extend_from_slice(&[0; 32]);
+        twice.extend_from_slice(&[1, 0, 1, 0]);
+        assert_eq!(decode(&body(&twice)), Err(DecodeError::DuplicateCurve(CurveId::default())));
+        let mut retargeted = encode(&[]);
+        retargeted[4] = 1;
+        assert_eq!(decode(&rehash(retargeted)), Err(DecodeError::NonCanonical));
+        let mut late = vec![1];
+        late.extend_from_slice(&[7; 32]);
+        late.extend_from_slice(&[2, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(decode(&body(&late)), Err(DecodeError::NonCanonical));
+    }
+
+    #[test]
+    fn disassembles_with_curve_annotations() {
+        let program = sample();
+        let text = disassemble(&encode(&program)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], format!("; curve {}", CurveId::default()));
+        assert_eq!(lines[3], "0000  BUY             5");
+        assert_eq!(lines[4], format!("0001  SELL            300  @{}", program[1].curve));
+        assert_eq!(lines[6], format!("0003  MIGRATE_TO_AMM  -1  @{}", program[3].curve));
+    }
+
+    #[test]
+    fn round_trips_limits_and_expiries() {
+        let mut program = sample();
+        program[0].limit = 1_000;
+        program[1].limit = -7;
+        program[1].expiry = u64::MAX;
+        program[3].expiry = 1;
+        let bytes = encode(&program);
+        assert_eq!(decode(&bytes).unwrap().1, program);
+        // Each set field costs only its own varint.
+        assert_eq!(bytes.len(), encode(&sample()).len() + 2 + 1 + 10 + 1);
+        let text = disassemble(&bytes).unwrap();
+        assert!(text.contains("0000  BUY             5  limit=1000\n"));
+        assert!(text.contains(&format!("0001  SELL            300  limit=-7  until={}  @", u64::MAX)));
+    }
+
+    // The property the fuzz target checks: decoding never panics, and anything
+    // that decodes is the one canonical encoding of its program.
+    fn decodes_canonically(bytes: &[u8]) -> Result<(), TestCaseError> {
+        if let Ok((header, program)) = decode(bytes) {
+            prop_assert_eq!(encode(&program), bytes);
+            prop_assert_eq!(header.code_hash, code_hash(&program));
+            prop_assert!(disassemble(bytes).is_ok());
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_decode_canonically(mut bytes in prop::collection::vec(any::<u8>(), 0..160), fix_header in any::<bool>()) {
+            if fix_header && bytes.len() >= HEADER_LEN {
+                bytes[..4].copy_from_slice(&[b'C', b'V', b'M', VERSION]);
+                bytes = rehash(bytes);
+            }
+            decodes_canonically(&bytes)?;
+        }
+
+        #[test]
+        fn mutated_programs_decode_canonically(
+            program in prop::collection::vec((any_instruction(), 0..3u8), 0..6),
+            edits in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..4),
+        ) {
+            let program: Vec<Instruction> =
+                program.into_iter().map(|(ins, curve)| Instruction { curve: CurveId([curve; 32]), ..ins }).collect();
+            let bytes = encode(&program);
+            prop_assert_eq!(decode(&bytes).unwrap().1, program);
+            // Edit the header curve or the body; rehashing then gets past the hash check.
+            let editable: Vec<usize> = (4..36).chain(HEADER_LEN..bytes.len()).collect();
+            let mut mutated = bytes.clone();
+            for (at, byte) in edits {
+                mutated[*at.get(&editable)] = byte;
+            }
+            decodes_canonically(&rehash(mutated))?;
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{Instruction, Opcode};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+pub const DEFAULT_COMPUTE_BUDGET: u64 = 300_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct CostTable {
+    pub buy: u64,
+    pub sell: u64,
+    pub add_liquidity: u64,
+    pub migrate_to_amm: u64,
+    pub claim: u64,
+    pub remove_liquidity: u64,
+    pub claim_fees: u64,
+}
+
+impl CostTable {
+    pub fn cost(&self, opcode: Opcode) -> u64 {
+        match opcode {
+            Opcode::Buy => self.buy,
+            Opcode::Sell => self.sell,
+            Opcode::AddLiquidity => self.add_liquidity,
+            Opcode::MigrateToAmm => self.migrate_to_amm,
+            Opcode::Claim => self.claim,
+            Opcode::RemoveLiquidity => self.remove_liquidity,
+            Opcode::ClaimFees => self.claim_fees,
+        }
+    }
+
+    pub fn program_cost(&self, program: &[Instruction]) -> u64 {
+        program.iter().fold(0u64, |total, ins| total.saturating_add(self.cost(ins.opcode)))
+    }
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        Self {
+            buy: 12_000,
+            sell: 12_000,
+            add_liquidity: 6_000,
+            migrate_to_amm: 40_000,
+            claim: 8_000,
+            remove_liquidity: 6_000,
+            claim_fees: 8_000,
+        }
+    }
+}
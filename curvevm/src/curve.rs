@@ -0,0 +1,479 @@
+use crate::math::{self, Rounding, UWAD, WAD};
+#[cfg(feature = "vm")]
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+
+pub const PRICE_SCALE: i128 = 1_000_000_000;
+
+const BPS: i128 = 10_000;
+const MAX_EXPONENT: i128 = 40;
+
+pub trait Curve {
+    fn spot_price(&self, supply: i64) -> i128;
+
+    // Reserve held by the curve once `supply` tokens are outstanding. Rounded up so the
+    // pool never owes more than it holds; trade amounts are differences of this value,
+    // which keeps pricing independent of how a trade is split.
+    fn reserve_at(&self, supply: i64) -> Option<i128>;
+
+    fn max_supply(&self) -> i64;
+
+    fn cost(&self, from: i64, to: i64) -> Option<i128> {
+        self.reserve_at(to)?.checked_sub(self.reserve_at(from)?)
+    }
+
+    fn amount_for_cost(&self, supply: i64, budget: i128) -> i64 {
+        let (mut lo, mut hi) = (0, self.max_supply() - supply);
+        if hi <= 0 || budget <= 0 {
+            return 0;
+        }
+        while lo < hi {
+            let mid = lo + (hi - lo + 1) / 2;
+            if self.cost(supply, supply + mid).is_some_and(|c| c <= budget) {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        lo
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+pub struct Linear {
+    pub base_price: i64,
+    pub slope_num: i64,
+    pub slope_den: i64,
+}
+
+impl Curve for Linear {
+    fn spot_price(&self, supply: i64) -> i128 {
+        let slope = math::mul_div(supply as i128 * PRICE_SCALE, self.slope_num as i128, self.slope_den as i128);
+        slope.map_or(i128::MAX, |p| p.saturating_add(self.base_price as i128 * PRICE_SCALE))
+    }
+
+    fn reserve_at(&self, supply: i64) -> Option<i128> {
+        let s = unsigned(supply.into())?;
+        let slope_den = unsigned(2 * self.slope_den as i128)?;
+        let area = math::mul_div_u(s * s, unsigned(self.slope_num.into())?, slope_den, Rounding::Up)?;
+        (self.base_price as i128 * supply as i128).checked_add(signed(area)?)
+    }
+
+    fn max_supply(&self) -> i64 {
+        i64::MAX
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+pub struct ConstantProduct {
+    pub virtual_reserve: i64,
+    pub virtual_supply: i64,
+}
+
+impl Curve for ConstantProduct {
+    fn spot_price(&self, supply: i64) -> i128 {
+        let remaining = self.virtual_supply as i128 - supply as i128;
+        math::mul_div(
+            self.virtual_reserve as i128 * self.virtual_supply as i128,
+            PRICE_SCALE,
+            remaining * remaining,
+        )
+        .unwrap_or(i128::MAX)
+    }
+
+    fn reserve_at(&self, supply: i64) -> Option<i128> {
+        let k = unsigned(self.virtual_reserve as i128 * self.virtual_supply as i128)?;
+        let remaining = unsigned(self.virtual_supply as i128 - supply as i128)?;
+        signed(math::div_u(k, remaining, Rounding::Up)?)?.checked_sub(self.virtual_reserve as i128)
+    }
+
+    fn max_supply(&self) -> i64 {
+        self.virtual_supply - 1
+    }
+}
+
+// price(s) = base_price * e^(s / scale)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+pub struct Exponential {
+    pub base_price: i64,
+    pub scale: i64,
+}
+
+impl Exponential {
+    fn growth(&self, supply: i64) -> Option<u128> {
+        unsigned(math::exp((supply as i128 * WAD).checked_div(self.scale as i128)?)?)
+    }
+}
+
+impl Curve for Exponential {
+    fn spot_price(&self, supply: i64) -> i128 {
+        let top = unsigned(self.base_price as i128 * PRICE_SCALE);
+        top.zip(self.growth(supply))
+            .and_then(|(top, growth)| math::wad_mul(top, growth, Rounding::Down))
+            .and_then(signed)
+            .unwrap_or(i128::MAX)
+    }
+
+    fn reserve_at(&self, supply: i64) -> Option<i128> {
+        let area = unsigned(self.base_price as i128 * self.scale as i128)?;
+        signed(math::wad_mul(area, self.growth(supply)?.checked_sub(UWAD)?, Rounding::Up)?)
+    }
+
+    fn max_supply(&self) -> i64 {
+        (MAX_EXPONENT * self.scale as i128).min(i64::MAX as i128) as i64
+    }
+}
+
+// price(s) = max_price / (1 + e^(-(s - midpoint) / width))
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+pub struct Sigmoid {
+    pub max_price: i64,
+    pub midpoint: i64,
+    pub width: i64,
+}
+
+impl Sigmoid {
+    fn x(&self, supply: i64) -> Option<i128> {
+        ((supply as i128 - self.midpoint as i128) * WAD).checked_div(self.width as i128)
+    }
+}
+
+impl Curve for Sigmoid {
+    fn spot_price(&self, supply: i64) -> i128 {
+        let top = unsigned(self.max_price as i128 * PRICE_SCALE);
+        let Some(x) = self.x(supply) else { return i128::MAX };
+        // Only ever evaluate e^-|x|, which stays below one on either side of the midpoint.
+        let e = math::exp(-x.abs()).and_then(unsigned);
+        top.zip(e)
+            .and_then(|(top, e)| math::mul_div_u(top, if x >= 0 { UWAD } else { e }, UWAD + e, Rounding::Down))
+            .and_then(signed)
+            .unwrap_or(i128::MAX)
+    }
+
+    fn reserve_at(&self, supply: i64) -> Option<i128> {
+        let area = unsigned(self.max_price as i128 * self.width as i128)?;
+        let delta = softplus(self.x(supply)?)? - softplus(self.x(0)?)?;
+        signed(math::wad_mul(area, unsigned(delta.max(0))?, Rounding::Up)?)
+    }
+
+    fn max_supply(&self) -> i64 {
+        i64::MAX
+    }
+}
+
+// Balancer-style weighted pool against a virtual reserve. The token weight shifts
+// linearly from `start_weight_bps` to `end_weight_bps` over `duration` steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+pub struct Lbp {
+    pub virtual_reserve: i64,
+    pub token_balance: i64,
+    pub start_weight_bps: u16,
+    pub end_weight_bps: u16,
+    pub duration: u64,
+    pub elapsed: u64,
+}
+
+impl Lbp {
+    pub fn at(self, elapsed: u64) -> Self {
+        Self { elapsed, ..self }
+    }
+
+    // Past the end of the schedule the weight no longer moves with the clock.
+    pub fn is_settled(&self) -> bool {
+        self.start_weight_bps == self.end_weight_bps || self.elapsed >= self.duration
+    }
+
+    pub fn token_weight_bps(&self) -> i128 {
+        if self.duration == 0 {
+            return self.end_weight_bps as i128;
+        }
+        let start = self.start_weight_bps as i128;
+        let end = self.end_weight_bps as i128;
+        let elapsed = self.elapsed.min(self.duration) as i128;
+        start + (end - start) * elapsed / self.duration as i128
+    }
+
+    fn exponent(&self) -> Option<i128> {
+        let wt = self.token_weight_bps();
+        (wt * WAD).checked_div(BPS - wt)
+    }
+
+    fn growth(&self, supply: i64) -> Option<u128> {
+        let t = self.token_balance as i128;
+        let ratio = math::wad_div(unsigned(t)?, unsigned(t - supply as i128)?, Rounding::Up)?;
+        unsigned(math::pow(signed(ratio)?, self.exponent()?)?)
+    }
+}
+
+impl Curve for Lbp {
+    fn spot_price(&self, supply: i64) -> i128 {
+        let wt = self.token_weight_bps();
+        let top = unsigned(self.virtual_reserve as i128 * PRICE_SCALE);
+        let remaining = unsigned((BPS - wt) * (self.token_balance as i128 - supply as i128));
+        top.zip(self.growth(supply))
+            .and_then(|(top, growth)| math::wad_mul(top, growth, Rounding::Down))
+            .zip(remaining)
+            .and_then(|(balance, remaining)| math::mul_div_u(balance, wt as u128, remaining, Rounding::Down))
+            .and_then(signed)
+            .unwrap_or(i128::MAX)
+    }
+
+    fn reserve_at(&self, supply: i64) -> Option<i128> {
+        let growth = self.growth(supply)?.checked_sub(UWAD)?;
+        signed(math::wad_mul(unsigned(self.virtual_reserve.into())?, growth, Rounding::Up)?)
+    }
+
+    fn max_supply(&self) -> i64 {
+        // Keep (T / (T - s))^(wt / ws) within the range the fixed-point exp supports.
+        let t = self.token_balance as i128;
+        let Some(exponent) = self.exponent().filter(|e| *e > 0) else { return 0 };
+        let shrink = math::exp(-MAX_EXPONENT * WAD * WAD / exponent).unwrap_or(0);
+        let floor = math::mul_div_ceil(t, shrink, WAD);
+        (t - floor.unwrap_or(t).max(1)) as i64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+#[serde(rename_all = "snake_case")]
+pub enum CurveKind {
+    Linear(Linear),
+    ConstantProduct(ConstantProduct),
+    Exponential(Exponential),
+    Sigmoid(Sigmoid),
+    Lbp(Lbp),
+}
+
+impl CurveKind {
+    // Parameters the curve maths can divide by, or that would make it meaningless.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            CurveKind::Linear(c) => c.base_price >= 0 && c.slope_num >= 0 && c.slope_den > 0,
+            CurveKind::ConstantProduct(c) => c.virtual_reserve > 0 && c.virtual_supply > 0,
+            CurveKind::Exponential(c) => c.base_price > 0 && c.scale > 0,
+            CurveKind::Sigmoid(c) => c.max_price > 0 && c.width > 0,
+            CurveKind::Lbp(c) => {
+                let weights = 1..10_000;
+                c.virtual_reserve > 0
+                    && c.token_balance > 0
+                    && weights.contains(&c.start_weight_bps)
+                    && weights.contains(&c.end_weight_bps)
+            }
+        }
+    }
+
+    // Prices the same at every later height.
+    pub fn is_settled(&self) -> bool {
+        match self {
+            CurveKind::Lbp(c) => c.is_settled(),
+            _ => true,
+        }
+    }
+
+    fn inner(&self) -> &dyn Curve {
+        match self {
+            CurveKind::Linear(c) => c,
+            CurveKind::ConstantProduct(c) => c,
+            CurveKind::Exponential(c) => c,
+            CurveKind::Sigmoid(c) => c,
+            CurveKind::Lbp(c) => c,
+        }
+    }
+}
+
+impl Curve for CurveKind {
+    fn spot_price(&self, supply: i64) -> i128 {
+        self.inner().spot_price(supply)
+    }
+
+    fn reserve_at(&self, supply: i64) -> Option<i128> {
+        self.inner().reserve_at(supply)
+    }
+
+    fn max_supply(&self) -> i64 {
+        self.inner().max_supply()
+    }
+
+    fn cost(&self, from: i64, to: i64) -> Option<i128> {
+        self.inner().cost(from, to)
+    }
+
+    fn amount_for_cost(&self, supply: i64, budget: i128) -> i64 {
+        self.inner().amount_for_cost(supply, budget)
+    }
+}
+
+impl Default for CurveKind {
+    fn default() -> Self {
+        CurveKind::Linear(Linear { base_price: 1, slope_num: 1, slope_den: 1 })
+    }
+}
+
+// ln(1 + e^x), written so exp only sees non-positive arguments.
+fn softplus(x: i128) -> Option<i128> {
+    Some(x.max(0) + math::ln(WAD + math::exp(-x.abs())?)?)
+}
+
+fn unsigned(v: i128) -> Option<u128> {
+    u128::try_from(v).ok()
+}
+
+fn signed(v: u128) -> Option<i128> {
+    i128::try_from(v).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn families() -> Vec<(CurveKind, i64)> {
+        vec![
+            (CurveKind::Linear(Linear { base_price: 10, slope_num: 1, slope_den: 100 }), 10_000),
+            (
+                CurveKind::ConstantProduct(ConstantProduct {
+                    virtual_reserve: 30_000_000_000,
+                    virtual_supply: 1_073_000_000_000_000,
+                }),
+                800_000_000_000_000,
+            ),
+            (CurveKind::Exponential(Exponential { base_price: 1_000, scale: 50_000 }), 400_000),
+            (CurveKind::Sigmoid(Sigmoid { max_price: 1_000_000, midpoint: 500_000, width: 100_000 }), 1_000_000),
+            (
+                CurveKind::Lbp(Lbp {
+                    virtual_reserve: 1_000_000,
+                    token_balance: 10_000_000,
+                    start_weight_bps: 9_000,
+                    end_weight_bps: 5_000,
+                    duration: 100,
+                    elapsed: 0,
+                }),
+                5_000_000,
+            ),
+        ]
+    }
+
+    #[test]
+    fn price_is_monotonic_in_supply() {
+        for (curve, upto) in families() {
+            let step = upto / 50;
+            let mut last = curve.spot_price(0);
+            for i in 1..=50 {
+                let p = curve.spot_price(i * step);
+                assert!(p > last, "{:?}: price at {} not above previous", curve, i * step);
+                last = p;
+            }
+        }
+    }
+
+    #[test]
+    fn split_buys_cost_the_same_as_one_buy() {
+        for (curve, upto) in families() {
+            let step = upto / 10;
+            let whole = curve.cost(0, upto).unwrap();
+            let split: i128 = (0..10).map(|i| curve.cost(i * step, (i + 1) * step).unwrap()).sum();
+            assert_eq!(whole, split, "{:?}", curve);
+            assert!(whole > 0);
+        }
+    }
+
+    #[test]
+    fn cost_tracks_spot_price() {
+        // Buying a sliver of supply should cost roughly spot price times amount.
+        for (curve, upto) in families() {
+            let s = upto / 2;
+            let amount = upto / 1_000;
+            let cost = curve.cost(s, s + amount).unwrap();
+            let lower = curve.spot_price(s) * amount as i128 / PRICE_SCALE;
+            let upper = curve.spot_price(s + amount) * amount as i128 / PRICE_SCALE + 2;
+            assert!(lower <= cost && cost <= upper, "{:?}: {} not in [{}, {}]", curve, cost, lower, upper);
+        }
+    }
+
+    #[test]
+    fn amount_for_cost_inverts_cost() {
+        for (curve, upto) in families() {
+            let s = upto / 4;
+            let budget = curve.cost(s, s + upto / 3).unwrap() + 1;
+            let amount = curve.amount_for_cost(s, budget);
+            assert!(curve.cost(s, s + amount).unwrap() <= budget);
+            assert!(curve.cost(s, s + amount + 1).unwrap() > budget, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn linear_cost_matches_integral() {
+        let curve = Linear { base_price: 2, slope_num: 1, slope_den: 1 };
+        // 2*10 + 10^2/2
+        assert_eq!(curve.cost(0, 10), Some(70));
+        assert_eq!(curve.cost(10, 20), Some(20 + 150));
+    }
+
+    #[test]
+    fn lbp_price_falls_as_weights_shift() {
+        let start = Lbp {
+            virtual_reserve: 1_000_000,
+            token_balance: 10_000_000,
+            start_weight_bps: 9_600,
+            end_weight_bps: 5_000,
+            duration: 10,
+            elapsed: 0,
+        };
+        let mut last = start.spot_price(0);
+        for step in 1..=10 {
+            let p = start.at(step).spot_price(0);
+            assert!(p < last);
+            last = p;
+        }
+        assert_eq!(start.at(50).token_weight_bps(), 5_000);
+    }
+
+    #[test]
+    fn out_of_range_supply_is_an_overflow_not_a_panic() {
+        let curve = Exponential { base_price: 1, scale: 1_000 };
+        assert!(curve.reserve_at(curve.max_supply()).is_some());
+        assert_eq!(curve.reserve_at(46_000), None);
+        assert_eq!(curve.cost(36_000, 46_000), None);
+        assert_eq!(curve.spot_price(46_000), i128::MAX);
+        assert_eq!(curve.amount_for_cost(0, i128::MAX), curve.max_supply());
+    }
+
+    #[test]
+    fn degenerate_parameters_are_invalid_not_a_panic() {
+        let lbp = Lbp {
+            virtual_reserve: 1_000,
+            token_balance: 1_000,
+            start_weight_bps: 10_000,
+            end_weight_bps: 0,
+            duration: 10,
+            elapsed: 0,
+        };
+        let curves = [
+            CurveKind::Linear(Linear { base_price: 1, slope_num: 1, slope_den: 0 }),
+            CurveKind::Exponential(Exponential { base_price: 1, scale: 0 }),
+            CurveKind::Sigmoid(Sigmoid { max_price: 1, midpoint: 0, width: 0 }),
+            CurveKind::Lbp(lbp),
+            CurveKind::Lbp(lbp.at(10)),
+        ];
+        for curve in curves {
+            assert!(!curve.is_valid(), "{:?}", curve);
+            // Evaluating them anyway must not divide by zero.
+            curve.reserve_at(10);
+            curve.max_supply();
+            curve.spot_price(10);
+        }
+        assert!(families().iter().all(|(curve, _)| curve.is_valid()));
+    }
+
+    #[test]
+    fn max_supply_stays_in_range() {
+        for (curve, _) in families() {
+            let max = curve.max_supply();
+            assert!(curve.reserve_at(max).unwrap() >= curve.reserve_at(max / 2).unwrap());
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{
+    Account, CostTable, CurveId, CurveKind, CurveStore, CurveVM, FeeConfig, LaunchRules, MigrationThreshold,
+    VestingSchedule, VmError,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<T>(T);
+
+impl CurveVM {
+    pub fn snapshot(&self) -> Snapshot<CurveVM> {
+        Snapshot(self.clone())
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot<CurveVM>) {
+        *self = snapshot.0;
+    }
+}
+
+impl CurveStore {
+    pub fn snapshot(&self) -> Snapshot<CurveStore> {
+        Snapshot(self.clone())
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot<CurveStore>) {
+        *self = snapshot.0;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub enum Field {
+    Curve,
+    MaxSupply,
+    Supply,
+    Reserve,
+    Liquidity,
+    MigratedToAmm,
+    MigrateValue,
+    MigrationThreshold,
+    ReserveRatioBps,
+    Fees,
+    ProtocolFees,
+    CreatorFees,
+    Launch,
+    CreatedAt,
+    AmmTokenReserve,
+    AmmSolReserve,
+    AmmLpSupply,
+    AmmFeeBps,
+    CostTable,
+    ComputeBudget,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub enum Value {
+    Int(i128),
+    Bool(bool),
+    Curve(CurveKind),
+    Threshold(Option<MigrationThreshold>),
+    Costs(CostTable),
+    Fees(FeeConfig),
+    Launch(LaunchRules),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct FieldChange {
+    pub field: Field,
+    pub before: Value,
+    pub after: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct AccountChange {
+    pub owner: String,
+    pub before: Option<Account>,
+    pub after: Option<Account>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct VestingChange {
+    pub beneficiary: String,
+    pub before: Option<VestingSchedule>,
+    pub after: Option<VestingSchedule>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct StateDiff {
+    pub fields: Vec<FieldChange>,
+    pub accounts: Vec<AccountChange>,
+    pub vesting: Vec<VestingChange>,
+}
+
+fn entry_changes<T: Copy + PartialEq>(
+    before: &BTreeMap<String, T>,
+    after: &BTreeMap<String, T>,
+) -> Vec<(String, Option<T>, Option<T>)> {
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (a, b) = (before.get(key).copied(), after.get(key).copied());
+            (a != b).then(|| (key.clone(), a, b))
+        })
+        .collect()
+}
+
+// Returns false without touching `next` if `current` does not hold `before`.
+fn apply_entry<T: Copy + PartialEq>(
+    current: &BTreeMap<String, T>,
+    next: &mut BTreeMap<String, T>,
+    key: &str,
+    before: Option<T>,
+    after: Option<T>,
+) -> bool {
+    if current.get(key).copied() != before {
+        return false;
+    }
+    match after {
+        Some(value) => next.insert(key.to_string(), value),
+        None => next.remove(key),
+    };
+    true
+}
+
+fn fields(vm: &CurveVM) -> [(Field, Value); 20] {
+    [
+        (Field::Curve, Value::Curve(vm.curve)),
+        (Field::MaxSupply, Value::Int(vm.max_supply as i128)),
+        (Field::Supply, Value::Int(vm.supply as i128)),
+        (Field::Reserve, Value::Int(vm.reserve as i128)),
+        (Field::Liquidity, Value::Int(vm.liquidity as i128)),
+        (Field::MigratedToAmm, Value::Bool(vm.migrated_to_amm)),
+        (Field::MigrateValue, Value::Int(vm.migrate_value as i128)),
+        (Field::MigrationThreshold, Value::Threshold(vm.migration_threshold)),
+        (Field::ReserveRatioBps, Value::Int(vm.reserve_ratio_bps as i128)),
+        (Field::Fees, Value::Fees(vm.fees.clone())),
+        (Field::ProtocolFees, Value::Int(vm.protocol_fees as i128)),
+        (Field::CreatorFees, Value::Int(vm.creator_fees as i128)),
+        (Field::Launch, Value::Launch(vm.launch)),
+        (Field::CreatedAt, Value::Int(vm.created_at as i128)),
+        (Field::AmmTokenReserve, Value::Int(vm.amm.token_reserve as i128)),
+        (Field::AmmSolReserve, Value::Int(vm.amm.sol_reserve as i128)),
+        (Field::AmmLpSupply, Value::Int(vm.amm.lp_supply as i128)),
+        (Field::AmmFeeBps, Value::Int(vm.amm.fee_bps as i128)),
+        (Field::CostTable, Value::Costs(vm.cost_table)),
+        (Field::ComputeBudget, Value::Int(vm.compute_budget as i128)),
+    ]
+}
+
+fn set(vm: &mut CurveVM, field: Field, value: &Value) -> Option<()> {
+    let int = |v: &Value| match v {
+        Value::Int(i) => Some(*i),
+        _ => None,
+    };
+    match (field, value) {
+        (Field::Curve, Value::Curve(c)) => vm.curve = *c,
+        (Field::MigratedToAmm, Value::Bool(b)) => vm.migrated_to_amm = *b,
+        (Field::MigrationThreshold, Value::Threshold(t)) => vm.migration_threshold = *t,
+        (Field::CostTable, Value::Costs(c)) => vm.cost_table = *c,
+        (Field::Fees, Value::Fees(f)) => vm.fees = f.clone(),
+        (Field::Launch, Value::Launch(l)) => vm.launch = *l,
+        (Field::CreatedAt, v) => vm.created_at = int(v)?.try_into().ok()?,
+        (Field::ProtocolFees, v) => vm.protocol_fees = int(v)?.try_into().ok()?,
+        (Field::CreatorFees, v) => vm.creator_fees = int(v)?.try_into().ok()?,
+        (Field::MaxSupply, v) => vm.max_supply = int(v)?.try_into().ok()?,
+        (Field::Supply, v) => vm.supply = int(v)?.try_into().ok()?,
+        (Field::Reserve, v) => vm.reserve = int(v)?.try_into().ok()?,
+        (Field::Liquidity, v) => vm.liquidity = int(v)?.try_into().ok()?,
+        (Field::MigrateValue, v) => vm.migrate_value = int(v)?.try_into().ok()?,
+        (Field::AmmTokenReserve, v) => vm.amm.token_reserve = int(v)?.try_into().ok()?,
+        (Field::AmmSolReserve, v) => vm.amm.sol_reserve = int(v)?.try_into().ok()?,
+        (Field::AmmLpSupply, v) => vm.amm.lp_supply = int(v)?.try_into().ok()?,
+        (Field::AmmFeeBps, v) => vm.amm.fee_bps = int(v)?.try_into().ok()?,
+        (Field::ReserveRatioBps, v) => vm.reserve_ratio_bps = int(v)?.try_into().ok()?,
+        (Field::ComputeBudget, v) => vm.compute_budget = int(v)?.try_into().ok()?,
+        _ => return None,
+    }
+    Some(())
+}
+
+impl StateDiff {
+    pub fn between(before: &CurveVM, after: &CurveVM) -> Self {
+        let fields = fields(before)
+            .into_iter()
+            .zip(fields(after))
+            .filter(|((_, a), (_, b))| a != b)
+            .map(|((field, before), (_, after))| FieldChange { field, before, after })
+            .collect();
+        let accounts = entry_changes(&before.accounts, &after.accounts)
+            .into_iter()
+            .map(|(owner, before, after)| AccountChange { owner, before, after })
+            .collect();
+        let vesting = entry_changes(&before.vesting, &after.vesting)
+            .into_iter()
+            .map(|(beneficiary, before, after)| VestingChange { beneficiary, before, after })
+            .collect();
+        Self { fields, accounts, vesting }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.accounts.is_empty() && self.vesting.is_empty()
+    }
+
+    // Replays the diff onto `vm`, refusing if any pre-state it records does not match.
+    pub fn apply(&self, vm: &mut CurveVM) -> Result<(), VmError> {
+        let current = fields(vm);
+        let mut next = vm.clone();
+        for change in &self.fields {
+            let (_, value) = current.iter().find(|(f, _)| *f == change.field).expect("every field is listed");
+            if *value != change.before {
+                return Err(VmError::StaleDiff(format!("{:?}", change.field)));
+            }
+            set(&mut next, change.field, &change.after).ok_or_else(|| VmError::StaleDiff(format!("{:?}", change.field)))?;
+        }
+        for change in &self.accounts {
+            if !apply_entry(&vm.accounts, &mut next.accounts, &change.owner, change.before, change.after) {
+                return Err(VmError::StaleDiff(format!("account {}", change.owner)));
+            }
+        }
+        for change in &self.vesting {
+            if !apply_entry(&vm.vesting, &mut next.vesting, &change.beneficiary, change.before, change.after) {
+                return Err(VmError::StaleDiff(format!("vesting {}", change.beneficiary)));
+            }
+        }
+        *vm = next;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct StoreDiff {
+    pub created: Vec<CurveId>,
+    pub curves: Vec<(CurveId, StateDiff)>,
+}
+
+impl StoreDiff {
+    // Curves are never removed, so a curve missing from `before` was created and is
+    // diffed against a fresh `CurveVM`.
+    pub fn between(before: &CurveStore, after: &CurveStore) -> Self {
+        let fresh = CurveVM::new();
+        let mut diff = StoreDiff::default();
+        for (id, vm) in &after.curves {
+            let base = match before.curves.get(id) {
+                Some(vm) => vm,
+                None => {
+                    diff.created.push(*id);
+                    &fresh
+                }
+            };
+            let change = StateDiff::between(base, vm);
+            if !change.is_empty() {
+                diff.curves.push((*id, change));
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.curves.is_empty()
+    }
+
+    pub fn apply(&self, store: &mut CurveStore) -> Result<(), VmError> {
+        let mut next = store.clone();
+        // Created curves were diffed against a fresh VM, so replay starts from one
+        // rather than from a VM stamped with this store's height.
+        for id in &self.created {
+            if next.curves.insert(*id, CurveVM::new()).is_some() {
+                return Err(VmError::DuplicateCurve(*id));
+            }
+        }
+        for (id, change) in &self.curves {
+            let vm = next.curves.get_mut(id).ok_or(VmError::UnknownCurve(*id))?;
+            change.apply(vm)?;
+        }
+        *store = next;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Instruction, Opcode};
+
+    fn ins(opcode: Opcode, operand: i64) -> Instruction {
+        Instruction { opcode, operand, curve: CurveId::default(), limit: 0, expiry: 0 }
+    }
+
+    #[test]
+    fn restore_reverts_to_snapshot() {
+        let mut vm = CurveVM::new();
+        vm.execute("alice", &[ins(Opcode::Buy, 5)]).unwrap();
+        let snapshot = vm.snapshot();
+        let before = vm.clone();
+        vm.execute("bob", &[ins(Opcode::Buy, 7), ins(Opcode::AddLiquidity, 2)]).unwrap();
+        assert_ne!(vm, before);
+        vm.restore(snapshot);
+        assert_eq!(vm, before);
+    }
+
+    #[test]
+    fn diff_lists_changed_fields_and_accounts() {
+        let mut vm = CurveVM::new();
+        vm.execute("alice", &[ins(Opcode::Buy, 5)]).unwrap();
+        let before = vm.clone();
+        vm.execute("bob", &[ins(Opcode::Buy, 2)]).unwrap();
+        let diff = StateDiff::between(&before, &vm);
+        let changed: Vec<Field> = diff.fields.iter().map(|c| c.field).collect();
+        assert_eq!(changed, [Field::Supply, Field::Reserve]);
+        assert_eq!(diff.accounts.len(), 1);
+        assert_eq!(diff.accounts[0].owner, "bob");
+        assert_eq!(diff.accounts[0].before, None);
+        assert!(StateDiff::between(&vm, &vm).is_empty());
+    }
+
+    #[test]
+    fn diff_replays_onto_matching_state_only() {
+        let mut vm = CurveVM::new();
+        vm.execute("alice", &[ins(Opcode::Buy, 50)]).unwrap();
+        let before = vm.clone();
+        vm.execute("alice", &[ins(Opcode::Sell, 10), ins(Opcode::MigrateToAmm, 3)]).unwrap();
+        let diff = StateDiff::between(&before, &vm);
+
+        let mut replay = before.clone();
+        diff.apply(&mut replay).unwrap();
+        assert_eq!(replay, vm);
+
+        let mut stale = before.clone();
+        stale.execute("bob", &[ins(Opcode::Buy, 1)]).unwrap();
+        let untouched = stale.clone();
+        assert!(matches!(diff.apply(&mut stale), Err(VmError::StaleDiff(_))));
+        assert_eq!(stale, untouched);
+    }
+
+    #[test]
+    fn store_diff_covers_created_curves() {
+        let dog = CurveId::derive("alice", "dog");
+        let mut before = CurveStore::new();
+        before.create(CurveId::default(), CurveVM::new()).unwrap();
+        before.clock.height = 7;
+        let mut after = before.clone();
+        let mut launch = CurveVM::new();
+        launch.compute_budget = 50_000;
+        after.create(dog, launch).unwrap();
+        after.execute("carol", &[Instruction { curve: dog, ..ins(Opcode::Buy, 4) }]).unwrap();
+
+        let diff = StoreDiff::between(&before, &after);
+        assert_eq!(diff.created, [dog]);
+        assert_eq!(diff.curves.len(), 1);
+        let mut replay = before.clone();
+        diff.apply(&mut replay).unwrap();
+        assert_eq!(replay.get(&dog).unwrap().created_at, 7);
+        assert_eq!(replay.root(), after.root());
+        assert!(StoreDiff::between(&after, &after).is_empty());
+        assert_eq!(diff.apply(&mut replay), Err(VmError::DuplicateCurve(dog)));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::CurveId;
+use std::fmt;
+
+#[cfg(feature = "vm")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    Overflow,
+    InvalidOperand {
+        operand: i64,
+    },
+    InsufficientBalance {
+        requested: i64,
+        available: i64,
+    },
+    SupplyExhausted {
+        requested: i64,
+        available: i64,
+    },
+    NegativeReserve {
+        requested: i128,
+        available: i64,
+    },
+    InsufficientLiquidity {
+        requested: i64,
+        available: i64,
+    },
+    EmptyPool,
+    DoubleMigration,
+    ComputeBudgetExceeded {
+        budget: u64,
+        required: u64,
+    },
+    UnknownCurve(CurveId),
+    DuplicateCurve(CurveId),
+    StaleDiff(String),
+    InvalidModule(String),
+    WasmTrap(String),
+    InvalidSchedule,
+    NoVesting,
+    EarlyClaim {
+        requested: i64,
+        claimable: i64,
+    },
+    NotMigrated,
+    AllocationAfterMigration,
+    SlippageExceeded {
+        limit: i64,
+        actual: i64,
+    },
+    Expired {
+        expiry: u64,
+        height: u64,
+    },
+    InvalidFeeConfig,
+    InvalidCurve,
+    InvalidLaunchRules,
+    LaunchBuyLimit {
+        bought: i64,
+        limit: i64,
+    },
+    LaunchSellLimit {
+        sold: i64,
+        limit: i64,
+    },
+    LaunchHoldingLimit {
+        holding: i64,
+        limit: i64,
+    },
+    NotFeeRecipient,
+    ReserveRatioBreached {
+        backing: i64,
+        market_cap: i128,
+        ratio_bps: u16,
+    },
+}
+
+#[cfg(feature = "vm")]
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::Overflow => write!(f, "arithmetic overflow"),
+            VmError::InvalidOperand { operand } => write!(f, "invalid operand {}", operand),
+            VmError::InsufficientBalance { requested, available } => {
+                write!(f, "insufficient balance: requested {} but only {} available", requested, available)
+            }
+            VmError::SupplyExhausted { requested, available } => {
+                write!(f, "supply exhausted: requested {} but only {} left on the curve", requested, available)
+            }
+            VmError::NegativeReserve { requested, available } => {
+                write!(f, "reserve would go negative: paying out {} from {}", requested, available)
+            }
+            VmError::InsufficientLiquidity { requested, available } => {
+                write!(f, "insufficient pool liquidity: requested {} but only {} available", requested, available)
+            }
+            VmError::EmptyPool => write!(f, "cannot migrate into an empty pool"),
+            VmError::DoubleMigration => write!(f, "curve has already migrated"),
+            VmError::ComputeBudgetExceeded { budget, required } => {
+                write!(f, "compute budget exceeded: {} units required, budget is {}", required, budget)
+            }
+            VmError::UnknownCurve(id) => write!(f, "unknown curve {}", id),
+            VmError::DuplicateCurve(id) => write!(f, "curve {} already exists", id),
+            VmError::StaleDiff(what) => write!(f, "diff does not match current {}", what),
+            VmError::InvalidModule(reason) => write!(f, "invalid wasm module: {}", reason),
+            VmError::WasmTrap(reason) => write!(f, "wasm execution trapped: {}", reason),
+            VmError::InvalidSchedule => write!(f, "invalid vesting schedule"),
+            VmError::NoVesting => write!(f, "no vesting schedule for this account"),
+            VmError::EarlyClaim { requested, claimable } => {
+                write!(f, "claim too early: requested {} but only {} has vested", requested, claimable)
+            }
+            VmError::NotMigrated => write!(f, "curve has not migrated to the pool yet"),
+            VmError::AllocationAfterMigration => write!(f, "allocations close when the curve migrates"),
+            VmError::SlippageExceeded { limit, actual } => {
+                write!(f, "slippage limit exceeded: trade settles at {} against a limit of {}", actual, limit)
+            }
+            VmError::Expired { expiry, height } => {
+                write!(f, "instruction expired at height {} and the block is at {}", expiry, height)
+            }
+            VmError::InvalidFeeConfig => write!(f, "fee shares must add up to 10000 bps and name who is paid"),
+            VmError::InvalidCurve => write!(f, "invalid curve parameters"),
+            VmError::InvalidLaunchRules => write!(f, "invalid launch rules"),
+            VmError::LaunchBuyLimit { bought, limit } => {
+                write!(f, "launch buy limit exceeded: wallet would buy {} of at most {}", bought, limit)
+            }
+            VmError::LaunchSellLimit { sold, limit } => {
+                write!(f, "launch sell limit exceeded: wallet would sell {} of at most {}", sold, limit)
+            }
+            VmError::LaunchHoldingLimit { holding, limit } => {
+                write!(f, "launch holding limit exceeded: wallet would hold {} of at most {}", holding, limit)
+            }
+            VmError::NotFeeRecipient => write!(f, "sender does not receive fees on this curve"),
+            VmError::ReserveRatioBreached { backing, market_cap, ratio_bps } => write!(
+                f,
+                "reserve ratio breached: {} backing a market cap of {} is below {} bps",
+                backing, market_cap, ratio_bps
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "vm")]
+impl std::error::Error for VmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    HashMismatch,
+    UnknownOpcode(u8),
+    UnknownCurveIndex(u64),
+    DuplicateCurve(CurveId),
+    UnusedCurve,
+    NonCanonical,
+    VarintOverflow,
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "bytecode is truncated"),
+            DecodeError::BadMagic => write!(f, "not curvevm bytecode"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported bytecode version {}", v),
+            DecodeError::HashMismatch => write!(f, "code hash does not match the program"),
+            DecodeError::UnknownOpcode(op) => write!(f, "unknown opcode 0x{:02x}", op),
+            DecodeError::UnknownCurveIndex(i) => write!(f, "curve index {} is out of range", i),
+            DecodeError::DuplicateCurve(id) => write!(f, "curve {} is listed twice", id),
+            DecodeError::UnusedCurve => write!(f, "curve table lists a curve no instruction uses"),
+            DecodeError::NonCanonical => write!(f, "integer is not minimally encoded"),
+            DecodeError::VarintOverflow => write!(f, "integer does not fit in 64 bits"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} unexpected bytes after the program", n),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
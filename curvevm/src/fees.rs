@@ -0,0 +1,198 @@
+use crate::{CurveVM, ExecutionReceipt, VmError, math};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+const BPS: u16 = 10_000;
+
+// `fee_bps` is charged on the SOL side of every trade. The shares split it and
+// must add up to 10_000; before migration there are no LPs, so their share is
+// divided between the protocol and the creator instead. Every share that can
+// be paid needs a named recipient, or an empty sender could claim it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct FeeConfig {
+    pub fee_bps: u16,
+    pub protocol_share_bps: u16,
+    pub creator_share_bps: u16,
+    pub lp_share_bps: u16,
+    pub treasury: String,
+    pub creator: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeSplit {
+    pub protocol: i64,
+    pub creator: i64,
+    pub lp: i64,
+}
+
+impl FeeConfig {
+    pub fn is_valid(&self) -> bool {
+        self.shares_add_up()
+            && (!self.needs_treasury() || !self.treasury.is_empty())
+            && (!self.needs_creator() || !self.creator.is_empty())
+    }
+
+    pub fn shares_add_up(&self) -> bool {
+        let shares = self.protocol_share_bps as u32 + self.creator_share_bps as u32 + self.lp_share_bps as u32;
+        self.fee_bps <= BPS && (shares == BPS as u32 || self.fee_bps == 0)
+    }
+
+    pub fn needs_treasury(&self) -> bool {
+        self.fee_bps > 0 && self.protocol_share_bps > 0
+    }
+
+    // With no owner shares the whole pre-migration fee falls to the creator.
+    pub fn needs_creator(&self) -> bool {
+        self.fee_bps > 0 && (self.creator_share_bps > 0 || self.protocol_share_bps == 0)
+    }
+
+    // Rounded up so the fee never undercharges.
+    pub fn fee_on(&self, sol: i64) -> Option<i64> {
+        math::mul_div_ceil(sol as i128, self.fee_bps as i128, BPS as i128).and_then(|fee| i64::try_from(fee).ok())
+    }
+
+    pub fn split(&self, fee: i64, migrated: bool) -> FeeSplit {
+        let lp = if migrated {
+            math::mul_div(fee as i128, self.lp_share_bps as i128, BPS as i128).expect("share is at most the fee") as i64
+        } else {
+            0
+        };
+        let owners = self.protocol_share_bps as i128 + self.creator_share_bps as i128;
+        let protocol = match owners {
+            0 => 0,
+            owners => math::mul_div((fee - lp) as i128, self.protocol_share_bps as i128, owners)
+                .expect("share is at most the fee") as i64,
+        };
+        FeeSplit { protocol, creator: fee - lp - protocol, lp }
+    }
+}
+
+impl CurveVM {
+    pub fn set_fees(&mut self, fees: FeeConfig) -> Result<(), VmError> {
+        if !fees.is_valid() {
+            return Err(VmError::InvalidFeeConfig);
+        }
+        self.fees = fees;
+        Ok(())
+    }
+
+    // Charges the fee on a trade of `sol` and accrues it. The LP share stays in
+    // the pool, where burning LP shares pays it out.
+    pub(crate) fn charge_fee(&mut self, sol: i64, receipt: &mut ExecutionReceipt) -> Result<i64, VmError> {
+        let fee = self.fees.fee_on(sol).ok_or(VmError::Overflow)?;
+        let split = self.fees.split(fee, self.migrated_to_amm);
+        self.protocol_fees = self.protocol_fees.checked_add(split.protocol).ok_or(VmError::Overflow)?;
+        self.creator_fees = self.creator_fees.checked_add(split.creator).ok_or(VmError::Overflow)?;
+        self.amm.sol_reserve = self.amm.sol_reserve.checked_add(split.lp).ok_or(VmError::Overflow)?;
+        receipt.fees_paid = receipt.fees_paid.checked_add(fee).ok_or(VmError::Overflow)?;
+        Ok(fee)
+    }
+
+    // Pays out accrued fees to the treasury or the creator, whichever the sender
+    // is. A sender that is both draws from the protocol bucket first.
+    pub(crate) fn claim_fees(&mut self, amount: i64, receipt: &mut ExecutionReceipt) -> Result<(), VmError> {
+        let sender = receipt.sender.as_str();
+        let is_treasury = sender == self.fees.treasury;
+        let is_creator = sender == self.fees.creator;
+        if !is_treasury && !is_creator {
+            return Err(VmError::NotFeeRecipient);
+        }
+        let from_protocol = if is_treasury { amount.min(self.protocol_fees) } else { 0 };
+        let from_creator = amount - from_protocol;
+        let available = if is_creator { self.creator_fees } else { 0 };
+        if from_creator > available {
+            return Err(VmError::InsufficientBalance { requested: amount, available: from_protocol + available });
+        }
+        self.protocol_fees -= from_protocol;
+        self.creator_fees -= from_creator;
+        let account = self.accounts.entry(receipt.sender.clone()).or_default();
+        account.received = account.received.checked_add(amount).ok_or(VmError::Overflow)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConstantProduct, CurveId, CurveKind, Event, Instruction, Opcode};
+
+    fn ins(opcode: Opcode, operand: i64) -> Instruction {
+        Instruction { opcode, operand, curve: CurveId::default(), limit: 0, expiry: 0 }
+    }
+
+    fn config() -> FeeConfig {
+        FeeConfig {
+            fee_bps: 100,
+            protocol_share_bps: 2_000,
+            creator_share_bps: 3_000,
+            lp_share_bps: 5_000,
+            treasury: "treasury".into(),
+            creator: "creator".into(),
+        }
+    }
+
+    #[test]
+    fn splits_follow_migration_state() {
+        let fees = config();
+        assert_eq!(fees.fee_on(1_001), Some(11));
+        assert_eq!(fees.split(1_000, false), FeeSplit { protocol: 400, creator: 600, lp: 0 });
+        assert_eq!(fees.split(1_000, true), FeeSplit { protocol: 200, creator: 300, lp: 500 });
+        assert_eq!(fees.split(7, true), FeeSplit { protocol: 1, creator: 3, lp: 3 });
+        assert!(!FeeConfig { lp_share_bps: 4_000, ..config() }.is_valid());
+        assert!(!FeeConfig { fee_bps: 10_001, ..config() }.is_valid());
+        assert!(FeeConfig::default().is_valid());
+        assert!(!FeeConfig { treasury: String::new(), ..config() }.is_valid());
+        assert!(!FeeConfig { creator: String::new(), ..config() }.is_valid());
+        let lp_only = FeeConfig { protocol_share_bps: 0, creator_share_bps: 0, lp_share_bps: 10_000, ..config() };
+        assert!(!FeeConfig { creator: String::new(), ..lp_only.clone() }.is_valid());
+        assert!(FeeConfig { treasury: String::new(), ..lp_only }.is_valid());
+    }
+
+    #[test]
+    fn trades_accrue_fees_into_state() {
+        let curve = CurveKind::ConstantProduct(ConstantProduct { virtual_reserve: 30_000, virtual_supply: 1_000_000 });
+        let mut vm = CurveVM::with_curve(curve, 800_000).unwrap();
+        assert_eq!(vm.set_fees(FeeConfig { protocol_share_bps: 1, ..config() }), Err(VmError::InvalidFeeConfig));
+        vm.set_fees(config()).unwrap();
+        let receipt = vm.execute("alice", &[ins(Opcode::Buy, 600_000)]).unwrap();
+        let Event::Trade { sol, fee, .. } = receipt.events[0] else { panic!("{:?}", receipt.events) };
+        assert_eq!(fee, (sol + 99) / 100);
+        assert_eq!(receipt.fees_paid, fee);
+        assert_eq!(vm.account("alice").spent, sol + fee);
+        assert_eq!(vm.protocol_fees + vm.creator_fees, fee);
+        assert_eq!(vm.reserve, sol);
+
+        // Accrued fees are committed by the state root.
+        let mut store = crate::CurveStore::new();
+        store.create(CurveId::default(), vm.clone()).unwrap();
+        let root = store.root();
+        store.curves.get_mut(&CurveId::default()).unwrap().creator_fees -= 1;
+        assert_ne!(store.root(), root);
+
+        vm.execute("alice", &[ins(Opcode::MigrateToAmm, 0)]).unwrap();
+        let pool = vm.amm;
+        let receipt = vm.execute("alice", &[ins(Opcode::Sell, 1_000)]).unwrap();
+        let Event::Trade { sol, fee, via_amm: true, .. } = receipt.events[0] else { panic!("{:?}", receipt.events) };
+        let lp = config().split(fee, true).lp;
+        assert_eq!(vm.amm.sol_reserve, pool.sol_reserve - sol + lp);
+        assert_eq!(receipt.reserve_out, sol);
+        assert_eq!(vm.account("alice").received, sol - fee);
+    }
+
+    #[test]
+    fn only_recipients_claim_their_bucket() {
+        let mut vm = CurveVM::new();
+        vm.set_fees(config()).unwrap();
+        vm.execute("alice", &[ins(Opcode::Buy, 2_000)]).unwrap();
+        let (protocol, creator) = (vm.protocol_fees, vm.creator_fees);
+        assert!(protocol > 0 && creator > 0);
+
+        assert_eq!(vm.execute("alice", &[ins(Opcode::ClaimFees, 1)]), Err(VmError::NotFeeRecipient));
+        let err = vm.execute("creator", &[ins(Opcode::ClaimFees, creator + 1)]).unwrap_err();
+        assert_eq!(err, VmError::InsufficientBalance { requested: creator + 1, available: creator });
+        vm.execute("creator", &[ins(Opcode::ClaimFees, creator)]).unwrap();
+        vm.execute("treasury", &[ins(Opcode::ClaimFees, protocol)]).unwrap();
+        assert_eq!((vm.protocol_fees, vm.creator_fees), (0, 0));
+        assert_eq!(vm.account("treasury").received, protocol);
+        assert_eq!(vm.account("creator").received, creator);
+    }
+}
@@ -0,0 +1,321 @@
+// Property harness for CurveVM. Strategies build randomly configured curves and
+// instruction sequences; every executed program is checked against the
+// invariants in `INVARIANTS`. A feature that adds state or rules registers its
+// own checks there and gets exercised by every scenario.
+use crate::{
+    Call, Clock, ConstantProduct, CurveId, CurveKind, CurveStore, CurveVM, Event, ExecutionReceipt, Exponential,
+    FeeConfig, Instruction, LaunchRules, Lbp, Linear, MigrationThreshold, Opcode, Side, Sigmoid, VestingSchedule,
+    VmError,
+};
+use proptest::prelude::*;
+
+pub(crate) const SENDERS: [&str; 4] = ["alice", "bob", "treasury", "creator"];
+
+pub(crate) struct Step<'a> {
+    pub before: &'a CurveVM,
+    pub after: &'a CurveVM,
+    pub sender: &'a str,
+    pub program: &'a [Instruction],
+    pub clock: Clock,
+    pub result: &'a Result<ExecutionReceipt, VmError>,
+}
+
+pub(crate) struct Invariant {
+    pub name: &'static str,
+    pub check: fn(&Step) -> Result<(), String>,
+}
+
+pub(crate) const INVARIANTS: &[Invariant] = &[
+    Invariant { name: "failed programs leave no trace", check: atomic },
+    Invariant { name: "execution is deterministic", check: deterministic },
+    Invariant { name: "balances stay non-negative", check: non_negative },
+    Invariant { name: "tokens are conserved", check: tokens_conserved },
+    Invariant { name: "sol is conserved", check: sol_conserved },
+    Invariant { name: "price follows the trade", check: monotonic_price },
+    Invariant { name: "reserve ratio survives guarded instructions", check: reserve_ratio },
+    Invariant { name: "trades settle within their limits", check: slippage_bounds },
+    Invariant { name: "expired instructions never run", check: expiries },
+    Invariant { name: "launch limits hold", check: launch_limits },
+];
+
+fn ensure(ok: bool, message: impl FnOnce() -> String) -> Result<(), String> {
+    if ok { Ok(()) } else { Err(message()) }
+}
+
+fn atomic(step: &Step) -> Result<(), String> {
+    ensure(step.result.is_ok() || step.after == step.before, || "state changed on error".into())
+}
+
+fn deterministic(step: &Step) -> Result<(), String> {
+    let mut replay = step.before.clone();
+    let result = replay.execute_at(step.sender, step.program, step.clock);
+    ensure(&result == step.result, || format!("replay returned {:?}", result))?;
+    ensure(replay == *step.after, || "replay reached a different state".into())
+}
+
+fn non_negative(step: &Step) -> Result<(), String> {
+    let vm = step.after;
+    let pool = [vm.reserve, vm.liquidity, vm.amm.token_reserve, vm.amm.sol_reserve, vm.amm.lp_supply];
+    ensure(pool.iter().all(|v| *v >= 0), || format!("negative pool {:?}", pool))?;
+    ensure(vm.protocol_fees >= 0 && vm.creator_fees >= 0, || "negative fee balance".into())?;
+    let shares: i128 = vm.accounts.values().map(|a| a.lp_shares as i128).sum();
+    ensure(shares <= vm.amm.lp_supply as i128, || format!("{} lp shares held of {}", shares, vm.amm.lp_supply))?;
+    match vm.accounts.iter().find(|(_, a)| a.tokens < 0 || a.lp_shares < 0) {
+        Some((owner, account)) => Err(format!("{} holds {:?}", owner, account)),
+        None => Ok(()),
+    }
+}
+
+// Circulating tokens live in accounts; the rest is unminted, still vesting or,
+// after migration, in the pool.
+fn tokens_conserved(step: &Step) -> Result<(), String> {
+    let vm = step.after;
+    let held: i128 = vm.accounts.values().map(|a| a.tokens as i128).sum();
+    ensure(held == vm.supply as i128, || format!("accounts hold {} of supply {}", held, vm.supply))?;
+    let pooled = if vm.migrated_to_amm { vm.amm.token_reserve } else { 0 };
+    let accounted = vm.supply as i128 + vm.locked_allocation() as i128 + pooled as i128;
+    if vm.migrated_to_amm {
+        ensure(accounted == vm.max_supply as i128, || format!("{} tokens accounted of {}", accounted, vm.max_supply))
+    } else {
+        ensure(accounted <= vm.max_supply as i128, || format!("{} tokens minted of {}", accounted, vm.max_supply))
+    }
+}
+
+// Every lamport the VM holds was paid in by an account and not yet paid out.
+fn sol_conserved(step: &Step) -> Result<(), String> {
+    let vm = step.after;
+    let net: i128 = vm.accounts.values().map(|a| a.spent as i128 - a.received as i128).sum();
+    let held: i128 = [vm.reserve, vm.liquidity, vm.amm.sol_reserve, vm.protocol_fees, vm.creator_fees]
+        .iter()
+        .map(|v| *v as i128)
+        .sum();
+    ensure(net == held, || format!("accounts paid in {} but the curve holds {}", net, held))
+}
+
+fn monotonic_price(step: &Step) -> Result<(), String> {
+    let Ok(receipt) = step.result else {
+        return Ok(());
+    };
+    for t in receipt.trace.iter().filter(|t| t.pre.migrated_to_amm == t.post.migrated_to_amm) {
+        let ok = match t.instruction.opcode {
+            Opcode::Buy => t.post.price >= t.pre.price,
+            Opcode::Sell => t.post.price <= t.pre.price,
+            _ => true,
+        };
+        ensure(ok, || format!("{:?} moved the price from {} to {}", t.instruction, t.pre.price, t.post.price))?;
+    }
+    Ok(())
+}
+
+fn reserve_ratio(step: &Step) -> Result<(), String> {
+    let guarded = step.program.iter().any(|ins| matches!(ins.opcode, Opcode::Sell | Opcode::Claim | Opcode::RemoveLiquidity));
+    let ok = step.result.is_err() || !guarded || step.after.reserve_ratio_holds();
+    ensure(ok, || format!("backing {} below {} bps", step.after.backing(), step.after.reserve_ratio_bps))
+}
+
+fn slippage_bounds(step: &Step) -> Result<(), String> {
+    let Ok(receipt) = step.result else {
+        return Ok(());
+    };
+    let trades = step.program.iter().filter(|ins| matches!(ins.opcode, Opcode::Buy | Opcode::Sell));
+    let events = receipt.events.iter().filter_map(|e| match e {
+        Event::Trade { side, sol, fee, .. } => Some((*side, *sol, *fee)),
+        _ => None,
+    });
+    for (ins, (side, sol, fee)) in trades.zip(events) {
+        let ok = match side {
+            Side::Buy => ins.limit == 0 || sol + fee <= ins.limit,
+            Side::Sell => ins.limit == 0 || sol - fee >= ins.limit,
+        };
+        ensure(ok, || format!("{:?} settled at {} with fee {}", ins, sol, fee))?;
+    }
+    Ok(())
+}
+
+fn expiries(step: &Step) -> Result<(), String> {
+    let expired = step.program.iter().any(|ins| ins.expiry != 0 && ins.expiry < step.clock.height);
+    ensure(step.result.is_err() || !expired, || format!("ran at height {}", step.clock.height))
+}
+
+fn launch_limits(step: &Step) -> Result<(), String> {
+    let vm = step.after;
+    if step.result.is_err() || !vm.in_launch_window(step.clock.height) {
+        return Ok(());
+    }
+    let rules = vm.launch;
+    for (owner, a) in &vm.accounts {
+        let ok = (rules.max_buy == 0 || a.launch_bought <= rules.max_buy)
+            && (rules.max_sell == 0 || a.launch_sold <= rules.max_sell);
+        ensure(ok, || format!("{} traded {:?} under {:?}", owner, a, rules))?;
+    }
+    // Only the sender's balance can grow, and every instruction that grows it is capped.
+    let holding = vm.balance_of(step.sender);
+    let grew = holding > step.before.balance_of(step.sender);
+    ensure(!grew || vm.max_holding().is_none_or(|cap| holding <= cap), || {
+        format!("{} holds {} under {:?}", step.sender, holding, rules)
+    })
+}
+
+pub(crate) fn any_curve() -> impl Strategy<Value = CurveKind> {
+    prop_oneof![
+        (1..1_000i64, 1..100i64, 1..100i64).prop_map(|(base_price, slope_num, slope_den)| {
+            CurveKind::Linear(Linear { base_price, slope_num, slope_den })
+        }),
+        (1_000..1_000_000i64, 2_000_000..100_000_000i64).prop_map(|(virtual_reserve, virtual_supply)| {
+            CurveKind::ConstantProduct(ConstantProduct { virtual_reserve, virtual_supply })
+        }),
+        (1..1_000i64, 100_000..10_000_000i64)
+            .prop_map(|(base_price, scale)| CurveKind::Exponential(Exponential { base_price, scale })),
+        (1..10_000i64, 0..1_000_000i64, 1..100_000i64).prop_map(|(max_price, midpoint, width)| {
+            CurveKind::Sigmoid(Sigmoid { max_price, midpoint, width })
+        }),
+        (100_000..1_000_000i64, 2_000_000..50_000_000i64, 5_000..9_500u16, 2_000..9_500u16, 0..40u64).prop_map(
+            |(virtual_reserve, token_balance, start_weight_bps, end_weight_bps, duration)| {
+                CurveKind::Lbp(Lbp {
+                    virtual_reserve,
+                    token_balance,
+                    start_weight_bps,
+                    end_weight_bps,
+                    duration,
+                    elapsed: 0,
+                })
+            }
+        ),
+    ]
+}
+
+fn any_fees() -> impl Strategy<Value = FeeConfig> {
+    (0..500u16, 0..=10_000u16, 0..=10_000u16).prop_map(|(fee_bps, a, b)| {
+        let (low, high) = (a.min(b), a.max(b));
+        FeeConfig {
+            fee_bps,
+            protocol_share_bps: low,
+            creator_share_bps: high - low,
+            lp_share_bps: 10_000 - high,
+            treasury: "treasury".into(),
+            creator: "creator".into(),
+        }
+    })
+}
+
+fn any_launch() -> impl Strategy<Value = LaunchRules> {
+    (0..4u64, 0..60_000i64, 0..20_000i64, 0..1_000u16)
+        .prop_map(|(blocks, max_buy, max_sell, max_holding_bps)| LaunchRules { blocks, max_buy, max_sell, max_holding_bps })
+}
+
+// A curve with every optional feature switched on at random.
+pub(crate) fn any_vm() -> impl Strategy<Value = CurveVM> {
+    (
+        any_curve(),
+        any_fees(),
+        any_launch(),
+        prop::option::of(10_000..2_000_000i64),
+        prop_oneof![Just(0u16), 1..3_000u16],
+        prop_oneof![Just(0i64), 1..200_000i64],
+    )
+        .prop_map(|(curve, fees, launch, threshold, ratio_bps, vested)| {
+            let mut vm = CurveVM::with_curve(curve, 1_000_000).unwrap();
+            vm.set_fees(fees).unwrap();
+            vm.set_launch_rules(launch).unwrap();
+            vm.migration_threshold = threshold.map(MigrationThreshold::Reserve);
+            vm.reserve_ratio_bps = ratio_bps;
+            if vested > 0 {
+                vm.add_vesting("creator", VestingSchedule::new(vested, 0, 10, 100)).unwrap();
+            }
+            vm
+        })
+}
+
+pub(crate) fn any_instruction() -> impl Strategy<Value = Instruction> {
+    let opcode = prop_oneof![
+        4 => Just(Opcode::Buy),
+        3 => Just(Opcode::Sell),
+        1 => Just(Opcode::AddLiquidity),
+        1 => Just(Opcode::RemoveLiquidity),
+        1 => Just(Opcode::MigrateToAmm),
+        1 => Just(Opcode::Claim),
+        1 => Just(Opcode::ClaimFees),
+    ];
+    let operand = prop_oneof![24 => 1..20_000i64, 1 => Just(0i64), 1 => Just(i64::MAX), 1 => -3..0i64];
+    let limit = prop_oneof![16 => Just(0i64), 3 => 1..2_000_000i64, 1 => Just(-1i64)];
+    let expiry = prop_oneof![8 => Just(0u64), 1 => 1..40u64];
+    (opcode, operand, limit, expiry).prop_map(|(opcode, operand, limit, expiry)| Instruction {
+        opcode,
+        operand,
+        curve: CurveId::default(),
+        limit,
+        expiry,
+    })
+}
+
+// Sender index, program, and seconds since the previous block.
+pub(crate) fn any_steps() -> impl Strategy<Value = Vec<(usize, Vec<Instruction>, u64)>> {
+    prop::collection::vec((0..SENDERS.len(), prop::collection::vec(any_instruction(), 1..4), 0..20u64), 1..40)
+}
+
+pub(crate) fn run_scenario(mut vm: CurveVM, steps: &[(usize, Vec<Instruction>, u64)]) -> Result<(), TestCaseError> {
+    let mut clock = Clock::default();
+    for (sender, program, elapsed) in steps {
+        clock = Clock { height: clock.height + 1, timestamp: clock.timestamp + elapsed };
+        let before = vm.clone();
+        let result = vm.execute_at(SENDERS[*sender], program, clock);
+        let step = Step { before: &before, after: &vm, sender: SENDERS[*sender], program, clock, result: &result };
+        for invariant in INVARIANTS {
+            (invariant.check)(&step)
+                .map_err(|err| TestCaseError::fail(format!("{}: {} after {:?}", invariant.name, err, program)))?;
+        }
+    }
+    Ok(())
+}
+
+mod tests {
+    use super::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(128))]
+
+        #[test]
+        fn random_programs_keep_every_invariant(vm in any_vm(), steps in any_steps()) {
+            run_scenario(vm, &steps)?;
+        }
+
+        #[test]
+        fn stores_agree_across_execution_engines(
+            vms in prop::collection::vec(any_vm(), 1..4),
+            calls in prop::collection::vec((0..SENDERS.len(), prop::collection::vec((any_instruction(), 0..4usize), 1..4)), 1..24),
+        ) {
+            let ids: Vec<CurveId> = (0..vms.len()).map(|i| CurveId::derive("creator", &i.to_string())).collect();
+            let mut sequential = CurveStore::new();
+            for (id, vm) in ids.iter().zip(vms) {
+                sequential.create(*id, vm).unwrap();
+            }
+            sequential.clock = Clock { height: 1, timestamp: 50 };
+            // Index 3 names a curve that does not exist.
+            let calls: Vec<Call> = calls
+                .into_iter()
+                .map(|(sender, program)| {
+                    let program = program
+                        .into_iter()
+                        .map(|(ins, curve)| Instruction { curve: ids.get(curve).copied().unwrap_or(CurveId([9; 32])), ..ins })
+                        .collect();
+                    Call::new(SENDERS[sender], program)
+                })
+                .collect();
+            let mut parallel = sequential.clone();
+            prop_assert_eq!(sequential.execute_block(&calls), parallel.execute_block_parallel(&calls));
+            prop_assert_eq!(sequential.root(), parallel.root());
+            prop_assert_eq!(sequential, parallel);
+        }
+    }
+
+    #[test]
+    fn broken_invariants_are_reported() {
+        let vm = CurveVM::new();
+        let mut after = vm.clone();
+        after.reserve = 5;
+        let result = Ok(ExecutionReceipt::default());
+        let step = Step { before: &vm, after: &after, sender: "alice", program: &[], clock: Clock::default(), result: &result };
+        let failed: Vec<&str> = INVARIANTS.iter().filter(|i| (i.check)(&step).is_err()).map(|i| i.name).collect();
+        assert_eq!(failed, ["execution is deterministic", "sol is conserved"]);
+    }
+}
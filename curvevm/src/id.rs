@@ -0,0 +1,28 @@
+#[cfg(feature = "vm")]
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+pub struct CurveId(pub [u8; 32]);
+
+impl CurveId {
+    pub fn derive(creator: &str, seed: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"curve:");
+        hasher.update(creator.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(seed.as_bytes());
+        Self(hasher.finalize().into())
+    }
+}
+
+impl fmt::Display for CurveId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{CurveVM, Side, VmError, math};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+// Anti-snipe rules for the first `blocks` blocks after a curve is created. Each
+// wallet may buy at most `max_buy` and sell at most `max_sell` tokens in total
+// over the window, and may not end a trade holding more than `max_holding_bps`
+// of the max supply. Zero turns a limit off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct LaunchRules {
+    pub blocks: u64,
+    pub max_buy: i64,
+    pub max_sell: i64,
+    pub max_holding_bps: u16,
+}
+
+impl LaunchRules {
+    pub fn is_valid(&self) -> bool {
+        self.max_buy >= 0 && self.max_sell >= 0 && self.max_holding_bps <= 10_000
+    }
+}
+
+impl CurveVM {
+    pub fn set_launch_rules(&mut self, rules: LaunchRules) -> Result<(), VmError> {
+        if !rules.is_valid() {
+            return Err(VmError::InvalidLaunchRules);
+        }
+        self.launch = rules;
+        Ok(())
+    }
+
+    // Heights run from `created_at` through `created_at + blocks`, so a curve
+    // created between blocks is covered for the next `blocks` of them. Heights
+    // before the curve existed are outside it.
+    pub fn in_launch_window(&self, height: u64) -> bool {
+        self.launch.blocks > 0 && height >= self.created_at && height - self.created_at <= self.launch.blocks
+    }
+
+    pub fn max_holding(&self) -> Option<i64> {
+        match self.launch.max_holding_bps {
+            0 => None,
+            bps => math::mul_div(self.max_supply as i128, bps as i128, 10_000).map(|cap| cap as i64),
+        }
+    }
+
+    // Runs after the trade has settled, so a breach unwinds with the program.
+    pub(crate) fn enforce_launch(&mut self, side: Side, amount: i64, sender: &str, height: u64) -> Result<(), VmError> {
+        if !self.in_launch_window(height) {
+            return Ok(());
+        }
+        let rules = self.launch;
+        let account = self.accounts.entry(sender.to_string()).or_default();
+        match side {
+            Side::Buy => {
+                account.launch_bought = account.launch_bought.checked_add(amount).ok_or(VmError::Overflow)?;
+                if rules.max_buy != 0 && account.launch_bought > rules.max_buy {
+                    return Err(VmError::LaunchBuyLimit { bought: account.launch_bought, limit: rules.max_buy });
+                }
+            }
+            Side::Sell => {
+                account.launch_sold = account.launch_sold.checked_add(amount).ok_or(VmError::Overflow)?;
+                if rules.max_sell != 0 && account.launch_sold > rules.max_sell {
+                    return Err(VmError::LaunchSellLimit { sold: account.launch_sold, limit: rules.max_sell });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Checked after every instruction that can raise a balance: buys, vesting
+    // claims and withdrawals from the pool.
+    pub(crate) fn enforce_holding(&self, sender: &str, height: u64) -> Result<(), VmError> {
+        if !self.in_launch_window(height) {
+            return Ok(());
+        }
+        let holding = self.balance_of(sender);
+        match self.max_holding() {
+            Some(limit) if holding > limit => Err(VmError::LaunchHoldingLimit { holding, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, CurveId, CurveStore, Instruction, Opcode, VestingSchedule};
+
+    fn ins(opcode: Opcode, operand: i64) -> Instruction {
+        Instruction { opcode, operand, curve: CurveId::default(), limit: 0, expiry: 0 }
+    }
+
+    fn at(height: u64) -> Clock {
+        Clock { height, timestamp: 0 }
+    }
+
+    fn launch() -> CurveVM {
+        let mut vm = CurveVM::new();
+        vm.max_supply = 1_000_000;
+        vm.set_launch_rules(LaunchRules { blocks: 3, max_buy: 20_000, max_sell: 5_000, max_holding_bps: 300 }).unwrap();
+        vm
+    }
+
+    #[test]
+    fn sniper_cannot_vacuum_supply_during_launch() {
+        let mut vm = launch();
+        let err = vm.execute_at("sniper", &[ins(Opcode::Buy, 800_000)], at(0)).unwrap_err();
+        assert_eq!(err, VmError::LaunchBuyLimit { bought: 800_000, limit: 20_000 });
+
+        // Splitting the order across instructions and blocks hits the same cap.
+        vm.execute_at("sniper", &[ins(Opcode::Buy, 10_000), ins(Opcode::Buy, 10_000)], at(1)).unwrap();
+        let before = vm.clone();
+        let err = vm.execute_at("sniper", &[ins(Opcode::Buy, 1)], at(2)).unwrap_err();
+        assert_eq!(err, VmError::LaunchBuyLimit { bought: 20_001, limit: 20_000 });
+        assert_eq!(vm, before);
+
+        // Dumping on the launch crowd is capped too.
+        vm.execute_at("sniper", &[ins(Opcode::Sell, 5_000)], at(2)).unwrap();
+        let err = vm.execute_at("sniper", &[ins(Opcode::Sell, 1)], at(3)).unwrap_err();
+        assert_eq!(err, VmError::LaunchSellLimit { sold: 5_001, limit: 5_000 });
+
+        // Fair buyers are unaffected, and the window closes after three blocks.
+        vm.execute_at("alice", &[ins(Opcode::Buy, 500)], at(3)).unwrap();
+        vm.execute_at("sniper", &[ins(Opcode::Buy, 700_000), ins(Opcode::Sell, 100_000)], at(4)).unwrap();
+        assert_eq!(vm.balance_of("sniper"), 615_000);
+    }
+
+    #[test]
+    fn holding_cap_counts_every_trade() {
+        let mut vm = launch();
+        vm.launch.max_buy = 0;
+        assert_eq!(vm.max_holding(), Some(30_000));
+        let err = vm.execute_at("sniper", &[ins(Opcode::Buy, 30_001)], at(1)).unwrap_err();
+        assert_eq!(err, VmError::LaunchHoldingLimit { holding: 30_001, limit: 30_000 });
+        vm.execute_at("sniper", &[ins(Opcode::Buy, 30_000)], at(1)).unwrap();
+        let err = vm.execute_at("sniper", &[ins(Opcode::Sell, 1), ins(Opcode::Buy, 2)], at(1)).unwrap_err();
+        assert_eq!(err, VmError::LaunchHoldingLimit { holding: 30_001, limit: 30_000 });
+        let rules = LaunchRules { max_holding_bps: 10_001, ..vm.launch };
+        assert_eq!(vm.set_launch_rules(rules), Err(VmError::InvalidLaunchRules));
+    }
+
+    #[test]
+    fn holding_cap_covers_claims_and_withdrawals() {
+        let mut vm = launch();
+        vm.add_vesting("creator", VestingSchedule::new(50_000, 0, 0, 1)).unwrap();
+        vm.execute_at("alice", &[ins(Opcode::Buy, 10)], at(1)).unwrap();
+        vm.execute_at("whale", &[ins(Opcode::AddLiquidity, 1_000_000), ins(Opcode::MigrateToAmm, 0)], at(1)).unwrap();
+
+        let claim = ins(Opcode::Claim, 40_000);
+        let err = vm.execute_at("creator", &[claim], Clock { height: 2, timestamp: 1 }).unwrap_err();
+        assert_eq!(err, VmError::LaunchHoldingLimit { holding: 40_000, limit: 30_000 });
+        let shares = vm.account("whale").lp_shares;
+        let err = vm.execute_at("whale", &[ins(Opcode::RemoveLiquidity, shares)], at(2)).unwrap_err();
+        assert!(matches!(err, VmError::LaunchHoldingLimit { limit: 30_000, .. }), "{:?}", err);
+
+        vm.execute_at("creator", &[claim], Clock { height: 4, timestamp: 1 }).unwrap();
+        vm.execute_at("whale", &[ins(Opcode::RemoveLiquidity, shares)], at(4)).unwrap();
+    }
+
+    #[test]
+    fn window_starts_when_the_store_creates_the_curve() {
+        let mut store = CurveStore::new();
+        store.clock = at(100);
+        store.create(CurveId::default(), launch()).unwrap();
+        let vm = store.get(&CurveId::default()).unwrap();
+        assert_eq!(vm.created_at, 100);
+        assert!(vm.in_launch_window(103) && !vm.in_launch_window(104));
+        assert!(vm.in_launch_window(100) && !vm.in_launch_window(99));
+
+        store.clock = at(104);
+        store.execute("sniper", &[ins(Opcode::Buy, 100_000)]).unwrap();
+        assert!(!CurveVM::new().in_launch_window(0));
+    }
+}
@@ -1,64 +1,1135 @@
+#[cfg(feature = "vm")]
 use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "vm")]
+use std::collections::BTreeMap;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+// Without the default `vm` feature only the curve maths, the instruction set and
+// its bytecode are built, which is all an offline verifier needs.
+#[cfg(feature = "vm")]
+mod amm;
+pub mod bytecode;
+#[cfg(feature = "vm")]
+mod compute;
+pub mod curve;
+#[cfg(feature = "vm")]
+mod diff;
+mod error;
+#[cfg(feature = "vm")]
+mod fees;
+#[cfg(test)]
+mod harness;
+mod id;
+#[cfg(feature = "vm")]
+mod launch;
+pub mod math;
+#[cfg(feature = "vm")]
+mod scheduler;
+#[cfg(feature = "vm")]
+mod store;
+#[cfg(feature = "vm")]
+mod trace;
+#[cfg(feature = "vm")]
+mod vesting;
+#[cfg(feature = "vm")]
+pub mod wasm;
+
+#[cfg(feature = "vm")]
+pub use amm::{AmmPool, DEFAULT_LP_FEE_BPS};
+#[cfg(feature = "vm")]
+pub use compute::{CostTable, DEFAULT_COMPUTE_BUDGET};
+pub use curve::{ConstantProduct, Curve, CurveKind, Exponential, Lbp, Linear, PRICE_SCALE, Sigmoid};
+#[cfg(feature = "vm")]
+pub use diff::{AccountChange, Field, FieldChange, Snapshot, StateDiff, StoreDiff, Value, VestingChange};
+pub use error::DecodeError;
+#[cfg(feature = "vm")]
+pub use error::VmError;
+#[cfg(feature = "vm")]
+pub use fees::{FeeConfig, FeeSplit};
+pub use id::CurveId;
+#[cfg(feature = "vm")]
+pub use launch::LaunchRules;
+#[cfg(feature = "vm")]
+pub use scheduler::{Call, schedule};
+#[cfg(feature = "vm")]
+pub use store::{CurveStore, conflicts, curves_touched};
+#[cfg(feature = "vm")]
+pub use trace::{CurveSnapshot, Event, Side, TraceStep};
+#[cfg(feature = "vm")]
+pub use vesting::VestingSchedule;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Opcode {
     Buy,
     Sell,
     AddLiquidity,
     MigrateToAmm,
+    Claim,
+    RemoveLiquidity,
+    ClaimFees,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+impl Opcode {
+    pub const ALL: [Opcode; 7] = [
+        Opcode::Buy,
+        Opcode::Sell,
+        Opcode::AddLiquidity,
+        Opcode::MigrateToAmm,
+        Opcode::Claim,
+        Opcode::RemoveLiquidity,
+        Opcode::ClaimFees,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Buy => "BUY",
+            Opcode::Sell => "SELL",
+            Opcode::AddLiquidity => "ADD_LIQUIDITY",
+            Opcode::MigrateToAmm => "MIGRATE_TO_AMM",
+            Opcode::Claim => "CLAIM",
+            Opcode::RemoveLiquidity => "REMOVE_LIQUIDITY",
+            Opcode::ClaimFees => "CLAIM_FEES",
+        }
+    }
+}
+
+// `limit` bounds the SOL side of a trade: the most a buy may pay, fee included,
+// or the least a sell must return after fees. `expiry` is the last block height
+// the instruction may execute at. Zero leaves either unbounded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub operand: i64,
+    pub curve: CurveId,
+    pub limit: i64,
+    pub expiry: u64,
+}
+
+// The block an instruction executes in. It is an input to execution rather than
+// state, so it never reaches the root.
+#[cfg(feature = "vm")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Clock {
+    pub height: u64,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "vm", derive(BorshSerialize, BorshDeserialize))]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationThreshold {
+    Reserve(i64),
+    MarketCap(i128),
+}
+
+#[cfg(feature = "vm")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct Account {
+    pub tokens: i64,
+    pub spent: i64,
+    pub received: i64,
+    pub lp_shares: i64,
+    pub launch_bought: i64,
+    pub launch_sold: i64,
+    pub pledged: i64,
 }
 
+#[cfg(feature = "vm")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionReceipt {
+    pub sender: String,
+    pub instructions: usize,
+    pub tokens_bought: i64,
+    pub tokens_sold: i64,
+    pub reserve_in: i64,
+    pub reserve_out: i64,
+    pub lp_minted: i64,
+    pub fees_paid: i64,
+    pub compute_units: u64,
+    pub migrated: bool,
+    pub trace: Vec<TraceStep>,
+    pub events: Vec<Event>,
+}
+
+#[cfg(feature = "vm")]
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
 pub struct CurveVM {
-    pub balance: i64,
+    pub curve: CurveKind,
+    pub max_supply: i64,
+    pub supply: i64,
+    pub reserve: i64,
     pub liquidity: i64,
     pub migrated_to_amm: bool,
     pub migrate_value: i64,
+    pub migration_threshold: Option<MigrationThreshold>,
+    pub reserve_ratio_bps: u16,
+    pub fees: FeeConfig,
+    pub protocol_fees: i64,
+    pub creator_fees: i64,
+    pub launch: LaunchRules,
+    pub created_at: u64,
+    pub amm: AmmPool,
+    pub accounts: BTreeMap<String, Account>,
+    pub vesting: BTreeMap<String, VestingSchedule>,
+    pub cost_table: CostTable,
+    pub compute_budget: u64,
 }
 
+#[cfg(feature = "vm")]
 impl CurveVM {
     pub fn new() -> Self {
-        Self { balance: 0, liquidity: 0, migrated_to_amm: false, migrate_value: 0 }
+        Self::with_curve(CurveKind::default(), CurveKind::default().max_supply()).expect("the default curve is valid")
+    }
+
+    pub fn with_curve(curve: CurveKind, max_supply: i64) -> Result<Self, VmError> {
+        if !curve.is_valid() {
+            return Err(VmError::InvalidCurve);
+        }
+        Ok(Self {
+            curve,
+            max_supply: max_supply.min(curve.max_supply()),
+            supply: 0,
+            reserve: 0,
+            liquidity: 0,
+            migrated_to_amm: false,
+            migrate_value: 0,
+            migration_threshold: None,
+            reserve_ratio_bps: 0,
+            fees: FeeConfig::default(),
+            protocol_fees: 0,
+            creator_fees: 0,
+            launch: LaunchRules::default(),
+            created_at: 0,
+            amm: AmmPool { fee_bps: DEFAULT_LP_FEE_BPS, ..Default::default() },
+            accounts: BTreeMap::new(),
+            vesting: BTreeMap::new(),
+            cost_table: CostTable::default(),
+            compute_budget: DEFAULT_COMPUTE_BUDGET,
+        })
+    }
+
+    pub fn price(&self) -> i128 {
+        if self.migrated_to_amm { self.amm.spot_price() } else { self.curve.spot_price(self.supply) }
+    }
+
+    pub fn market_cap(&self) -> i128 {
+        math::mul_div(self.price(), self.supply as i128, PRICE_SCALE).unwrap_or(i128::MAX)
+    }
+
+    pub fn threshold_reached(&self) -> bool {
+        match self.migration_threshold {
+            Some(MigrationThreshold::Reserve(target)) => self.reserve >= target,
+            Some(MigrationThreshold::MarketCap(target)) => self.market_cap() >= target,
+            None => false,
+        }
+    }
+
+    // SOL backing the token: the curve reserve plus pledged liquidity before
+    // migration, the pool's SOL side afterwards.
+    pub fn backing(&self) -> i64 {
+        if self.migrated_to_amm { self.amm.sol_reserve } else { self.reserve.saturating_add(self.liquidity) }
+    }
+
+    pub fn reserve_ratio_holds(&self) -> bool {
+        self.backing() as i128 * 10_000 >= (self.reserve_ratio_bps as i128).saturating_mul(self.market_cap())
+    }
+
+    pub fn account(&self, owner: &str) -> Account {
+        self.accounts.get(owner).copied().unwrap_or_default()
+    }
+
+    pub fn balance_of(&self, owner: &str) -> i64 {
+        self.account(owner).tokens
+    }
+
+    pub fn execute(&mut self, sender: &str, program: &[Instruction]) -> Result<ExecutionReceipt, VmError> {
+        self.execute_metered(sender, program, self.compute_budget, Clock::default())
+    }
+
+    pub fn execute_at(
+        &mut self,
+        sender: &str,
+        program: &[Instruction],
+        clock: Clock,
+    ) -> Result<ExecutionReceipt, VmError> {
+        self.execute_metered(sender, program, self.compute_budget, clock)
     }
 
-    pub fn execute(&mut self, program: &[Instruction]) {
+    pub fn execute_metered(
+        &mut self,
+        sender: &str,
+        program: &[Instruction],
+        budget: u64,
+        clock: Clock,
+    ) -> Result<ExecutionReceipt, VmError> {
+        let snapshot = self.snapshot();
+        let mut receipt = ExecutionReceipt { sender: sender.to_string(), ..Default::default() };
         for ins in program {
-            match ins.opcode {
-                Opcode::Buy => self.balance += ins.operand,
-                Opcode::Sell => self.balance -= ins.operand,
-                Opcode::AddLiquidity => self.liquidity += ins.operand,
-                Opcode::MigrateToAmm => {
-                    self.migrated_to_amm = true;
-                    self.migrate_value = ins.operand;
+            if let Err(err) = self.run(ins, &mut receipt, budget, clock) {
+                self.restore(snapshot);
+                return Err(err);
+            }
+        }
+        Ok(receipt)
+    }
+
+    pub(crate) fn run(
+        &mut self,
+        ins: &Instruction,
+        receipt: &mut ExecutionReceipt,
+        budget: u64,
+        clock: Clock,
+    ) -> Result<(), VmError> {
+        let cost = self.cost_table.cost(ins.opcode);
+        let required = receipt.compute_units.saturating_add(cost);
+        if required > budget {
+            return Err(VmError::ComputeBudgetExceeded { budget, required });
+        }
+        receipt.compute_units = required;
+        self.sync_clock(clock);
+        let pre = CurveSnapshot::of(self);
+        let (tokens_moved, sol_moved) = self.step(ins, receipt, clock)?;
+        receipt.trace.push(TraceStep {
+            index: receipt.instructions,
+            instruction: *ins,
+            pre,
+            post: CurveSnapshot::of(self),
+            tokens_moved,
+            sol_moved,
+            compute_units: cost,
+        });
+        receipt.instructions += 1;
+        Ok(())
+    }
+
+    // Weight-shifting curves price against the blocks since the curve was created,
+    // so every instruction first brings the schedule up to its own height.
+    pub fn sync_clock(&mut self, clock: Clock) {
+        if let CurveKind::Lbp(lbp) = &mut self.curve {
+            lbp.elapsed = clock.height.saturating_sub(self.created_at);
+        }
+    }
+
+    // Returns the tokens and SOL the instruction moved.
+    fn step(&mut self, ins: &Instruction, receipt: &mut ExecutionReceipt, clock: Clock) -> Result<(i64, i64), VmError> {
+        if ins.operand < 0 {
+            return Err(VmError::InvalidOperand { operand: ins.operand });
+        }
+        if ins.expiry != 0 && clock.height > ins.expiry {
+            return Err(VmError::Expired { expiry: ins.expiry, height: clock.height });
+        }
+        // Only trades have a SOL amount to bound.
+        if ins.limit < 0 || (ins.limit != 0 && !matches!(ins.opcode, Opcode::Buy | Opcode::Sell)) {
+            return Err(VmError::InvalidOperand { operand: ins.limit });
+        }
+        let was_migrated = self.migrated_to_amm;
+        let sender = receipt.sender.clone();
+        let moved = match ins.opcode {
+            Opcode::Buy | Opcode::Sell => {
+                let (side, (sol, fee)) = if ins.opcode == Opcode::Buy {
+                    (Side::Buy, self.buy(ins.operand, receipt)?)
+                } else {
+                    (Side::Sell, self.sell(ins.operand, receipt)?)
+                };
+                let (actual, within) = match side {
+                    Side::Buy => (sol + fee, sol + fee <= ins.limit),
+                    Side::Sell => (sol - fee, sol - fee >= ins.limit),
+                };
+                if ins.limit != 0 && !within {
+                    return Err(VmError::SlippageExceeded { limit: ins.limit, actual });
                 }
+                self.enforce_launch(side, ins.operand, &sender, clock.height)?;
+                receipt.events.push(Event::Trade {
+                    curve: ins.curve,
+                    trader: sender,
+                    side,
+                    tokens: ins.operand,
+                    sol,
+                    fee,
+                    via_amm: was_migrated,
+                });
+                (ins.operand, sol)
+            }
+            Opcode::AddLiquidity => {
+                let (tokens, lp_shares) = self.add_liquidity(ins.operand, receipt)?;
+                receipt.events.push(Event::LiquidityAdded {
+                    curve: ins.curve,
+                    provider: sender,
+                    sol: ins.operand,
+                    tokens,
+                    lp_shares,
+                });
+                (tokens, ins.operand)
+            }
+            Opcode::MigrateToAmm => {
+                self.migrate(ins.operand)?;
+                (self.amm.token_reserve, self.amm.sol_reserve)
+            }
+            Opcode::Claim => {
+                self.claim(ins.operand, clock.timestamp, receipt)?;
+                receipt.events.push(Event::Claimed { curve: ins.curve, beneficiary: sender, tokens: ins.operand });
+                (ins.operand, 0)
+            }
+            Opcode::RemoveLiquidity => {
+                let (tokens, sol) = self.remove_liquidity(ins.operand, receipt)?;
+                receipt.events.push(Event::LiquidityRemoved {
+                    curve: ins.curve,
+                    provider: sender,
+                    sol,
+                    tokens,
+                    lp_shares: ins.operand,
+                });
+                (tokens, sol)
+            }
+            Opcode::ClaimFees => {
+                self.claim_fees(ins.operand, receipt)?;
+                receipt.events.push(Event::FeesClaimed { curve: ins.curve, recipient: sender, sol: ins.operand });
+                (0, ins.operand)
+            }
+        };
+        if matches!(ins.opcode, Opcode::Buy | Opcode::Claim | Opcode::RemoveLiquidity) {
+            self.enforce_holding(&receipt.sender, clock.height)?;
+        }
+        // Buys pay the curve price into the reserve; only instructions that take
+        // SOL out or mint tokens without paying can leave the vault under-reserved.
+        if matches!(ins.opcode, Opcode::Sell | Opcode::Claim | Opcode::RemoveLiquidity) && !self.reserve_ratio_holds() {
+            return Err(VmError::ReserveRatioBreached {
+                backing: self.backing(),
+                market_cap: self.market_cap(),
+                ratio_bps: self.reserve_ratio_bps,
+            });
+        }
+        if !was_migrated && self.migrated_to_amm {
+            receipt.migrated = true;
+            receipt.events.push(Event::Migrated {
+                curve: ins.curve,
+                token_reserve: self.amm.token_reserve,
+                sol_reserve: self.amm.sol_reserve,
+                lp_supply: self.amm.lp_supply,
+                automatic: ins.opcode != Opcode::MigrateToAmm,
+            });
+        }
+        Ok(moved)
+    }
+
+    // Returns the SOL paid into the curve or pool and the fee charged on top.
+    fn buy(&mut self, amount: i64, receipt: &mut ExecutionReceipt) -> Result<(i64, i64), VmError> {
+        let cost = if self.migrated_to_amm {
+            let available = self.amm.token_reserve - 1;
+            if amount > available {
+                return Err(VmError::InsufficientLiquidity { requested: amount, available });
+            }
+            let cost = self.amm.buy_cost(amount).ok_or(VmError::Overflow)?;
+            self.amm.sol_reserve = self.amm.sol_reserve.checked_add(cost).ok_or(VmError::Overflow)?;
+            self.amm.token_reserve -= amount;
+            cost
+        } else {
+            let available = self.unallocated_supply();
+            if amount > available {
+                return Err(VmError::SupplyExhausted { requested: amount, available });
             }
+            let cost = self.curve.cost(self.supply, self.supply + amount).ok_or(VmError::Overflow)?;
+            let cost = i64::try_from(cost).map_err(|_| VmError::Overflow)?;
+            self.reserve = self.reserve.checked_add(cost).ok_or(VmError::Overflow)?;
+            cost
+        };
+        let fee = self.charge_fee(cost, receipt)?;
+        self.supply = self.supply.checked_add(amount).ok_or(VmError::Overflow)?;
+        let account = self.accounts.entry(receipt.sender.clone()).or_default();
+        account.tokens = account.tokens.checked_add(amount).ok_or(VmError::Overflow)?;
+        account.spent = account.spent.checked_add(cost).and_then(|s| s.checked_add(fee)).ok_or(VmError::Overflow)?;
+        receipt.tokens_bought = receipt.tokens_bought.checked_add(amount).ok_or(VmError::Overflow)?;
+        receipt.reserve_in = receipt.reserve_in.checked_add(cost).ok_or(VmError::Overflow)?;
+        // The buy that crosses the threshold completes on the curve; everything after it
+        // trades against the pool.
+        if !self.migrated_to_amm && self.threshold_reached() {
+            self.migrate(0)?;
         }
+        Ok((cost, fee))
+    }
+
+    // Returns the SOL taken out of the curve or pool and the fee kept from it.
+    fn sell(&mut self, amount: i64, receipt: &mut ExecutionReceipt) -> Result<(i64, i64), VmError> {
+        let held = self.balance_of(&receipt.sender);
+        if amount > held {
+            return Err(VmError::InsufficientBalance { requested: amount, available: held });
+        }
+        let proceeds = if self.migrated_to_amm {
+            let proceeds = self.amm.sell_proceeds(amount).ok_or(VmError::Overflow)?;
+            self.amm.token_reserve = self.amm.token_reserve.checked_add(amount).ok_or(VmError::Overflow)?;
+            self.amm.sol_reserve -= proceeds;
+            proceeds
+        } else {
+            let proceeds = self.curve.cost(self.supply - amount, self.supply).ok_or(VmError::Overflow)?;
+            // Weight-shifting curves can quote more than the pool holds.
+            if proceeds > self.reserve as i128 {
+                return Err(VmError::NegativeReserve { requested: proceeds, available: self.reserve });
+            }
+            self.reserve -= proceeds as i64;
+            proceeds as i64
+        };
+        let fee = self.charge_fee(proceeds, receipt)?;
+        self.supply -= amount;
+        let account = self.accounts.entry(receipt.sender.clone()).or_default();
+        account.tokens -= amount;
+        account.received = account.received.checked_add(proceeds - fee).ok_or(VmError::Overflow)?;
+        receipt.tokens_sold = receipt.tokens_sold.checked_add(amount).ok_or(VmError::Overflow)?;
+        receipt.reserve_out = receipt.reserve_out.checked_add(proceeds).ok_or(VmError::Overflow)?;
+        Ok((proceeds, fee))
+    }
+
+    // Before migration, liquidity is pledged SOL that seeds the pool; afterwards it is
+    // a proportional deposit that mints LP shares.
+    fn add_liquidity(&mut self, amount: i64, receipt: &mut ExecutionReceipt) -> Result<(i64, i64), VmError> {
+        let (tokens, shares) = if self.migrated_to_amm {
+            let (tokens, shares) = self.amm.deposit_quote(amount).ok_or(VmError::Overflow)?;
+            if shares == 0 {
+                return Err(VmError::InvalidOperand { operand: amount });
+            }
+            let held = self.balance_of(&receipt.sender);
+            if tokens > held {
+                return Err(VmError::InsufficientBalance { requested: tokens, available: held });
+            }
+            self.amm.sol_reserve = self.amm.sol_reserve.checked_add(amount).ok_or(VmError::Overflow)?;
+            self.amm.token_reserve = self.amm.token_reserve.checked_add(tokens).ok_or(VmError::Overflow)?;
+            self.amm.lp_supply = self.amm.lp_supply.checked_add(shares).ok_or(VmError::Overflow)?;
+            self.supply -= tokens;
+            (tokens, shares)
+        } else {
+            self.liquidity = self.liquidity.checked_add(amount).ok_or(VmError::Overflow)?;
+            let account = self.accounts.entry(receipt.sender.clone()).or_default();
+            account.pledged = account.pledged.checked_add(amount).ok_or(VmError::Overflow)?;
+            (0, 0)
+        };
+        let account = self.accounts.entry(receipt.sender.clone()).or_default();
+        account.tokens -= tokens;
+        account.spent = account.spent.checked_add(amount).ok_or(VmError::Overflow)?;
+        account.lp_shares = account.lp_shares.checked_add(shares).ok_or(VmError::Overflow)?;
+        receipt.reserve_in = receipt.reserve_in.checked_add(amount).ok_or(VmError::Overflow)?;
+        receipt.lp_minted = receipt.lp_minted.checked_add(shares).ok_or(VmError::Overflow)?;
+        Ok((tokens, shares))
+    }
+
+    // Burns LP shares for a proportional slice of both pool reserves.
+    fn remove_liquidity(&mut self, shares: i64, receipt: &mut ExecutionReceipt) -> Result<(i64, i64), VmError> {
+        if !self.migrated_to_amm {
+            return Err(VmError::NotMigrated);
+        }
+        let held = self.account(&receipt.sender).lp_shares;
+        if shares > held || shares == 0 {
+            return Err(VmError::InsufficientBalance { requested: shares, available: held });
+        }
+        let (tokens, sol) = self.amm.withdraw_quote(shares).ok_or(VmError::Overflow)?;
+        self.amm.token_reserve -= tokens;
+        self.amm.sol_reserve -= sol;
+        self.amm.lp_supply -= shares;
+        self.supply = self.supply.checked_add(tokens).ok_or(VmError::Overflow)?;
+        let account = self.accounts.entry(receipt.sender.clone()).or_default();
+        account.lp_shares -= shares;
+        account.tokens = account.tokens.checked_add(tokens).ok_or(VmError::Overflow)?;
+        account.received = account.received.checked_add(sol).ok_or(VmError::Overflow)?;
+        receipt.reserve_out = receipt.reserve_out.checked_add(sol).ok_or(VmError::Overflow)?;
+        Ok((tokens, sol))
+    }
+
+    fn migrate(&mut self, value: i64) -> Result<(), VmError> {
+        if self.migrated_to_amm {
+            return Err(VmError::DoubleMigration);
+        }
+        let tokens = self.unallocated_supply();
+        let sol = self.reserve.checked_add(self.liquidity).ok_or(VmError::Overflow)?;
+        self.amm = AmmPool::open(tokens, sol, self.amm.fee_bps).ok_or(VmError::EmptyPool)?;
+        // Pledgers own the slice of the opening shares their SOL paid for; the slice
+        // backed by the curve reserve stays locked in the pool.
+        for account in self.accounts.values_mut().filter(|a| a.pledged > 0) {
+            let shares = math::mul_div(self.amm.lp_supply as i128, account.pledged as i128, sol as i128)
+                .and_then(|s| i64::try_from(s).ok())
+                .ok_or(VmError::Overflow)?;
+            account.lp_shares = account.lp_shares.checked_add(shares).ok_or(VmError::Overflow)?;
+            account.pledged = 0;
+        }
+        self.reserve = 0;
+        self.liquidity = 0;
+        self.migrated_to_amm = true;
+        self.migrate_value = value;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "vm")]
+impl Default for CurveVM {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::harness::any_curve;
+    use proptest::prelude::*;
 
     #[test]
     fn basic_program() {
         let program = [
-            Instruction { opcode: Opcode::Buy, operand: 5 },
-            Instruction { opcode: Opcode::Sell, operand: 2 },
-            Instruction { opcode: Opcode::AddLiquidity, operand: 3 },
-            Instruction { opcode: Opcode::MigrateToAmm, operand: 1 },
+            ins(Opcode::Buy, 5),
+            ins(Opcode::Sell, 2),
+            ins(Opcode::AddLiquidity, 3),
+            ins(Opcode::MigrateToAmm, 1),
         ];
         let mut vm = CurveVM::new();
-        vm.execute(&program);
-        assert_eq!(vm.balance, 3);
-        assert_eq!(vm.liquidity, 3);
+        vm.execute("alice", &program).unwrap();
+        assert_eq!(vm.supply, 3);
+        assert_eq!(vm.amm.sol_reserve, vm.curve.reserve_at(3).unwrap() as i64 + 3);
+        assert_eq!(vm.amm.token_reserve, vm.max_supply - 3);
+        assert_eq!(vm.reserve, 0);
+        assert_eq!(vm.liquidity, 0);
         assert!(vm.migrated_to_amm);
         assert_eq!(vm.migrate_value, 1);
     }
+
+    #[test]
+    fn buys_get_more_expensive() {
+        let curve = CurveKind::ConstantProduct(ConstantProduct { virtual_reserve: 30_000, virtual_supply: 1_000_000 });
+        let mut vm = CurveVM::with_curve(curve, 800_000).unwrap();
+        let mut last_cost = 0;
+        let mut last_price = vm.price();
+        for _ in 0..8 {
+            let before = vm.reserve;
+            vm.execute("alice", &[ins(Opcode::Buy, 100_000)]).unwrap();
+            let cost = vm.reserve - before;
+            assert!(cost > last_cost);
+            assert!(vm.price() > last_price);
+            last_cost = cost;
+            last_price = vm.price();
+        }
+        assert_eq!(vm.supply, 800_000);
+        let err = vm.execute("alice", &[ins(Opcode::Buy, 1)]).unwrap_err();
+        assert_eq!(err, VmError::SupplyExhausted { requested: 1, available: 0 });
+    }
+
+    #[test]
+    fn sell_returns_reserve_along_curve() {
+        let curve = CurveKind::Linear(Linear { base_price: 5, slope_num: 1, slope_den: 2 });
+        let mut vm = CurveVM::with_curve(curve, 1_000).unwrap();
+        vm.execute("alice", &[ins(Opcode::Buy, 100)]).unwrap();
+        let reserve = vm.reserve;
+        vm.execute("alice", &[ins(Opcode::Sell, 40)]).unwrap();
+        assert_eq!(reserve - vm.reserve, vm.curve.cost(60, 100).unwrap() as i64);
+        vm.execute("alice", &[ins(Opcode::Sell, 60)]).unwrap();
+        assert_eq!(vm.supply, 0);
+        assert_eq!(vm.reserve, 0);
+    }
+
+    #[test]
+    fn dispatches_through_selected_family() {
+        let curve = CurveKind::Sigmoid(Sigmoid { max_price: 1_000, midpoint: 5_000, width: 1_000 });
+        let mut vm = CurveVM::with_curve(curve, 10_000).unwrap();
+        vm.execute("alice", &[ins(Opcode::Buy, 5_000)]).unwrap();
+        assert_eq!(vm.reserve as i128, curve.reserve_at(5_000).unwrap());
+        assert_eq!(vm.price(), curve.spot_price(5_000));
+        assert_eq!(vm.price(), 500 * PRICE_SCALE);
+    }
+
+    fn ins(opcode: Opcode, operand: i64) -> Instruction {
+        Instruction { opcode, operand, curve: CurveId::default(), limit: 0, expiry: 0 }
+    }
+
+    #[test]
+    fn receipt_reports_flows() {
+        let mut vm = CurveVM::new();
+        let receipt = vm.execute("alice", &[ins(Opcode::Buy, 10), ins(Opcode::Sell, 4)]).unwrap();
+        assert_eq!(receipt.instructions, 2);
+        assert_eq!(receipt.tokens_bought, 10);
+        assert_eq!(receipt.tokens_sold, 4);
+        assert_eq!(receipt.reserve_in - receipt.reserve_out, vm.reserve);
+    }
+
+    #[test]
+    fn overflow_is_rejected() {
+        let curve = CurveKind::Linear(Linear { base_price: 1_000_000, slope_num: 1, slope_den: 1 });
+        let mut vm = CurveVM::with_curve(curve, i64::MAX).unwrap();
+        let err = vm.execute("alice", &[ins(Opcode::Buy, i64::MAX / 2)]).unwrap_err();
+        assert_eq!(err, VmError::Overflow);
+        vm.execute("alice", &[ins(Opcode::AddLiquidity, i64::MAX)]).unwrap();
+        let err = vm.execute("alice", &[ins(Opcode::AddLiquidity, 1)]).unwrap_err();
+        assert_eq!(err, VmError::Overflow);
+        assert_eq!(vm.liquidity, i64::MAX);
+    }
+
+    #[test]
+    fn oversell_is_rejected() {
+        let mut vm = CurveVM::new();
+        let err = vm.execute("alice", &[ins(Opcode::Buy, 5), ins(Opcode::Sell, 6)]).unwrap_err();
+        assert_eq!(err, VmError::InsufficientBalance { requested: 6, available: 5 });
+        let err = vm.execute("alice", &[ins(Opcode::Buy, -1)]).unwrap_err();
+        assert_eq!(err, VmError::InvalidOperand { operand: -1 });
+    }
+
+    #[test]
+    fn reserve_cannot_go_negative() {
+        let lbp = Lbp {
+            virtual_reserve: 1_000_000,
+            token_balance: 10_000_000,
+            start_weight_bps: 5_000,
+            end_weight_bps: 9_000,
+            duration: 10,
+            elapsed: 0,
+        };
+        let mut vm = CurveVM::with_curve(CurveKind::Lbp(lbp), 5_000_000).unwrap();
+        vm.execute("alice", &[ins(Opcode::Buy, 1_000_000)]).unwrap();
+        let later = Clock { height: 10, timestamp: 0 };
+        let err = vm.execute_at("alice", &[ins(Opcode::Sell, 1_000_000)], later).unwrap_err();
+        assert!(matches!(err, VmError::NegativeReserve { .. }));
+    }
+
+    #[test]
+    fn lbp_weights_follow_the_clock() {
+        let lbp = Lbp {
+            virtual_reserve: 1_000_000,
+            token_balance: 10_000_000,
+            start_weight_bps: 9_000,
+            end_weight_bps: 5_000,
+            duration: 10,
+            elapsed: 0,
+        };
+        let mut vm = CurveVM::with_curve(CurveKind::Lbp(lbp), 5_000_000).unwrap();
+        vm.created_at = 100;
+        let opening = vm.price();
+        vm.execute_at("alice", &[ins(Opcode::Buy, 1)], Clock { height: 104, timestamp: 0 }).unwrap();
+        assert_eq!(vm.curve, CurveKind::Lbp(lbp.at(4)));
+        assert!(vm.price() < opening);
+        vm.execute_at("alice", &[ins(Opcode::Buy, 1)], Clock { height: 500, timestamp: 0 }).unwrap();
+        assert_eq!(vm.curve, CurveKind::Lbp(lbp.at(400)));
+        assert_eq!(lbp.at(400).token_weight_bps(), 5_000);
+    }
+
+    fn migrated_vm() -> CurveVM {
+        let curve = CurveKind::ConstantProduct(ConstantProduct { virtual_reserve: 30_000, virtual_supply: 1_000_000 });
+        let mut vm = CurveVM::with_curve(curve, 800_000).unwrap();
+        vm.execute("alice", &[ins(Opcode::Buy, 600_000)]).unwrap();
+        vm.execute("creator", &[ins(Opcode::AddLiquidity, 5_000), ins(Opcode::MigrateToAmm, 0)]).unwrap();
+        vm
+    }
+
+    #[test]
+    fn migration_seeds_pool_from_curve() {
+        let vm = migrated_vm();
+        let reserve = vm.curve.reserve_at(600_000).unwrap() as i64;
+        assert_eq!(vm.amm.token_reserve, 200_000);
+        assert_eq!(vm.amm.sol_reserve, reserve + 5_000);
+        assert_eq!(vm.amm.lp_supply, math::isqrt(200_000 * (reserve + 5_000) as u128) as i64);
+        assert_eq!(vm.account("creator").spent, 5_000);
+        assert_eq!((vm.reserve, vm.liquidity), (0, 0));
+        let mut vm = vm;
+        assert_eq!(vm.execute("alice", &[ins(Opcode::MigrateToAmm, 2)]), Err(VmError::DoubleMigration));
+        assert_eq!(CurveVM::new().execute("alice", &[ins(Opcode::MigrateToAmm, 0)]), Err(VmError::EmptyPool));
+    }
+
+    #[test]
+    fn pledges_are_credited_at_migration() {
+        let mut vm = migrated_vm();
+        let creator = vm.account("creator");
+        assert_eq!(creator.pledged, 0);
+        assert_eq!(creator.lp_shares as i128, vm.amm.lp_supply as i128 * 5_000 / vm.amm.sol_reserve as i128);
+        assert!(creator.lp_shares < vm.amm.lp_supply);
+        let receipt = vm.execute("creator", &[ins(Opcode::RemoveLiquidity, creator.lp_shares)]).unwrap();
+        assert!((4_990..=5_000).contains(&receipt.reserve_out));
+        assert_eq!(vm.account("alice").lp_shares, 0);
+    }
+
+    #[test]
+    fn trades_route_through_pool_after_migration() {
+        let mut vm = migrated_vm();
+        let pool = vm.amm;
+        let k = pool.token_reserve as i128 * pool.sol_reserve as i128;
+        let bought = vm.execute("bob", &[ins(Opcode::Buy, 10_000)]).unwrap();
+        assert_eq!(bought.reserve_in, pool.buy_cost(10_000).unwrap());
+        assert_eq!(vm.amm.token_reserve, pool.token_reserve - 10_000);
+        assert!(vm.amm.token_reserve as i128 * vm.amm.sol_reserve as i128 > k);
+        assert_eq!(vm.supply, 610_000);
+
+        let sold = vm.execute("bob", &[ins(Opcode::Sell, 10_000)]).unwrap();
+        assert!(sold.reserve_out < bought.reserve_in);
+        assert_eq!(vm.amm.token_reserve, pool.token_reserve);
+        assert_eq!(vm.balance_of("bob"), 0);
+        let err = vm.execute("bob", &[ins(Opcode::Buy, 200_000)]).unwrap_err();
+        assert_eq!(err, VmError::InsufficientLiquidity { requested: 200_000, available: 199_999 });
+    }
+
+    #[test]
+    fn add_liquidity_mints_shares_after_migration() {
+        let mut vm = migrated_vm();
+        let pool = vm.amm;
+        let (tokens, shares) = pool.deposit_quote(pool.sol_reserve / 10).unwrap();
+        let receipt = vm.execute("alice", &[ins(Opcode::AddLiquidity, pool.sol_reserve / 10)]).unwrap();
+        assert_eq!(receipt.lp_minted, shares);
+        assert_eq!(vm.account("alice").lp_shares, shares);
+        assert_eq!(vm.balance_of("alice"), 600_000 - tokens);
+        assert_eq!(vm.amm.lp_supply, pool.lp_supply + shares);
+        assert_eq!(vm.supply, 600_000 - tokens);
+        let err = vm.execute("bob", &[ins(Opcode::AddLiquidity, 1_000)]).unwrap_err();
+        assert!(matches!(err, VmError::InsufficientBalance { .. }));
+    }
+
+    #[test]
+    fn failed_program_leaves_state_unchanged() {
+        let mut vm = CurveVM::new();
+        vm.execute("alice", &[ins(Opcode::Buy, 5)]).unwrap();
+        let before = vm.clone();
+        let program = [ins(Opcode::Buy, 5), ins(Opcode::AddLiquidity, 3), ins(Opcode::Sell, 100)];
+        assert!(vm.execute("alice", &program).is_err());
+        assert_eq!(vm, before);
+    }
+
+    #[test]
+    fn balances_are_tracked_per_sender() {
+        let mut vm = CurveVM::new();
+        let alice = vm.execute("alice", &[ins(Opcode::Buy, 10)]).unwrap();
+        let bob = vm.execute("bob", &[ins(Opcode::Buy, 10)]).unwrap();
+        assert_eq!(alice.sender, "alice");
+        assert!(bob.reserve_in > alice.reserve_in);
+        assert_eq!(vm.balance_of("alice"), 10);
+        assert_eq!(vm.balance_of("bob"), 10);
+        assert_eq!(vm.supply, 20);
+
+        let err = vm.execute("alice", &[ins(Opcode::Sell, 11)]).unwrap_err();
+        assert_eq!(err, VmError::InsufficientBalance { requested: 11, available: 10 });
+        let err = vm.execute("carol", &[ins(Opcode::Sell, 1)]).unwrap_err();
+        assert_eq!(err, VmError::InsufficientBalance { requested: 1, available: 0 });
+
+        let sold = vm.execute("bob", &[ins(Opcode::Sell, 10)]).unwrap();
+        let bob = vm.account("bob");
+        assert_eq!(bob.tokens, 0);
+        assert_eq!(bob.received, sold.reserve_out);
+        assert_eq!(vm.account("alice").spent, alice.reserve_in);
+        assert_eq!(vm.reserve, alice.reserve_in + bob.spent - bob.received);
+    }
+
+    #[test]
+    fn compute_is_metered_per_opcode() {
+        let mut vm = CurveVM::new();
+        let program = [ins(Opcode::Buy, 10), ins(Opcode::Sell, 4), ins(Opcode::AddLiquidity, 1)];
+        let receipt = vm.execute("alice", &program).unwrap();
+        assert_eq!(receipt.compute_units, 30_000);
+        assert_eq!(receipt.compute_units, vm.cost_table.program_cost(&program));
+    }
+
+    #[test]
+    fn budget_overrun_aborts_whole_program() {
+        let mut vm = CurveVM::new();
+        vm.compute_budget = 20_000;
+        let before = vm.clone();
+        let err = vm.execute("alice", &[ins(Opcode::Buy, 10), ins(Opcode::Buy, 10)]).unwrap_err();
+        assert_eq!(err, VmError::ComputeBudgetExceeded { budget: 20_000, required: 24_000 });
+        assert_eq!(vm, before);
+        let program = [ins(Opcode::Buy, 10), ins(Opcode::Buy, 10)];
+        let receipt = vm.execute_metered("alice", &program, 24_000, Clock::default()).unwrap();
+        assert_eq!(receipt.compute_units, 24_000);
+    }
+
+    fn threshold_vm(threshold: MigrationThreshold) -> CurveVM {
+        let curve = CurveKind::Linear(Linear { base_price: 1, slope_num: 1, slope_den: 1 });
+        let mut vm = CurveVM::with_curve(curve, 1_000).unwrap();
+        vm.migration_threshold = Some(threshold);
+        vm
+    }
+
+    #[test]
+    fn migrates_on_the_buy_that_reaches_reserve_threshold() {
+        // reserve_at(20) = 20 + 400 / 2 = 220
+        let mut vm = threshold_vm(MigrationThreshold::Reserve(220));
+        let receipt = vm.execute("alice", &[ins(Opcode::Buy, 19)]).unwrap();
+        assert!(!receipt.migrated && !vm.migrated_to_amm);
+        let receipt = vm.execute("bob", &[ins(Opcode::Buy, 1)]).unwrap();
+        assert!(receipt.migrated && vm.migrated_to_amm);
+        assert_eq!(vm.amm.sol_reserve, 220);
+        assert_eq!(vm.amm.token_reserve, 980);
+        assert_eq!(vm.balance_of("bob"), 1);
+
+        let pool = vm.amm;
+        let receipt = vm.execute("carol", &[ins(Opcode::Buy, 10)]).unwrap();
+        assert_eq!(receipt.reserve_in, pool.buy_cost(10).unwrap());
+        assert!(!receipt.migrated);
+        assert_eq!(vm.execute("carol", &[ins(Opcode::MigrateToAmm, 0)]), Err(VmError::DoubleMigration));
+    }
+
+    #[test]
+    fn crossing_trade_in_a_program_migrates_before_the_next_instruction() {
+        let mut vm = threshold_vm(MigrationThreshold::Reserve(220));
+        let program = [ins(Opcode::Buy, 25), ins(Opcode::Buy, 5)];
+        let receipt = vm.execute("alice", &program).unwrap();
+        assert!(receipt.migrated);
+        let pool = AmmPool::open(975, vm.curve.reserve_at(25).unwrap() as i64, DEFAULT_LP_FEE_BPS).unwrap();
+        assert_eq!(receipt.reserve_in, pool.sol_reserve + pool.buy_cost(5).unwrap());
+        assert_eq!(vm.amm.sol_reserve, receipt.reserve_in);
+        assert_eq!(vm.amm.token_reserve, 970);
+    }
+
+    #[test]
+    fn migrates_on_market_cap_threshold() {
+        // market cap at supply 10 is (1 + 10) * 10 = 110
+        let mut vm = threshold_vm(MigrationThreshold::MarketCap(110));
+        vm.execute("alice", &[ins(Opcode::Buy, 9)]).unwrap();
+        assert!(!vm.migrated_to_amm);
+        assert_eq!(vm.market_cap(), 90);
+        vm.execute("alice", &[ins(Opcode::Buy, 1)]).unwrap();
+        assert!(vm.migrated_to_amm);
+    }
+
+    #[test]
+    fn curve_ids_are_deterministic() {
+        assert_eq!(CurveId::derive("alice", "dog"), CurveId::derive("alice", "dog"));
+        assert_ne!(CurveId::derive("alice", "dog"), CurveId::derive("alice", "cat"));
+        assert_ne!(CurveId::derive("alic", "edog"), CurveId::derive("alice", "dog"));
+        assert_eq!(CurveId::derive("alice", "dog").to_string().len(), 64);
+    }
+
+    #[test]
+    fn store_routes_instructions_by_curve() {
+        let dog = CurveId::derive("alice", "dog");
+        let cat = CurveId::derive("bob", "cat");
+        let mut store = CurveStore::new();
+        store.create(dog, CurveVM::new()).unwrap();
+        store.create(cat, CurveVM::new()).unwrap();
+        assert_eq!(store.create(cat, CurveVM::new()), Err(VmError::DuplicateCurve(cat)));
+        let flat = CurveKind::Exponential(Exponential { base_price: 1, scale: 0 });
+        assert_eq!(CurveVM::with_curve(flat, 1_000), Err(VmError::InvalidCurve));
+        let bird = CurveId::derive("alice", "bird");
+        assert_eq!(store.create(bird, CurveVM { curve: flat, ..CurveVM::new() }), Err(VmError::InvalidCurve));
+
+        let on = |curve, opcode, operand| Instruction { opcode, operand, curve, limit: 0, expiry: 0 };
+        store.execute("carol", &[on(dog, Opcode::Buy, 10), on(cat, Opcode::Buy, 3)]).unwrap();
+        assert_eq!(store.get(&dog).unwrap().balance_of("carol"), 10);
+        assert_eq!(store.get(&cat).unwrap().balance_of("carol"), 3);
+
+        let before = store.clone();
+        let program = [on(dog, Opcode::Sell, 10), on(cat, Opcode::Sell, 4)];
+        assert!(matches!(store.execute("carol", &program), Err(VmError::InsufficientBalance { .. })));
+        assert_eq!(store, before);
+        let ghost = CurveId::derive("nobody", "ghost");
+        assert_eq!(store.execute("carol", &[on(ghost, Opcode::Buy, 1)]), Err(VmError::UnknownCurve(ghost)));
+    }
+
+    #[test]
+    fn programs_on_different_curves_do_not_conflict() {
+        let dog = CurveId::derive("alice", "dog");
+        let cat = CurveId::derive("bob", "cat");
+        let on = |curve, opcode, operand| Instruction { opcode, operand, curve, limit: 0, expiry: 0 };
+        let a = [on(dog, Opcode::Buy, 1)];
+        let b = [on(cat, Opcode::Buy, 1), on(cat, Opcode::Sell, 1)];
+        let c = [on(cat, Opcode::AddLiquidity, 1), on(dog, Opcode::Sell, 1)];
+        assert!(!conflicts(&a, &b));
+        assert!(conflicts(&a, &c));
+        assert!(conflicts(&b, &c));
+        assert_eq!(curves_touched(&c).len(), 2);
+    }
+
+    #[test]
+    fn trace_records_each_instruction() {
+        let mut vm = CurveVM::new();
+        let receipt = vm.execute("alice", &[ins(Opcode::Buy, 10), ins(Opcode::Sell, 4)]).unwrap();
+        assert_eq!(receipt.trace.len(), 2);
+        let (buy, sell) = (&receipt.trace[0], &receipt.trace[1]);
+        assert_eq!(buy.pre.supply, 0);
+        assert_eq!(buy.post.supply, 10);
+        assert_eq!(buy.post, sell.pre);
+        assert_eq!(buy.sol_moved, buy.post.reserve - buy.pre.reserve);
+        assert!(buy.post.price > buy.pre.price);
+        assert_eq!((sell.index, sell.tokens_moved), (1, 4));
+        assert_eq!(sell.compute_units, vm.cost_table.sell);
+        assert_eq!(sell.post.reserve, vm.reserve);
+    }
+
+    #[test]
+    fn events_follow_the_launch_lifecycle() {
+        let curve = CurveKind::Linear(Linear { base_price: 1, slope_num: 1, slope_den: 1 });
+        let mut vm = CurveVM::with_curve(curve, 1_000).unwrap();
+        vm.migration_threshold = Some(MigrationThreshold::Reserve(220));
+        let receipt = vm.execute("alice", &[ins(Opcode::Buy, 20), ins(Opcode::Sell, 1)]).unwrap();
+        let cost = vm.curve.reserve_at(20).unwrap() as i64;
+        assert_eq!(
+            receipt.events[..2],
+            [
+                Event::Trade {
+                    curve: CurveId::default(),
+                    trader: "alice".into(),
+                    side: Side::Buy,
+                    tokens: 20,
+                    sol: cost,
+                    fee: 0,
+                    via_amm: false,
+                },
+                Event::Migrated {
+                    curve: CurveId::default(),
+                    token_reserve: 980,
+                    sol_reserve: cost,
+                    lp_supply: math::isqrt(980 * cost as u128) as i64,
+                    automatic: true,
+                },
+            ]
+        );
+        assert!(matches!(receipt.events[2], Event::Trade { side: Side::Sell, via_amm: true, .. }));
+
+        let receipt = vm.execute("alice", &[ins(Opcode::AddLiquidity, 2)]).unwrap();
+        let Event::LiquidityAdded { sol, tokens, lp_shares, .. } = receipt.events[0].clone() else {
+            panic!("expected LiquidityAdded, got {:?}", receipt.events);
+        };
+        assert_eq!((sol, tokens, lp_shares), (2, receipt.trace[0].tokens_moved, receipt.lp_minted));
+        assert!(lp_shares > 0);
+    }
+
+    #[test]
+    fn liquidity_removal_returns_a_proportional_slice() {
+        let mut vm = migrated_vm();
+        assert_eq!(CurveVM::new().execute("alice", &[ins(Opcode::RemoveLiquidity, 1)]), Err(VmError::NotMigrated));
+        let sol = vm.amm.sol_reserve / 2;
+        let receipt = vm.execute("alice", &[ins(Opcode::AddLiquidity, sol)]).unwrap();
+        let (shares, deposited) = (receipt.lp_minted, receipt.trace[0].tokens_moved);
+        let err = vm.execute("alice", &[ins(Opcode::RemoveLiquidity, shares + 1)]).unwrap_err();
+        assert_eq!(err, VmError::InsufficientBalance { requested: shares + 1, available: shares });
+
+        let receipt = vm.execute("alice", &[ins(Opcode::RemoveLiquidity, shares)]).unwrap();
+        let Event::LiquidityRemoved { sol: out, tokens, .. } = receipt.events[0] else {
+            panic!("expected LiquidityRemoved, got {:?}", receipt.events);
+        };
+        assert!(out <= sol && sol - out <= 1);
+        assert!(tokens <= deposited && deposited - tokens <= 1);
+        assert_eq!(vm.account("alice").lp_shares, 0);
+    }
+
+    #[test]
+    fn reserve_ratio_guards_withdrawals() {
+        let mut vm = migrated_vm();
+        let sol = vm.amm.sol_reserve / 2;
+        let shares = vm.execute("alice", &[ins(Opcode::AddLiquidity, sol)]).unwrap().lp_minted;
+        // The deposit moved tokens into the pool; pulling them all back out would
+        // leave a third of the market cap backed.
+        vm.reserve_ratio_bps = 5_000;
+        assert!(vm.reserve_ratio_holds());
+        let before = vm.clone();
+        let err = vm.execute("alice", &[ins(Opcode::RemoveLiquidity, shares)]).unwrap_err();
+        assert!(matches!(err, VmError::ReserveRatioBreached { ratio_bps: 5_000, .. }));
+        assert_eq!(vm, before);
+        vm.execute("alice", &[ins(Opcode::RemoveLiquidity, shares / 10)]).unwrap();
+        vm.execute("alice", &[ins(Opcode::Sell, 1_000)]).unwrap();
+
+        let mut vm = CurveVM::new();
+        vm.max_supply = 1_000_000;
+        vm.add_vesting("creator", VestingSchedule::new(10_000, 0, 0, 1)).unwrap();
+        vm.execute("alice", &[ins(Opcode::Buy, 900_000), ins(Opcode::MigrateToAmm, 0)]).unwrap();
+        vm.reserve_ratio_bps = 990;
+        assert!(vm.reserve_ratio_holds());
+        let clock = Clock { height: 0, timestamp: 1 };
+        let err = vm.execute_at("creator", &[ins(Opcode::Claim, 10_000)], clock).unwrap_err();
+        assert!(matches!(err, VmError::ReserveRatioBreached { .. }));
+        vm.execute_at("creator", &[ins(Opcode::Claim, 10)], clock).unwrap();
+    }
+
+    #[test]
+    fn trades_respect_slippage_limits_and_deadlines() {
+        let mut vm = CurveVM::new();
+        let treasury = "treasury".to_string();
+        vm.set_fees(FeeConfig { fee_bps: 100, protocol_share_bps: 10_000, treasury, ..Default::default() }).unwrap();
+        let mut probe = vm.clone();
+        let quote = probe.execute("alice", &[ins(Opcode::Buy, 500)]).unwrap();
+        let paid = probe.account("alice").spent;
+        assert_eq!(paid, quote.reserve_in + quote.fees_paid);
+
+        // The limit covers the fee, so the bare curve cost is not enough.
+        let bounded = |opcode, operand, limit| Instruction { limit, ..ins(opcode, operand) };
+        let err = vm.execute("alice", &[bounded(Opcode::Buy, 500, paid - 1)]).unwrap_err();
+        assert_eq!(err, VmError::SlippageExceeded { limit: paid - 1, actual: paid });
+        // A buy ordered ahead of alice's moves the price past her limit.
+        let mut front_run = vm.clone();
+        front_run.execute("mallory", &[ins(Opcode::Buy, 10)]).unwrap();
+        let before = front_run.clone();
+        let err = front_run.execute("alice", &[bounded(Opcode::Buy, 500, paid)]).unwrap_err();
+        assert!(matches!(err, VmError::SlippageExceeded { actual, .. } if actual > paid));
+        assert_eq!(front_run, before);
+        vm.execute("alice", &[bounded(Opcode::Buy, 500, paid)]).unwrap();
+        assert_eq!(vm, probe);
+
+        let proceeds = probe.clone().execute("alice", &[ins(Opcode::Sell, 200)]).unwrap().reserve_out;
+        let net = proceeds - (proceeds + 99) / 100;
+        let err = vm.execute("alice", &[bounded(Opcode::Sell, 200, net + 1)]).unwrap_err();
+        assert_eq!(err, VmError::SlippageExceeded { limit: net + 1, actual: net });
+        vm.execute("alice", &[bounded(Opcode::Sell, 200, net)]).unwrap();
+        assert_eq!(vm.execute("alice", &[bounded(Opcode::Sell, 1, -1)]), Err(VmError::InvalidOperand { operand: -1 }));
+        let err = vm.execute("alice", &[bounded(Opcode::AddLiquidity, 1, 5)]).unwrap_err();
+        assert_eq!(err, VmError::InvalidOperand { operand: 5 });
+
+        let expiring = Instruction { expiry: 7, ..ins(Opcode::Buy, 1) };
+        let err = vm.execute_at("alice", &[expiring], Clock { height: 8, timestamp: 0 }).unwrap_err();
+        assert_eq!(err, VmError::Expired { expiry: 7, height: 8 });
+        vm.execute_at("alice", &[expiring], Clock { height: 7, timestamp: 0 }).unwrap();
+    }
+
+    proptest! {
+        #[test]
+        fn buy_then_sell_never_extracts_value(curve in any_curve(), prior in 0..100_000i64, amount in 1..100_000i64) {
+            let mut vm = CurveVM::with_curve(curve, 1_000_000).unwrap();
+            if prior > 0 && vm.execute("whale", &[ins(Opcode::Buy, prior)]).is_err() {
+                return Ok(());
+            }
+            let reserve = vm.reserve;
+            if let Ok(receipt) = vm.execute("trader", &[ins(Opcode::Buy, amount), ins(Opcode::Sell, amount)]) {
+                prop_assert!(receipt.reserve_out <= receipt.reserve_in);
+                prop_assert!(vm.reserve >= reserve);
+            }
+        }
+
+        #[test]
+        fn guarded_instructions_never_leave_the_pool_under_reserved(
+            curve in any_curve(),
+            ratio_bps in 1..6_000u16,
+            steps in prop::collection::vec((0..5usize, 0..2usize, 1..50_000i64), 1..40),
+        ) {
+            let opcodes = [Opcode::Buy, Opcode::Sell, Opcode::AddLiquidity, Opcode::RemoveLiquidity, Opcode::MigrateToAmm];
+            let mut vm = CurveVM::with_curve(curve, 1_000_000).unwrap();
+            vm.reserve_ratio_bps = ratio_bps;
+            for (op, who, amount) in steps {
+                let sender = ["alice", "bob"][who];
+                let opcode = opcodes[op];
+                let before = vm.clone();
+                let amount = match opcode {
+                    Opcode::Sell => amount.min(vm.balance_of(sender)),
+                    Opcode::RemoveLiquidity => amount.min(vm.account(sender).lp_shares),
+                    _ => amount,
+                };
+                match vm.execute(sender, &[ins(opcode, amount)]) {
+                    Ok(_) if matches!(opcode, Opcode::Sell | Opcode::RemoveLiquidity) => {
+                        prop_assert!(vm.reserve_ratio_holds(), "{:?} {} left {:?}", opcode, amount, vm);
+                    }
+                    Ok(_) => {}
+                    Err(_) => prop_assert_eq!(&vm, &before),
+                }
+            }
+        }
+
+        #[test]
+        fn amm_round_trip_never_extracts_value(
+            token_reserve in 1_000..1_000_000_000i64,
+            sol_reserve in 1_000..1_000_000_000i64,
+            fee_bps in 0..1_000u16,
+            amount in 1..1_000_000i64,
+        ) {
+            let pool = AmmPool::open(token_reserve, sol_reserve, fee_bps).unwrap();
+            let Some(cost) = pool.buy_cost(amount) else {
+                return Ok(());
+            };
+            let bought = AmmPool { token_reserve: token_reserve - amount, sol_reserve: sol_reserve + cost, ..pool };
+            let out = bought.sell_proceeds(amount).unwrap();
+            prop_assert!(out <= cost);
+            let k = |p: &AmmPool| p.token_reserve as i128 * p.sol_reserve as i128;
+            prop_assert!(k(&bought) >= k(&pool));
+            let sold = AmmPool { token_reserve: bought.token_reserve + amount, sol_reserve: bought.sol_reserve - out, ..bought };
+            prop_assert!(k(&sold) >= k(&bought));
+        }
+    }
 }
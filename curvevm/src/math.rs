@@ -0,0 +1,283 @@
+pub const WAD: i128 = 1_000_000_000_000_000_000;
+
+pub const UWAD: u128 = WAD as u128;
+
+const LN2: i128 = 693_147_180_559_945_309;
+
+// Every helper that can lose precision takes an explicit direction. Callers pick
+// whichever direction leaves the pool no worse off: amounts paid in round up,
+// amounts paid out round down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+pub fn mul_div(a: i128, b: i128, d: i128) -> Option<i128> {
+    let negative = (a < 0) ^ (b < 0) ^ (d < 0);
+    let q = i128::try_from(mul_div_u(a.unsigned_abs(), b.unsigned_abs(), d.unsigned_abs(), Rounding::Down)?).ok()?;
+    Some(if negative { -q } else { q })
+}
+
+pub fn mul_div_ceil(a: i128, b: i128, d: i128) -> Option<i128> {
+    if a < 0 || b < 0 || d <= 0 {
+        return None;
+    }
+    i128::try_from(mul_div_u(a as u128, b as u128, d as u128, Rounding::Up)?).ok()
+}
+
+pub fn mul_div_u(a: u128, b: u128, d: u128, rounding: Rounding) -> Option<u128> {
+    let (hi, lo) = full_mul(a, b);
+    if hi >= d {
+        return None;
+    }
+    let (q, r) = if hi == 0 {
+        (lo / d, lo % d)
+    } else {
+        // Restoring division of the 256-bit product, one bit at a time.
+        let (mut q, mut r) = (0u128, hi);
+        for i in (0..128).rev() {
+            let carry = r >> 127;
+            r = (r << 1) | ((lo >> i) & 1);
+            q <<= 1;
+            if carry == 1 || r >= d {
+                r = r.wrapping_sub(d);
+                q |= 1;
+            }
+        }
+        (q, r)
+    };
+    if rounding == Rounding::Up && r != 0 { q.checked_add(1) } else { Some(q) }
+}
+
+pub fn div_u(a: u128, d: u128, rounding: Rounding) -> Option<u128> {
+    mul_div_u(a, 1, d, rounding)
+}
+
+pub fn wad_mul(a: u128, b: u128, rounding: Rounding) -> Option<u128> {
+    mul_div_u(a, b, UWAD, rounding)
+}
+
+pub fn wad_div(a: u128, b: u128, rounding: Rounding) -> Option<u128> {
+    mul_div_u(a, UWAD, b, rounding)
+}
+
+fn full_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+    let mid = (lo_lo >> 64) + (hi_lo & MASK) + (lo_hi & MASK);
+    let lo = (lo_lo & MASK) | (mid << 64);
+    let hi = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+    (hi, lo)
+}
+
+pub fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = 1u128 << (128 - n.leading_zeros()).div_ceil(2);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+pub fn sqrt(n: u128, rounding: Rounding) -> u128 {
+    let root = isqrt(n);
+    if rounding == Rounding::Up && root * root != n { root + 1 } else { root }
+}
+
+pub fn wad_sqrt(x: u128, rounding: Rounding) -> u128 {
+    match x.checked_mul(UWAD) {
+        Some(scaled) => sqrt(scaled, rounding),
+        // sqrt(x * 1e18) = sqrt(x) * 1e9; still rounded the requested way.
+        None => sqrt(x, rounding) * 1_000_000_000,
+    }
+}
+
+// Exact-direction integer power: every intermediate product rounds the same way,
+// so the result is a bound on the true value.
+pub fn wad_powi(base: u128, mut n: u32, rounding: Rounding) -> Option<u128> {
+    let (mut base, mut acc) = (base, UWAD);
+    while n > 0 {
+        if n & 1 == 1 {
+            acc = wad_mul(acc, base, rounding)?;
+        }
+        n >>= 1;
+        if n > 0 {
+            base = wad_mul(base, base, rounding)?;
+        }
+    }
+    Some(acc)
+}
+
+// Real exponents go through exp/ln, which are accurate to well under one part in
+// 10^12; widening by that margin turns the estimate into a bound.
+pub fn wad_pow(base: u128, exponent: u128, rounding: Rounding) -> Option<u128> {
+    if base == 0 {
+        return Some(if exponent == 0 { UWAD } else { 0 });
+    }
+    let estimate = u128::try_from(pow(i128::try_from(base).ok()?, i128::try_from(exponent).ok()?)?).ok()?;
+    let margin = estimate / 1_000_000_000_000 + 1;
+    Some(match rounding {
+        Rounding::Down => estimate.saturating_sub(margin),
+        Rounding::Up => estimate.checked_add(margin)?,
+    })
+}
+
+// The transcendental helpers return None outside the range the fixed-point series
+// covers, so callers surface an overflow instead of aborting.
+pub fn exp(x: i128) -> Option<i128> {
+    if x < -41 * WAD {
+        return Some(0);
+    }
+    if x > 88 * WAD / 2 {
+        return None;
+    }
+    let half = if x < 0 { -LN2 / 2 } else { LN2 / 2 };
+    let k = (x + half) / LN2;
+    let r = x - k * LN2;
+    let mut term = WAD;
+    let mut sum = WAD;
+    let mut i = 1;
+    while term != 0 {
+        term = term * r / (i * WAD);
+        sum += term;
+        i += 1;
+    }
+    Some(if k >= 0 { sum << k } else { sum >> -k })
+}
+
+pub fn ln(x: i128) -> Option<i128> {
+    if x <= 0 {
+        return None;
+    }
+    let mut m = x;
+    let mut k: i128 = 0;
+    while m >= 2 * WAD {
+        m >>= 1;
+        k += 1;
+    }
+    while m < WAD {
+        m <<= 1;
+        k -= 1;
+    }
+    // ln(m) = 2 * atanh((m - 1) / (m + 1)), which converges quickly for m in [1, 2).
+    let z = (m - WAD) * WAD / (m + WAD);
+    let z2 = z * z / WAD;
+    let mut term = z;
+    let mut sum = 0;
+    let mut i = 1;
+    while term != 0 {
+        sum += term / i;
+        term = term * z2 / WAD;
+        i += 2;
+    }
+    Some(2 * sum + k * LN2)
+}
+
+pub fn pow(base: i128, exponent: i128) -> Option<i128> {
+    exp(mul_div(exponent, ln(base)?, WAD)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn close(a: i128, b: i128, tolerance: i128) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    #[test]
+    fn exp_and_ln_are_accurate() {
+        assert_eq!(exp(0), Some(WAD));
+        assert!(close(exp(WAD).unwrap(), 2_718_281_828_459_045_235, 1_000));
+        assert!(close(exp(-WAD).unwrap(), 367_879_441_171_442_321, 1_000));
+        assert!(close(ln(2 * WAD).unwrap(), LN2, 1_000));
+        assert!(close(ln(exp(10 * WAD).unwrap()).unwrap(), 10 * WAD, 1_000_000));
+        assert!(close(pow(4 * WAD, WAD / 2).unwrap(), 2 * WAD, 1_000));
+    }
+
+    #[test]
+    fn out_of_range_arguments_are_rejected() {
+        assert_eq!(exp(45 * WAD), None);
+        assert_eq!(exp(-50 * WAD), Some(0));
+        assert_eq!(ln(0), None);
+        assert_eq!(ln(-WAD), None);
+        assert_eq!(pow(WAD / 1_000_000, -10 * WAD), None);
+    }
+
+    #[test]
+    fn mul_div_handles_wide_products() {
+        let a = 3 * 10i128.pow(30);
+        let b = 7 * 10i128.pow(30);
+        assert_eq!(mul_div(a, b, 10i128.pow(30)), Some(21 * 10i128.pow(30)));
+        assert_eq!(mul_div_ceil(10, 10, 3), Some(34));
+        assert_eq!(mul_div(-10, 10, 3), Some(-33));
+        assert_eq!(mul_div(a, b, 1), None);
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(15), 3);
+        assert_eq!(isqrt(16), 4);
+        assert_eq!(isqrt(u128::MAX), u64::MAX as u128);
+        assert_eq!(sqrt(15, Rounding::Up), 4);
+        assert_eq!(sqrt(16, Rounding::Up), 4);
+        assert_eq!(wad_sqrt(4 * UWAD, Rounding::Down), 2 * UWAD);
+        assert_eq!(wad_sqrt(u128::MAX, Rounding::Up), (u64::MAX as u128 + 1) * 1_000_000_000);
+    }
+
+    #[test]
+    fn powers_bound_the_true_value() {
+        let half = UWAD / 2;
+        assert_eq!(wad_powi(2 * UWAD, 10, Rounding::Down), Some(1024 * UWAD));
+        assert_eq!(wad_powi(half, 0, Rounding::Up), Some(UWAD));
+        let third = UWAD / 3;
+        let down = wad_powi(third, 3, Rounding::Down).unwrap();
+        let up = wad_powi(third, 3, Rounding::Up).unwrap();
+        assert!(down < up && up - down <= 3);
+        let root = 1_414_213_562_373_095_048u128;
+        assert!(wad_pow(2 * UWAD, half, Rounding::Down).unwrap() <= root);
+        assert!(wad_pow(2 * UWAD, half, Rounding::Up).unwrap() > root);
+    }
+
+    proptest! {
+        #[test]
+        fn rounding_brackets_the_exact_quotient(a in any::<u64>(), b in any::<u64>(), d in 1..u64::MAX) {
+            let (a, b, d) = (a as u128, b as u128, d as u128);
+            let down = mul_div_u(a, b, d, Rounding::Down).unwrap();
+            let up = mul_div_u(a, b, d, Rounding::Up).unwrap();
+            prop_assert_eq!(down, a * b / d);
+            prop_assert_eq!(up - down, u128::from(a * b % d != 0));
+        }
+
+        #[test]
+        fn wide_products_round_consistently(a in any::<u128>(), b in any::<u128>(), d in 1..u128::MAX) {
+            if let Some(down) = mul_div_u(a, b, d, Rounding::Down) {
+                let up = mul_div_u(a, b, d, Rounding::Up);
+                prop_assert!(up == Some(down) || up == down.checked_add(1));
+                prop_assert_eq!(mul_div_u(b, a, d, Rounding::Down), Some(down));
+            }
+        }
+
+        #[test]
+        fn sqrt_brackets_the_root(n in any::<u128>()) {
+            let down = sqrt(n, Rounding::Down);
+            let up = sqrt(n, Rounding::Up);
+            prop_assert!(down * down <= n);
+            prop_assert!(up.checked_mul(up).is_none_or(|sq| sq >= n));
+            prop_assert!(up - down <= 1);
+        }
+    }
+}
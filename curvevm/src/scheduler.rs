@@ -0,0 +1,189 @@
+use crate::{CurveId, CurveStore, ExecutionReceipt, Instruction, VmError};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Call {
+    pub sender: String,
+    pub program: Vec<Instruction>,
+}
+
+impl Call {
+    pub fn new(sender: &str, program: Vec<Instruction>) -> Self {
+        Self { sender: sender.to_string(), program }
+    }
+}
+
+// Partitions calls into groups that share no curve. Calls keep their block order
+// inside a group, and groups are ordered by their first call.
+pub fn schedule(calls: &[Call]) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..calls.len()).collect();
+    let mut owner: BTreeMap<CurveId, usize> = BTreeMap::new();
+    for (i, call) in calls.iter().enumerate() {
+        for ins in &call.program {
+            match owner.get(&ins.curve) {
+                Some(&j) => union(&mut parent, i, j),
+                None => {
+                    owner.insert(ins.curve, i);
+                }
+            }
+        }
+    }
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..calls.len() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+    let mut groups: Vec<_> = groups.into_values().collect();
+    groups.sort_by_key(|g| g[0]);
+    groups
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    // Point the later root at the earlier one so roots stay stable.
+    if a < b {
+        parent[b] = a;
+    } else {
+        parent[a] = b;
+    }
+}
+
+impl CurveStore {
+    // Each call commits or fails on its own, in block order.
+    pub fn execute_block(&mut self, calls: &[Call]) -> Vec<Result<ExecutionReceipt, VmError>> {
+        calls.iter().map(|call| self.execute(&call.sender, &call.program)).collect()
+    }
+
+    // Produces the same state and results as `execute_block`, running groups of calls
+    // that touch disjoint curves on the rayon thread pool.
+    pub fn execute_block_parallel(&mut self, calls: &[Call]) -> Vec<Result<ExecutionReceipt, VmError>> {
+        let groups = schedule(calls);
+        let shards: Vec<CurveStore> = groups
+            .iter()
+            .map(|group| {
+                let mut shard = CurveStore {
+                    curves: BTreeMap::new(),
+                    compute_budget: self.compute_budget,
+                    clock: self.clock,
+                };
+                for &i in group {
+                    for ins in &calls[i].program {
+                        if let Some(vm) = self.curves.remove(&ins.curve) {
+                            shard.curves.insert(ins.curve, vm);
+                        }
+                    }
+                }
+                shard
+            })
+            .collect();
+        let executed: Vec<_> = shards
+            .into_par_iter()
+            .zip(groups.par_iter())
+            .map(|(mut shard, group)| {
+                let results: Vec<_> = group.iter().map(|&i| (i, shard.execute(&calls[i].sender, &calls[i].program))).collect();
+                (shard, results)
+            })
+            .collect();
+        let mut results: Vec<Option<Result<ExecutionReceipt, VmError>>> = vec![None; calls.len()];
+        for (shard, group_results) in executed {
+            self.curves.extend(shard.curves);
+            for (i, result) in group_results {
+                results[i] = Some(result);
+            }
+        }
+        results.into_iter().map(|r| r.expect("every call belongs to a group")).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CurveVM, Opcode};
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    fn store_with(curves: &[CurveId]) -> CurveStore {
+        let mut store = CurveStore::new();
+        for &id in curves {
+            store.create(id, CurveVM::new()).unwrap();
+        }
+        store
+    }
+
+    fn random_block(rng: &mut XorShift, curves: &[CurveId], senders: &[&str], len: usize) -> Vec<Call> {
+        let opcodes = [Opcode::Buy, Opcode::Buy, Opcode::Sell, Opcode::AddLiquidity, Opcode::MigrateToAmm];
+        (0..len)
+            .map(|_| {
+                let sender = senders[rng.below(senders.len() as u64) as usize];
+                let program = (0..1 + rng.below(3))
+                    .map(|_| Instruction {
+                        opcode: opcodes[rng.below(opcodes.len() as u64) as usize],
+                        operand: rng.below(50) as i64,
+                        curve: curves[rng.below(curves.len() as u64) as usize],
+                        limit: 0,
+                        expiry: 0,
+                    })
+                    .collect();
+                Call::new(sender, program)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn schedule_groups_by_shared_curves() {
+        let [a, b, c] = [CurveId::derive("x", "a"), CurveId::derive("x", "b"), CurveId::derive("x", "c")];
+        let on = |curve| Instruction { opcode: Opcode::Buy, operand: 1, curve, limit: 0, expiry: 0 };
+        let calls = vec![
+            Call::new("u", vec![on(a)]),
+            Call::new("u", vec![on(b)]),
+            Call::new("u", vec![on(c)]),
+            Call::new("v", vec![on(b), on(c)]),
+            Call::new("v", vec![on(a)]),
+        ];
+        assert_eq!(schedule(&calls), vec![vec![0, 4], vec![1, 2, 3]]);
+        assert!(schedule(&[]).is_empty());
+    }
+
+    #[test]
+    fn parallel_matches_sequential_on_random_blocks() {
+        let curves: Vec<CurveId> = (0..6).map(|i| CurveId::derive("creator", &i.to_string())).collect();
+        let senders = ["alice", "bob", "carol", "dave"];
+        for seed in 1..=64u64 {
+            let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut sequential = store_with(&curves);
+            let mut parallel = sequential.clone();
+            // Unknown curves must fail identically on both paths.
+            let mut known = curves.clone();
+            known.push(CurveId::derive("nobody", "ghost"));
+            for _ in 0..4 {
+                let block = random_block(&mut rng, &known, &senders, 40);
+                let expected = sequential.execute_block(&block);
+                let actual = parallel.execute_block_parallel(&block);
+                assert_eq!(expected, actual, "seed {}", seed);
+                assert_eq!(sequential.root(), parallel.root(), "seed {}", seed);
+            }
+        }
+    }
+}
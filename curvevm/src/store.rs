@@ -0,0 +1,92 @@
+use crate::{Clock, CurveId, CurveVM, DEFAULT_COMPUTE_BUDGET, ExecutionReceipt, Instruction, VmError};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub fn curves_touched(program: &[Instruction]) -> BTreeSet<CurveId> {
+    program.iter().map(|ins| ins.curve).collect()
+}
+
+// Every account lives inside a single curve, so programs only conflict when they
+// target a common curve.
+pub fn conflicts(a: &[Instruction], b: &[Instruction]) -> bool {
+    let touched = curves_touched(a);
+    b.iter().any(|ins| touched.contains(&ins.curve))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct CurveStore {
+    pub curves: BTreeMap<CurveId, CurveVM>,
+    pub compute_budget: u64,
+    // Block the next execution runs in; it stays out of the root.
+    #[borsh_skip]
+    pub clock: Clock,
+}
+
+impl CurveStore {
+    pub fn new() -> Self {
+        Self { curves: BTreeMap::new(), compute_budget: DEFAULT_COMPUTE_BUDGET, clock: Clock::default() }
+    }
+
+    // The curve's launch window is counted from the current block height.
+    pub fn create(&mut self, id: CurveId, mut vm: CurveVM) -> Result<(), VmError> {
+        if self.curves.contains_key(&id) {
+            return Err(VmError::DuplicateCurve(id));
+        }
+        // A VM can be assembled field by field, so its curve is checked again here.
+        if !vm.curve.is_valid() {
+            return Err(VmError::InvalidCurve);
+        }
+        vm.created_at = self.clock.height;
+        self.curves.insert(id, vm);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &CurveId) -> Option<&CurveVM> {
+        self.curves.get(id)
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        let encoded = self.try_to_vec().expect("in-memory serialization cannot fail");
+        Sha256::digest(encoded).into()
+    }
+
+    // Runs the program against every curve it names. All touched curves commit
+    // together or not at all.
+    pub fn execute(&mut self, sender: &str, program: &[Instruction]) -> Result<ExecutionReceipt, VmError> {
+        let mut touched: BTreeMap<CurveId, CurveVM> = BTreeMap::new();
+        let mut receipt = ExecutionReceipt { sender: sender.to_string(), ..Default::default() };
+        for ins in program {
+            run_touched(&self.curves, &mut touched, ins, &mut receipt, self.compute_budget, self.clock)?;
+        }
+        self.curves.extend(touched);
+        Ok(receipt)
+    }
+}
+
+// Runs one instruction against a working copy of its curve, cloning the curve
+// out of `curves` the first time the program touches it.
+pub(crate) fn run_touched(
+    curves: &BTreeMap<CurveId, CurveVM>,
+    touched: &mut BTreeMap<CurveId, CurveVM>,
+    ins: &Instruction,
+    receipt: &mut ExecutionReceipt,
+    budget: u64,
+    clock: Clock,
+) -> Result<(), VmError> {
+    let vm = match touched.entry(ins.curve) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let vm = curves.get(&ins.curve).ok_or(VmError::UnknownCurve(ins.curve))?;
+            entry.insert(vm.clone())
+        }
+    };
+    vm.run(ins, receipt, budget, clock)
+}
+
+impl Default for CurveStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{AmmPool, CurveId, CurveVM, Instruction};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CurveSnapshot {
+    pub supply: i64,
+    pub reserve: i64,
+    pub liquidity: i64,
+    pub migrated_to_amm: bool,
+    pub amm: AmmPool,
+    pub price: i128,
+}
+
+impl CurveSnapshot {
+    pub fn of(vm: &CurveVM) -> Self {
+        Self {
+            supply: vm.supply,
+            reserve: vm.reserve,
+            liquidity: vm.liquidity,
+            migrated_to_amm: vm.migrated_to_amm,
+            amm: vm.amm,
+            price: vm.price(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    pub index: usize,
+    pub instruction: Instruction,
+    pub pre: CurveSnapshot,
+    pub post: CurveSnapshot,
+    pub tokens_moved: i64,
+    pub sol_moved: i64,
+    pub compute_units: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Trade {
+        curve: CurveId,
+        trader: String,
+        side: Side,
+        tokens: i64,
+        sol: i64,
+        fee: i64,
+        via_amm: bool,
+    },
+    LiquidityAdded {
+        curve: CurveId,
+        provider: String,
+        sol: i64,
+        tokens: i64,
+        lp_shares: i64,
+    },
+    Migrated {
+        curve: CurveId,
+        token_reserve: i64,
+        sol_reserve: i64,
+        lp_supply: i64,
+        automatic: bool,
+    },
+    LiquidityRemoved {
+        curve: CurveId,
+        provider: String,
+        sol: i64,
+        tokens: i64,
+        lp_shares: i64,
+    },
+    Claimed {
+        curve: CurveId,
+        beneficiary: String,
+        tokens: i64,
+    },
+    FeesClaimed {
+        curve: CurveId,
+        recipient: String,
+        sol: i64,
+    },
+}
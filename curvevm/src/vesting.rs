@@ -0,0 +1,189 @@
+use crate::{CurveVM, ExecutionReceipt, VmError, math};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+// Tokens vest linearly from `start` to `start + duration`, but nothing can be
+// claimed before `start + cliff`. Times are block timestamps in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct VestingSchedule {
+    pub total: i64,
+    pub claimed: i64,
+    pub start: u64,
+    pub cliff: u64,
+    pub duration: u64,
+}
+
+impl VestingSchedule {
+    pub fn new(total: i64, start: u64, cliff: u64, duration: u64) -> Self {
+        Self { total, claimed: 0, start, cliff, duration }
+    }
+
+    pub fn vested_at(&self, now: u64) -> i64 {
+        let elapsed = now.saturating_sub(self.start);
+        if elapsed < self.cliff {
+            0
+        } else if elapsed >= self.duration {
+            self.total
+        } else {
+            math::mul_div(self.total as i128, elapsed as i128, self.duration as i128).expect("fits below total") as i64
+        }
+    }
+
+    pub fn claimable_at(&self, now: u64) -> i64 {
+        self.vested_at(now) - self.claimed
+    }
+
+    pub fn unclaimed(&self) -> i64 {
+        self.total - self.claimed
+    }
+}
+
+impl CurveVM {
+    // Allocations are carved out of `max_supply`, so the curve can never sell
+    // tokens that are promised to a creator. Once the curve migrates, whatever
+    // was left over sits in the pool and nothing more can be allocated.
+    pub fn add_vesting(&mut self, beneficiary: &str, schedule: VestingSchedule) -> Result<(), VmError> {
+        if self.migrated_to_amm {
+            return Err(VmError::AllocationAfterMigration);
+        }
+        if schedule.total <= 0 || schedule.claimed != 0 || schedule.cliff > schedule.duration {
+            return Err(VmError::InvalidSchedule);
+        }
+        if self.vesting.contains_key(beneficiary) {
+            return Err(VmError::InvalidSchedule);
+        }
+        let available = self.unallocated_supply();
+        if schedule.total > available {
+            return Err(VmError::SupplyExhausted { requested: schedule.total, available });
+        }
+        self.vesting.insert(beneficiary.to_string(), schedule);
+        Ok(())
+    }
+
+    pub fn locked_allocation(&self) -> i64 {
+        self.vesting.values().map(VestingSchedule::unclaimed).sum()
+    }
+
+    pub(crate) fn unallocated_supply(&self) -> i64 {
+        self.max_supply - self.supply - self.locked_allocation()
+    }
+
+    // Vested tokens are released only once the curve has migrated: minted earlier,
+    // they would be curve supply no buyer paid into the reserve.
+    pub(crate) fn claim(&mut self, amount: i64, now: u64, receipt: &mut ExecutionReceipt) -> Result<(), VmError> {
+        if !self.migrated_to_amm {
+            return Err(VmError::NotMigrated);
+        }
+        let schedule = self.vesting.get_mut(&receipt.sender).ok_or(VmError::NoVesting)?;
+        let claimable = schedule.claimable_at(now);
+        if amount > claimable {
+            return Err(VmError::EarlyClaim { requested: amount, claimable });
+        }
+        schedule.claimed += amount;
+        self.supply = self.supply.checked_add(amount).ok_or(VmError::Overflow)?;
+        let account = self.accounts.entry(receipt.sender.clone()).or_default();
+        account.tokens = account.tokens.checked_add(amount).ok_or(VmError::Overflow)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, Curve, CurveId, Event, Instruction, Opcode, StateDiff};
+
+    const DAY: u64 = 86_400;
+
+    fn ins(opcode: Opcode, operand: i64) -> Instruction {
+        Instruction { opcode, operand, curve: CurveId::default(), limit: 0, expiry: 0 }
+    }
+
+    fn claim(amount: i64) -> Instruction {
+        ins(Opcode::Claim, amount)
+    }
+
+    fn at(timestamp: u64) -> Clock {
+        Clock { height: 0, timestamp }
+    }
+
+    fn vm() -> CurveVM {
+        let mut vm = CurveVM::new();
+        vm.max_supply = 1_000_000;
+        vm.add_vesting("creator", VestingSchedule::new(100_000, 1_000, 30 * DAY, 100 * DAY)).unwrap();
+        vm
+    }
+
+    fn migrated() -> CurveVM {
+        let mut vm = vm();
+        vm.execute("alice", &[ins(Opcode::Buy, 10), ins(Opcode::MigrateToAmm, 0)]).unwrap();
+        vm
+    }
+
+    #[test]
+    fn schedule_has_cliff_then_linear_release() {
+        let s = VestingSchedule::new(100_000, 1_000, 30 * DAY, 100 * DAY);
+        assert_eq!(s.vested_at(0), 0);
+        assert_eq!(s.vested_at(1_000 + 30 * DAY - 1), 0);
+        assert_eq!(s.vested_at(1_000 + 30 * DAY), 30_000);
+        assert_eq!(s.vested_at(1_000 + 50 * DAY + 1), 50_000);
+        assert_eq!(s.vested_at(1_000 + 100 * DAY), 100_000);
+        assert_eq!(s.vested_at(u64::MAX), 100_000);
+    }
+
+    #[test]
+    fn claims_release_only_vested_tokens() {
+        let mut vm = migrated();
+        let err = vm.execute_at("creator", &[claim(1)], at(1_000 + 10 * DAY)).unwrap_err();
+        assert_eq!(err, VmError::EarlyClaim { requested: 1, claimable: 0 });
+
+        let now = at(1_000 + 40 * DAY);
+        let before = vm.clone();
+        let receipt = vm.execute_at("creator", &[claim(30_000), claim(10_000)], now).unwrap();
+        let diff = StateDiff::between(&before, &vm);
+        assert_eq!(diff.vesting[0].after.unwrap().claimed, 40_000);
+        let mut replay = before;
+        diff.apply(&mut replay).unwrap();
+        assert_eq!(replay, vm);
+        assert_eq!(vm.balance_of("creator"), 40_000);
+        assert_eq!(vm.supply, 40_010);
+        assert_eq!(receipt.events[1], Event::Claimed { curve: CurveId::default(), beneficiary: "creator".into(), tokens: 10_000 });
+        let err = vm.execute_at("creator", &[claim(1)], now).unwrap_err();
+        assert_eq!(err, VmError::EarlyClaim { requested: 1, claimable: 0 });
+
+        vm.execute_at("creator", &[claim(60_000)], at(1_000 + 365 * DAY)).unwrap();
+        assert_eq!(vm.locked_allocation(), 0);
+        assert_eq!(vm.execute_at("mallory", &[claim(1)], at(u64::MAX)).unwrap_err(), VmError::NoVesting);
+    }
+
+    #[test]
+    fn vesting_is_released_only_after_migration() {
+        let mut vm = CurveVM::new();
+        vm.max_supply = 1_000_000;
+        vm.add_vesting("creator", VestingSchedule::new(100_000, 0, 0, 1)).unwrap();
+        vm.execute("alice", &[ins(Opcode::Buy, 10)]).unwrap();
+        assert_eq!(vm.execute_at("creator", &[claim(1)], at(u64::MAX)).unwrap_err(), VmError::NotMigrated);
+        assert_eq!((vm.supply, vm.reserve), (10, vm.curve.reserve_at(10).unwrap() as i64));
+        vm.execute_at("creator", &[ins(Opcode::MigrateToAmm, 0), claim(100_000)], at(u64::MAX)).unwrap();
+        assert_eq!(vm.balance_of("creator"), 100_000);
+    }
+
+    #[test]
+    fn allocations_are_reserved_from_the_curve() {
+        let mut vm = vm();
+        let err = vm.execute("alice", &[ins(Opcode::Buy, 900_001)]);
+        assert_eq!(err.unwrap_err(), VmError::SupplyExhausted { requested: 900_001, available: 900_000 });
+        let err = vm.add_vesting("advisor", VestingSchedule::new(900_001, 0, 0, 1));
+        assert_eq!(err.unwrap_err(), VmError::SupplyExhausted { requested: 900_001, available: 900_000 });
+        assert_eq!(vm.add_vesting("creator", VestingSchedule::new(1, 0, 0, 1)), Err(VmError::InvalidSchedule));
+        assert_eq!(vm.add_vesting("team", VestingSchedule::new(1, 0, 2, 1)), Err(VmError::InvalidSchedule));
+
+        vm.execute("alice", &[ins(Opcode::MigrateToAmm, 0)]).unwrap_err();
+        vm.execute("alice", &[ins(Opcode::Buy, 10)]).unwrap();
+        vm.execute("alice", &[ins(Opcode::MigrateToAmm, 0)]).unwrap();
+        assert_eq!(vm.amm.token_reserve, 1_000_000 - 10 - 100_000);
+        vm.execute_at("creator", &[claim(100_000)], at(u64::MAX)).unwrap();
+        assert_eq!(vm.supply + vm.amm.token_reserve, vm.max_supply);
+        // The pool already holds every token left over, so there is nothing to allocate.
+        let late = vm.add_vesting("advisor", VestingSchedule::new(1, 0, 0, 1));
+        assert_eq!(late, Err(VmError::AllocationAfterMigration));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::store::run_touched;
+use crate::{Clock, CurveId, CurveStore, CurveVM, ExecutionReceipt, Instruction, Opcode, VmError};
+use std::collections::BTreeMap;
+use wasmi::core::Trap;
+use wasmi::{Caller, Config, Engine, Extern, Linker, Module, Store};
+
+pub const IMPORT_MODULE: &str = "curvevm";
+pub const RUN_EXPORT: &str = "run";
+pub const MEMORY_EXPORT: &str = "memory";
+
+// Every opcode is imported as
+// `curvevm.<mnemonic>(curve_ptr: i32, operand: i64, limit: i64, expiry: i64)`,
+// where `curve_ptr` points at the 32-byte curve ID in the module's memory and
+// `expiry` carries the u64 height bit for bit.
+pub fn import_name(opcode: Opcode) -> String {
+    opcode.mnemonic().to_ascii_lowercase()
+}
+
+struct Host {
+    curves: BTreeMap<CurveId, CurveVM>,
+    touched: BTreeMap<CurveId, CurveVM>,
+    receipt: ExecutionReceipt,
+    budget: u64,
+    clock: Clock,
+    error: Option<VmError>,
+}
+
+impl Host {
+    fn call(&mut self, ins: Instruction) -> Result<(), Trap> {
+        run_touched(&self.curves, &mut self.touched, &ins, &mut self.receipt, self.budget, self.clock).map_err(|err| {
+            let trap = Trap::new(err.to_string());
+            self.error = Some(err);
+            trap
+        })
+    }
+}
+
+fn import(
+    caller: &mut Caller<'_, Host>,
+    opcode: Opcode,
+    curve_ptr: i32,
+    operand: i64,
+    limit: i64,
+    expiry: i64,
+) -> Result<(), Trap> {
+    let memory = caller
+        .get_export(MEMORY_EXPORT)
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("module does not export its memory"))?;
+    let mut id = [0u8; 32];
+    memory.read(&*caller, curve_ptr as u32 as usize, &mut id).map_err(|_| Trap::new("curve id is out of bounds"))?;
+    caller.data_mut().call(Instruction { opcode, operand, curve: CurveId(id), limit, expiry: expiry as u64 })
+}
+
+impl CurveStore {
+    // Runs a compiled module under wasmi. Host imports are metered exactly like
+    // native instructions, so receipts and state match `execute` byte for byte.
+    // Wasm fuel is capped at the compute budget to bound loops between imports.
+    pub fn execute_wasm(&mut self, sender: &str, module: &[u8]) -> Result<ExecutionReceipt, VmError> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, module).map_err(|e| VmError::InvalidModule(e.to_string()))?;
+        let host = Host {
+            curves: std::mem::take(&mut self.curves),
+            touched: BTreeMap::new(),
+            receipt: ExecutionReceipt { sender: sender.to_string(), ..Default::default() },
+            budget: self.compute_budget,
+            clock: self.clock,
+            error: None,
+        };
+        let mut store = Store::new(&engine, host);
+        store.add_fuel(self.compute_budget).expect("fuel metering is enabled");
+        let result = instantiate_and_run(&engine, &mut store, &module);
+        let host = store.into_data();
+        self.curves = host.curves;
+        match (result, host.error) {
+            (_, Some(err)) => Err(err),
+            (Err(err), None) => Err(err),
+            (Ok(()), None) => {
+                self.curves.extend(host.touched);
+                Ok(host.receipt)
+            }
+        }
+    }
+}
+
+fn instantiate_and_run(engine: &Engine, store: &mut Store<Host>, module: &Module) -> Result<(), VmError> {
+    let mut linker = Linker::<Host>::new(engine);
+    for opcode in Opcode::ALL {
+        linker
+            .func_wrap(
+                IMPORT_MODULE,
+                &import_name(opcode),
+                move |mut caller: Caller<'_, Host>, ptr: i32, operand: i64, limit: i64, expiry: i64| {
+                    import(&mut caller, opcode, ptr, operand, limit, expiry)
+                },
+            )
+            .expect("import names are unique");
+    }
+    let instance = linker
+        .instantiate(&mut *store, module)
+        .and_then(|pre| pre.ensure_no_start(&mut *store).map_err(Into::into))
+        .map_err(|e| VmError::InvalidModule(e.to_string()))?;
+    let run = instance
+        .get_typed_func::<(), ()>(&*store, RUN_EXPORT)
+        .map_err(|e| VmError::InvalidModule(e.to_string()))?;
+    run.call(&mut *store, ()).map_err(|e| VmError::WasmTrap(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> CurveStore {
+        let mut store = CurveStore::new();
+        store.create(CurveId::default(), CurveVM::new()).unwrap();
+        store
+    }
+
+    #[test]
+    fn runs_hand_written_modules() {
+        let wat = r#"(module
+            (import "curvevm" "buy" (func $buy (param i32 i64 i64 i64)))
+            (import "curvevm" "sell" (func $sell (param i32 i64 i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "run")
+                (call $buy (i32.const 0) (i64.const 9) (i64.const 0) (i64.const 0))
+                (call $sell (i32.const 0) (i64.const 4) (i64.const 0) (i64.const 0))))"#;
+        let mut wasm_store = store();
+        let receipt = wasm_store.execute_wasm("alice", &wat::parse_str(wat).unwrap()).unwrap();
+        let mut native = store();
+        let program = [
+            Instruction { opcode: Opcode::Buy, operand: 9, curve: CurveId::default(), limit: 0, expiry: 0 },
+            Instruction { opcode: Opcode::Sell, operand: 4, curve: CurveId::default(), limit: 0, expiry: 0 },
+        ];
+        assert_eq!(receipt, native.execute("alice", &program).unwrap());
+        assert_eq!(wasm_store, native);
+    }
+
+    #[test]
+    fn failed_modules_leave_state_untouched() {
+        let oversell = r#"(module
+            (import "curvevm" "buy" (func $buy (param i32 i64 i64 i64)))
+            (import "curvevm" "sell" (func $sell (param i32 i64 i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "run")
+                (call $buy (i32.const 0) (i64.const 3) (i64.const 0) (i64.const 0))
+                (call $sell (i32.const 0) (i64.const 5) (i64.const 0) (i64.const 0))))"#;
+        let mut s = store();
+        let before = s.clone();
+        let err = s.execute_wasm("alice", &wat::parse_str(oversell).unwrap()).unwrap_err();
+        assert!(matches!(err, VmError::InsufficientBalance { requested: 5, available: 3 }));
+        assert_eq!(s, before);
+
+        let spin = r#"(module (memory (export "memory") 1) (func (export "run") (loop $l (br $l))))"#;
+        let err = s.execute_wasm("alice", &wat::parse_str(spin).unwrap()).unwrap_err();
+        assert!(matches!(err, VmError::WasmTrap(_)));
+
+        let stray = r#"(module
+            (import "curvevm" "buy" (func $buy (param i32 i64 i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "run") (call $buy (i32.const 65530) (i64.const 1) (i64.const 0) (i64.const 0))))"#;
+        assert!(matches!(s.execute_wasm("alice", &wat::parse_str(stray).unwrap()), Err(VmError::WasmTrap(_))));
+
+        let foreign = r#"(module (import "env" "abort" (func)) (func (export "run")))"#;
+        assert!(matches!(s.execute_wasm("alice", &wat::parse_str(foreign).unwrap()), Err(VmError::InvalidModule(_))));
+        assert!(matches!(s.execute_wasm("alice", b"not wasm"), Err(VmError::InvalidModule(_))));
+        assert_eq!(s, before);
+    }
+}
@@ -19,12 +19,12 @@ impl HotShotConsensus {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use curvevm::Opcode;
+    use curvevm::{CurveId, Opcode};
 
     #[test]
     fn commits_blocks() {
         let mut hs = HotShotConsensus::new();
-        let block = vec![Instruction { opcode: Opcode::Buy, operand: 1 }];
+        let block = vec![Instruction { opcode: Opcode::Buy, operand: 1, curve: CurveId::default(), limit: 0, expiry: 0 }];
         let h1 = hs.commit_block(&block);
         let h2 = hs.commit_block(&block);
         assert_eq!(h1, 1);
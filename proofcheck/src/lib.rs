@@ -0,0 +1,533 @@
+#[cfg(feature = "vm")]
+use curvevm::{Clock, CurveVM};
+use curvevm::{Curve, CurveKind, Instruction, MigrationThreshold, Opcode, PRICE_SCALE, bytecode, math};
+use serde::Deserialize;
+use std::fmt;
+
+// Re-checks a `proof.json` from the compiler's proof engine without trusting the
+// compiler: every step is recomputed from the bounds the certificate claims before
+// it, its safety conditions are checked against those bounds, and the bounds it
+// claims after must contain the recomputed ones. Only the curve maths is shared.
+pub const VERSION: u8 = 1;
+
+const MAX: i128 = i64::MAX as i128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Interval {
+    pub lo: i128,
+    pub hi: i128,
+}
+
+impl Interval {
+    fn exact(value: i128) -> Self {
+        Self { lo: value, hi: value }
+    }
+
+    fn within(&self, lo: i128, hi: i128) -> bool {
+        lo <= self.lo && self.lo <= self.hi && self.hi <= hi
+    }
+
+    fn covers(&self, other: Interval) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Curve,
+    MaybeMigrated,
+    Migrated,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bounds {
+    pub phase: Phase,
+    pub supply: Interval,
+    pub reserve: Interval,
+    pub deficit: Interval,
+    pub locked: Interval,
+    pub liquidity: Interval,
+    pub balance: Interval,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub index: usize,
+    pub opcode: String,
+    pub operand: i64,
+    pub sol: Interval,
+    pub after: Bounds,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Certificate {
+    pub version: u8,
+    pub code_hash: String,
+    pub curve: CurveKind,
+    pub max_supply: i64,
+    pub migration_threshold: Option<MigrationThreshold>,
+    pub initial: Bounds,
+    pub steps: Vec<Step>,
+}
+
+// `step` is the first instruction whose claim does not hold, or `None` when the
+// certificate as a whole is malformed or does not belong to the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub step: Option<usize>,
+    pub reason: String,
+}
+
+impl Rejection {
+    fn certificate(reason: impl Into<String>) -> Self {
+        Self { step: None, reason: reason.into() }
+    }
+
+    fn step(step: usize, reason: impl Into<String>) -> Self {
+        Self { step: Some(step), reason: reason.into() }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.step {
+            Some(step) => write!(f, "Rejected at step {}: {}", step, self.reason),
+            None => write!(f, "Rejected certificate: {}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+pub fn check(program: &[Instruction], certificate: &str) -> Result<Certificate, Rejection> {
+    let cert: Certificate =
+        serde_json::from_str(certificate).map_err(|err| Rejection::certificate(format!("unreadable: {}", err)))?;
+    if cert.version != VERSION {
+        return Err(Rejection::certificate(format!("unsupported version {}", cert.version)));
+    }
+    let hash: String = bytecode::code_hash(program).iter().map(|b| format!("{:02x}", b)).collect();
+    if cert.code_hash != hash {
+        return Err(Rejection::certificate("the code hash does not match the program"));
+    }
+    if cert.steps.len() != program.len() {
+        return Err(Rejection::certificate(format!(
+            "{} steps for a program of {} instructions",
+            cert.steps.len(),
+            program.len()
+        )));
+    }
+    if !cert.curve.is_valid() || cert.max_supply < 0 || cert.max_supply > cert.curve.max_supply() {
+        return Err(Rejection::certificate("the curve parameters are out of range"));
+    }
+    // A shifting weight prices each height differently; no single proof covers them.
+    if !cert.curve.is_settled() {
+        return Err(Rejection::certificate("the curve's weights still shift with the block height"));
+    }
+    well_formed(&cert, &cert.initial).map_err(Rejection::certificate)?;
+    // Once the pool is open the curve reserve has been emptied into it.
+    let derived = match cert.initial.phase {
+        Phase::Curve => derive_reserve(&cert.curve, cert.initial.supply, cert.initial.deficit)
+            .ok_or_else(|| Rejection::certificate("the initial reserve can overflow"))?,
+        _ => Interval::exact(0),
+    };
+    if !cert.initial.reserve.covers(derived) {
+        return Err(Rejection::certificate(format!(
+            "the initial reserve {} does not cover {}",
+            cert.initial.reserve, derived
+        )));
+    }
+
+    let mut before = cert.initial;
+    for (i, (ins, claim)) in program.iter().zip(&cert.steps).enumerate() {
+        if claim.index != i || claim.opcode != ins.opcode.mnemonic() || claim.operand != ins.operand {
+            return Err(Rejection::step(i, "the step does not describe the instruction"));
+        }
+        if ins.curve != program[0].curve {
+            return Err(Rejection::step(i, "the instruction targets a different curve"));
+        }
+        let (sol, after) = apply(&cert, &before, ins).map_err(|reason| Rejection::step(i, reason))?;
+        well_formed(&cert, &claim.after).map_err(|reason| Rejection::step(i, reason))?;
+        let claimed = &claim.after;
+        let fields = [
+            ("sol", claim.sol, sol),
+            ("supply", claimed.supply, after.supply),
+            ("reserve", claimed.reserve, after.reserve),
+            ("deficit", claimed.deficit, after.deficit),
+            ("locked", claimed.locked, after.locked),
+            ("liquidity", claimed.liquidity, after.liquidity),
+            ("balance", claimed.balance, after.balance),
+        ];
+        if let Some((name, claimed, actual)) = fields.into_iter().find(|(_, claimed, actual)| !claimed.covers(*actual))
+        {
+            return Err(Rejection::step(i, format!("claims {} {} but it can reach {}", name, claimed, actual)));
+        }
+        if claimed.phase != after.phase {
+            return Err(Rejection::step(i, format!("claims phase {:?} but it is {:?}", claimed.phase, after.phase)));
+        }
+        before = claim.after;
+    }
+    Ok(cert)
+}
+
+pub fn check_bytecode(bytes: &[u8], certificate: &str) -> Result<Certificate, Rejection> {
+    let (_, program) = bytecode::decode(bytes).map_err(|err| Rejection::certificate(err.to_string()))?;
+    check(&program, certificate)
+}
+
+// Checks the certificate and that it speaks about `vm` as the program will find
+// it at `clock`: the same curve priced at that height, and a state inside the
+// bounds the proof started from.
+#[cfg(feature = "vm")]
+pub fn check_for(
+    vm: &CurveVM,
+    clock: Clock,
+    sender: &str,
+    program: &[Instruction],
+    certificate: &str,
+) -> Result<(), Rejection> {
+    let cert = check(program, certificate)?;
+    let mut live = vm.clone();
+    live.sync_clock(clock);
+    let vm = &live;
+    if schedule(cert.curve) != schedule(vm.curve)
+        || cert.max_supply != vm.max_supply
+        || cert.migration_threshold != vm.migration_threshold
+    {
+        return Err(Rejection::certificate("proved against a different curve"));
+    }
+    // Both schedules have settled, so they price alike from here on.
+    if !vm.curve.is_settled() {
+        return Err(Rejection::certificate(format!("the certificate does not cover height {}", clock.height)));
+    }
+    let phase = if vm.migrated_to_amm { Phase::Migrated } else { Phase::Curve };
+    let deficit = if vm.migrated_to_amm {
+        0
+    } else {
+        vm.curve.reserve_at(vm.supply).map_or(i128::MAX, |r| r - vm.reserve as i128)
+    };
+    let state = [
+        ("supply", cert.initial.supply, vm.supply as i128),
+        ("reserve", cert.initial.reserve, vm.reserve as i128),
+        ("deficit", cert.initial.deficit, deficit),
+        ("locked", cert.initial.locked, vm.locked_allocation() as i128),
+        ("liquidity", cert.initial.liquidity, vm.liquidity as i128),
+        ("balance", cert.initial.balance, vm.balance_of(sender) as i128),
+    ];
+    if let Some((name, bound, value)) =
+        state.into_iter().find(|(_, bound, value)| !bound.covers(Interval::exact(*value)))
+    {
+        return Err(Rejection::certificate(format!("the {} {} is outside {}", name, value, bound)));
+    }
+    if cert.initial.phase != phase {
+        return Err(Rejection::certificate("the curve's migration state is outside the proof"));
+    }
+    Ok(())
+}
+
+// The curve with an LBP's clock wound back to the start of its schedule.
+#[cfg(feature = "vm")]
+fn schedule(curve: CurveKind) -> CurveKind {
+    match curve {
+        CurveKind::Lbp(c) => CurveKind::Lbp(c.at(0)),
+        other => other,
+    }
+}
+
+// Bounds on what the curve can hold at all; anything outside them is an overflow
+// or a negative balance the certificate is claiming away.
+fn well_formed(cert: &Certificate, b: &Bounds) -> Result<(), String> {
+    let max_supply = cert.max_supply as i128;
+    let checks = [
+        ("supply", b.supply.within(0, max_supply)),
+        ("reserve", b.reserve.within(0, MAX)),
+        ("deficit", b.deficit.within(i128::MIN, i128::MAX)),
+        ("locked", b.locked.within(0, max_supply)),
+        ("liquidity", b.liquidity.within(0, MAX)),
+        ("balance", b.balance.within(0, max_supply)),
+    ];
+    if let Some((name, _)) = checks.into_iter().find(|(_, ok)| !ok) {
+        return Err(format!("the {} bounds are out of range", name));
+    }
+    // Circulating and still-vesting tokens share the max supply.
+    if b.supply.hi + b.locked.lo > max_supply {
+        return Err("the supply and locked bounds exceed the max supply".into());
+    }
+    Ok(())
+}
+
+fn apply(cert: &Certificate, b: &Bounds, ins: &Instruction) -> Result<(Interval, Bounds), String> {
+    if ins.operand < 0 {
+        return Err("the operand is negative".into());
+    }
+    let n = ins.operand as i128;
+    let curve = &cert.curve;
+    let curve_only = !matches!(ins.opcode, Opcode::Claim | Opcode::ClaimFees);
+    if curve_only && b.phase != Phase::Curve {
+        return Err(format!("{} may run after migration", ins.opcode.mnemonic()));
+    }
+    let mut a = *b;
+    let sol = match ins.opcode {
+        Opcode::Buy => {
+            let top = (cert.max_supply as i128 - b.locked.lo - n).min(b.supply.hi);
+            if top < b.supply.lo {
+                return Err("the buy never fits in the unallocated supply".into());
+            }
+            let from = Interval { lo: b.supply.lo, hi: top };
+            a.supply = Interval { lo: from.lo + n, hi: from.hi + n };
+            a.balance = Interval { lo: b.balance.lo + n, hi: b.balance.hi + n };
+            a.reserve = derive_reserve(curve, a.supply, b.deficit).ok_or("the reserve can overflow")?;
+            let cost = cost(curve, from, n).ok_or("the cost can overflow")?;
+            let reached = |s: i128, r: i128| match cert.migration_threshold {
+                Some(MigrationThreshold::Reserve(target)) => r >= target as i128,
+                Some(MigrationThreshold::MarketCap(target)) => market_cap(curve, s) >= target,
+                None => false,
+            };
+            let always = reached(a.supply.lo, a.reserve.lo);
+            if reached(a.supply.hi, a.reserve.hi) {
+                if a.reserve.hi + b.liquidity.hi > MAX {
+                    return Err("the pool reserve can overflow".into());
+                }
+                a.phase = if always { Phase::Migrated } else { Phase::MaybeMigrated };
+                a.reserve.lo = 0;
+                a.liquidity.lo = 0;
+                if always {
+                    a.reserve.hi = 0;
+                    a.liquidity.hi = 0;
+                }
+            }
+            cost
+        }
+        Opcode::Sell => {
+            if b.balance.lo < n || b.supply.lo < n {
+                return Err(format!("sells {} but the sender may hold {}", n, b.balance.lo));
+            }
+            a.supply = Interval { lo: b.supply.lo - n, hi: b.supply.hi - n };
+            a.balance = Interval { lo: b.balance.lo - n, hi: b.balance.hi - n };
+            // reserve - proceeds = reserve_at(supply - n) - deficit
+            let left = reserve_at(curve, a.supply.lo).ok_or("the reserve can overflow")?;
+            if left < b.deficit.hi {
+                return Err("the proceeds can exceed the reserve".into());
+            }
+            a.reserve = derive_reserve(curve, a.supply, b.deficit).ok_or("the reserve can overflow")?;
+            cost(curve, a.supply, n).ok_or("the proceeds can overflow")?
+        }
+        Opcode::AddLiquidity => {
+            a.liquidity = Interval { lo: b.liquidity.lo + n, hi: b.liquidity.hi + n };
+            if a.liquidity.hi > MAX {
+                return Err("the liquidity can overflow".into());
+            }
+            Interval::exact(n)
+        }
+        Opcode::MigrateToAmm => {
+            if cert.max_supply as i128 - b.supply.lo - b.locked.lo <= 0 {
+                return Err("no supply is left to seed the pool".into());
+            }
+            let seed = Interval { lo: b.reserve.lo + b.liquidity.lo, hi: b.reserve.hi + b.liquidity.hi };
+            if seed.hi > MAX {
+                return Err("the pool reserve can overflow".into());
+            }
+            a.phase = Phase::Migrated;
+            a.reserve = Interval::exact(0);
+            a.liquidity = Interval::exact(0);
+            seed
+        }
+        Opcode::Claim => {
+            if b.phase == Phase::Curve {
+                return Err("vesting is only released after migration".into());
+            }
+            if b.locked.hi < n {
+                return Err("claims more than is vesting".into());
+            }
+            // The claimed tokens were part of the max supply all along.
+            let top = b.supply.hi.min(cert.max_supply as i128 - n);
+            if top < b.supply.lo {
+                return Err("the claim never fits in the max supply".into());
+            }
+            a.supply = Interval { lo: b.supply.lo + n, hi: top + n };
+            a.balance = Interval { lo: b.balance.lo + n, hi: b.balance.hi + n };
+            a.locked = Interval { lo: (b.locked.lo - n).max(0), hi: b.locked.hi - n };
+            Interval::exact(0)
+        }
+        Opcode::RemoveLiquidity => return Err("liquidity can only be removed after migration".into()),
+        Opcode::ClaimFees => Interval::exact(n),
+    };
+    a.balance.hi = a.balance.hi.min(a.supply.hi);
+    Ok((sol, a))
+}
+
+fn reserve_at(curve: &CurveKind, supply: i128) -> Option<i128> {
+    curve.reserve_at(i64::try_from(supply).ok()?)
+}
+
+fn market_cap(curve: &CurveKind, supply: i128) -> i128 {
+    let price = i64::try_from(supply).map_or(i128::MAX, |s| curve.spot_price(s));
+    math::mul_div(price, supply, PRICE_SCALE).unwrap_or(i128::MAX)
+}
+
+fn derive_reserve(curve: &CurveKind, supply: Interval, deficit: Interval) -> Option<Interval> {
+    let lo = reserve_at(curve, supply.lo)?.saturating_sub(deficit.hi).max(0);
+    let hi = reserve_at(curve, supply.hi)?.checked_sub(deficit.lo)?;
+    (hi <= MAX).then_some(Interval { lo, hi })
+}
+
+// `reserve_at` never decreases, so neither does the cost of `n` tokens; the
+// extremes sit at the ends of `from`.
+fn cost(curve: &CurveKind, from: Interval, n: i128) -> Option<Interval> {
+    let at = |s: i128| reserve_at(curve, s + n)?.checked_sub(reserve_at(curve, s)?);
+    let (a, b) = (at(from.lo)?, at(from.hi)?);
+    Some(Interval { lo: a.min(b), hi: a.max(b) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::{Bounds as Envelope, compile_launch, compile_program, parse, parse_script, prove};
+    use curvevm::Lbp;
+
+    const DOG: &str = "launch dog {\n  curve linear base_price=10 slope=1/100\n  supply 1_000_000\n}\n";
+
+    fn setup(launch: &str, source: &str) -> (CurveVM, Vec<Instruction>, String) {
+        let vm = compile_launch(&parse_script(launch).unwrap().launch.unwrap()).unwrap();
+        let program = compile_program(&parse(source).unwrap()).unwrap();
+        let cert = prove(&vm, &Envelope::pre_migration(&vm), &program).unwrap();
+        (vm, program, cert.to_json())
+    }
+
+    #[test]
+    fn accepts_what_the_compiler_proves() {
+        let (vm, program, json) = setup(DOG, "BUY 500\nSELL 200\nADD_LIQUIDITY 1000\nMIGRATE_TO_AMM 0\nCLAIM_FEES 5");
+        let cert = check(&program, &json).unwrap();
+        assert_eq!(cert.steps.len(), 5);
+        assert_eq!(check_bytecode(&bytecode::encode(&program), &json), Ok(cert));
+        check_for(&vm, Clock::default(), "alice", &program, &json).unwrap();
+
+        let mut migrated = vm.clone();
+        migrated.execute("bob", &program[..4]).unwrap();
+        assert_eq!(
+            check_for(&migrated, Clock::default(), "bob", &program, &json),
+            Err(Rejection::certificate("the curve's migration state is outside the proof"))
+        );
+
+        let vested = DOG.replace("}", "  vesting alice 1_000 duration=10\n}");
+        let (vm, program, json) = setup(&vested, "BUY 500\nMIGRATE_TO_AMM 0\nCLAIM 1000");
+        check_for(&vm, Clock::default(), "alice", &program, &json).unwrap();
+    }
+
+    #[test]
+    fn certificates_cover_only_the_heights_they_price() {
+        let lbp = Lbp {
+            virtual_reserve: 1_000_000,
+            token_balance: 10_000_000,
+            start_weight_bps: 5_000,
+            end_weight_bps: 9_000,
+            duration: 10,
+            elapsed: 0,
+        };
+        let mut vm = CurveVM::with_curve(CurveKind::Lbp(lbp), 5_000_000).unwrap();
+        vm.execute_at("alice", &compile_program(&parse("BUY 1_000_000").unwrap()).unwrap(), Clock::default()).unwrap();
+        let program = compile_program(&parse("SELL 1_000").unwrap()).unwrap();
+        let at = |height| Clock { height, timestamp: 0 };
+        let mut settled = vm.clone();
+        settled.sync_clock(at(10));
+        let json = prove(&settled, &Envelope::at(&settled, "alice"), &program).unwrap().to_json();
+        check_for(&vm, at(10), "alice", &program, &json).unwrap();
+        check_for(&vm, at(500), "alice", &program, &json).unwrap();
+        assert_eq!(
+            check_for(&vm, at(5), "alice", &program, &json),
+            Err(Rejection::certificate("the certificate does not cover height 5"))
+        );
+
+        let shifting = json.replace("\"elapsed\": 10", "\"elapsed\": 0");
+        assert_eq!(
+            check(&program, &shifting),
+            Err(Rejection::certificate("the curve's weights still shift with the block height"))
+        );
+    }
+
+    #[test]
+    fn rejects_at_the_first_false_claim() {
+        let (_, program, json) = setup(DOG, "BUY 500\nSELL 200\nBUY 10");
+        let tampered = |pointer: &str, value: serde_json::Value| {
+            let mut cert: serde_json::Value = serde_json::from_str(&json).unwrap();
+            *cert.pointer_mut(pointer).unwrap() = value;
+            check(&program, &cert.to_string()).unwrap_err()
+        };
+        let err = tampered("/steps/2/after/balance/lo", 400.into());
+        assert_eq!(err, Rejection::step(2, "claims balance [400, 999810] but it can reach [310, 999810]"));
+        let err = tampered("/steps/0/after/reserve/hi", 0.into());
+        assert_eq!(err.step, Some(0));
+
+        let err = tampered("/steps/1/operand", 20.into());
+        assert_eq!(err, Rejection::step(1, "the step does not describe the instruction"));
+
+        let selling = compile_program(&parse("BUY 500\nSELL 600\nBUY 10").unwrap()).unwrap();
+        let forged = json
+            .replacen("\"operand\": 200", "\"operand\": 600", 1)
+            .replace(&check(&program, &json).unwrap().code_hash, &hex(&bytecode::code_hash(&selling)));
+        assert_eq!(check(&selling, &forged), Err(Rejection::step(1, "sells 600 but the sender may hold 500")));
+
+        let err = check(&program[..2], &json).unwrap_err();
+        assert_eq!(err, Rejection::certificate("the code hash does not match the program"));
+        assert_eq!(tampered("/version", 9.into()), Rejection::certificate("unsupported version 9"));
+    }
+
+    #[test]
+    fn hostile_bounds_are_rejected_instead_of_evaluated() {
+        let program = compile_program(&parse("CLAIM 10000").unwrap()).unwrap();
+        let interval = |lo: i64, hi: i64| serde_json::json!({ "lo": lo, "hi": hi });
+        let certificate = |locked: i64, claimed: i64| {
+            let bounds = |supply, locked, balance| {
+                serde_json::json!({
+                    "phase": "migrated",
+                    "supply": supply,
+                    "reserve": interval(0, 0),
+                    "deficit": interval(0, 0),
+                    "locked": locked,
+                    "liquidity": interval(0, 0),
+                    "balance": balance,
+                })
+            };
+            serde_json::json!({
+                "version": VERSION,
+                "code_hash": hex(&bytecode::code_hash(&program)),
+                "curve": { "exponential": { "base_price": 1, "scale": 1_000 } },
+                "max_supply": 40_000,
+                "migration_threshold": null,
+                "initial": bounds(interval(0, 36_000), interval(locked, 10_000), interval(0, 36_000)),
+                "steps": [{
+                    "index": 0,
+                    "opcode": "CLAIM",
+                    "operand": 10_000,
+                    "sol": interval(0, 0),
+                    "after": bounds(interval(10_000, claimed), interval(0, 0), interval(10_000, claimed)),
+                }],
+            })
+            .to_string()
+        };
+        // The pre-claim supply is capped so the claim lands inside the curve's domain.
+        assert!(check(&program, &certificate(0, 40_000)).is_ok());
+        assert_eq!(
+            check(&program, &certificate(0, 46_000)),
+            Err(Rejection::step(0, "the supply bounds are out of range"))
+        );
+        assert_eq!(
+            check(&program, &certificate(10_000, 40_000)),
+            Err(Rejection::certificate("the supply and locked bounds exceed the max supply"))
+        );
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
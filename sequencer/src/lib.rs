@@ -1,17 +1,18 @@
 use base64::{Engine as _, engine::general_purpose};
 use compiler::Instruction;
-use curvevm::{CurveVM, Opcode};
+use curvevm::{
+    Call, Clock, CostTable, CurveId, CurveStore, CurveVM, Event, ExecutionReceipt, StoreDiff,
+    VmError, bytecode,
+};
 use hotshot::HotShotConsensus;
-use serde::Serialize;
-use serde_json::json;
-use sha2::{Digest, Sha256};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub struct Mempool {
     fast_pool: Vec<Tx>,
     big_pool: Vec<Tx>,
 }
 
+#[derive(Clone)]
 pub struct Tx {
     pub sender: String,
     pub nonce: u64,
@@ -30,6 +31,10 @@ impl Tx {
             timestamp: SystemTime::now(),
         }
     }
+
+    pub fn compute_units(&self) -> u64 {
+        CostTable::default().program_cost(&self.program)
+    }
 }
 
 impl Mempool {
@@ -74,11 +79,40 @@ impl Mempool {
         let txs = pool.drain(0..limit.min(pool.len())).collect();
         txs
     }
+
+    pub fn get_txs_by_compute(&mut self, kind: &str, max_compute: u64) -> Vec<Tx> {
+        self.prune();
+        let pool = self.pool(kind);
+        let mut used = 0u64;
+        let count = pool
+            .iter()
+            .take_while(|tx| {
+                used = used.saturating_add(tx.compute_units());
+                used <= max_compute
+            })
+            .count();
+        pool.drain(0..count).collect()
+    }
 }
 
 pub struct Block {
-    pub program: Vec<Instruction>,
+    pub txs: Vec<Tx>,
     pub kind: String,
+    pub timestamp: u64,
+}
+
+impl Block {
+    pub fn program(&self) -> Vec<Instruction> {
+        self.txs.iter().flat_map(|tx| tx.program.iter().copied()).collect()
+    }
+
+    pub fn compute_units(&self) -> u64 {
+        self.txs.iter().map(Tx::compute_units).sum()
+    }
+
+    pub fn fee(&self, lamports_per_cu: u64) -> u64 {
+        self.compute_units().saturating_mul(lamports_per_cu)
+    }
 }
 
 pub struct RoundRobin<T> {
@@ -104,19 +138,45 @@ impl<T: Clone> Iterator for RoundRobin<T> {
     }
 }
 
-fn state_root(program: &[Instruction]) -> String {
-    let mut vm = CurveVM::new();
-    vm.execute(program);
-    let state = json!({
-        "balance": vm.balance,
-        "liquidity": vm.liquidity,
-        "migrated": vm.migrated_to_amm,
-        "migrate_value": vm.migrate_value,
-    });
-    let encoded = serde_json::to_vec(&state).unwrap();
-    let mut hasher = Sha256::new();
-    hasher.update(&encoded);
-    format!("{:x}", hasher.finalize())
+pub fn genesis_state() -> CurveStore {
+    let mut state = CurveStore::new();
+    state
+        .create(CurveId::default(), CurveVM::new())
+        .expect("fresh store has no curves");
+    state
+}
+
+pub struct BlockLog {
+    pub height: u64,
+    pub root: String,
+    pub receipts: Vec<Result<ExecutionReceipt, VmError>>,
+    pub diff: StoreDiff,
+}
+
+impl BlockLog {
+    pub fn events(&self) -> impl Iterator<Item = &Event> {
+        self.receipts.iter().flatten().flat_map(|r| r.events.iter())
+    }
+}
+
+fn apply_block(
+    state: &CurveStore,
+    txs: &[Tx],
+    clock: Clock,
+) -> (CurveStore, Vec<Result<ExecutionReceipt, VmError>>) {
+    let calls: Vec<Call> = txs
+        .iter()
+        .map(|tx| Call::new(&tx.sender, tx.program.clone()))
+        .collect();
+    let mut next = state.clone();
+    next.clock = clock;
+    // A transaction that fails to execute leaves the state untouched.
+    let receipts = next.execute_block_parallel(&calls);
+    (next, receipts)
+}
+
+fn state_root(state: &CurveStore) -> String {
+    state.root().iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 pub struct FakeSolanaClient {
@@ -145,34 +205,7 @@ impl BatchPoster {
     }
 
     pub fn commit(&mut self, program: &[Instruction]) -> String {
-        #[derive(Serialize)]
-        struct Instr<'a> {
-            op: &'a str,
-            arg: i64,
-        }
-        use std::collections::BTreeMap;
-        let list: Vec<BTreeMap<&str, serde_json::Value>> = program
-            .iter()
-            .map(|ins| {
-                let mut map = BTreeMap::new();
-                let op_str = match ins.opcode {
-                    Opcode::Buy => "BUY",
-                    Opcode::Sell => "SELL",
-                    Opcode::AddLiquidity => "ADD_LIQUIDITY",
-                    Opcode::MigrateToAmm => "MIGRATE_TO_AMM",
-                };
-                map.insert("op", serde_json::Value::String(op_str.into()));
-                map.insert("arg", serde_json::Value::from(ins.operand));
-                map
-            })
-            .collect();
-        let encoded = serde_json::to_string(&list).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(encoded.as_bytes());
-        let root = format!("{:x}", hasher.finalize());
-        let payload = json!({ "root": root, "program": list });
-        let data = serde_json::to_vec(&payload).unwrap();
-        self.client.send_transaction(&data)
+        self.client.send_transaction(&bytecode::encode(program))
     }
 }
 
@@ -186,9 +219,11 @@ impl FakePoster {
 pub struct Consensus {
     engine: HotShotConsensus,
     pub poster: BatchPoster,
+    pub state: CurveStore,
+    pub logs: Vec<BlockLog>,
     validators: Vec<String>,
     schedule: RoundRobin<String>,
-    state_root_hook: Option<Box<dyn FnMut(&[Instruction]) -> String>>,
+    state_root_hook: Option<Box<dyn FnMut(&[Tx]) -> String>>,
 }
 
 impl Consensus {
@@ -197,23 +232,33 @@ impl Consensus {
         Ok(Self {
             engine: HotShotConsensus::new(),
             poster,
+            state: genesis_state(),
+            logs: Vec::new(),
             validators,
             schedule,
             state_root_hook: None,
         })
     }
 
-    fn compute_root(&mut self, program: &[Instruction]) -> String {
+    // The block being proposed executes at the height it will commit at.
+    fn clock(&self, block: &Block) -> Clock {
+        Clock {
+            height: self.engine.height + 1,
+            timestamp: block.timestamp,
+        }
+    }
+
+    fn compute_root(&mut self, block: &Block) -> String {
         if let Some(hook) = self.state_root_hook.as_mut() {
-            hook(program)
+            hook(&block.txs)
         } else {
-            state_root(program)
+            state_root(&apply_block(&self.state, &block.txs, self.clock(block)).0)
         }
     }
 
     pub fn set_state_root_hook<F>(&mut self, f: F)
     where
-        F: FnMut(&[Instruction]) -> String + 'static,
+        F: FnMut(&[Tx]) -> String + 'static,
     {
         self.state_root_hook = Some(Box::new(f));
     }
@@ -222,13 +267,24 @@ impl Consensus {
         let _leader = self.schedule.next().unwrap();
         let mut roots = std::collections::HashSet::new();
         for _ in 0..self.validators.len() {
-            roots.insert(self.compute_root(&block.program));
+            roots.insert(self.compute_root(&block));
         }
         if roots.len() != 1 {
             return Err("State roots diverged".into());
         }
-        self.engine.commit_block(&block.program);
-        Ok(self.poster.commit(&block.program))
+        let (state, receipts) = apply_block(&self.state, &block.txs, self.clock(&block));
+        let root = state_root(&state);
+        let diff = StoreDiff::between(&self.state, &state);
+        self.state = state;
+        let program = block.program();
+        let height = self.engine.commit_block(&program);
+        self.logs.push(BlockLog {
+            height,
+            root,
+            receipts,
+            diff,
+        });
+        Ok(self.poster.commit(&program))
     }
 }
 
@@ -244,13 +300,22 @@ impl Miner {
 
     pub fn mine(&mut self, kind: &str, max_txs: usize) -> Result<String, String> {
         let txs = self.mp.get_txs(kind, max_txs);
-        let mut program = Vec::new();
-        for tx in txs {
-            program.extend(tx.program);
-        }
+        self.commit_txs(kind, txs)
+    }
+
+    pub fn mine_by_compute(&mut self, kind: &str, max_compute: u64) -> Result<String, String> {
+        let txs = self.mp.get_txs_by_compute(kind, max_compute);
+        self.commit_txs(kind, txs)
+    }
+
+    fn commit_txs(&mut self, kind: &str, txs: Vec<Tx>) -> Result<String, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
         let block = Block {
-            program,
+            txs,
             kind: kind.into(),
+            timestamp,
         };
         self.consensus.propose_and_commit(block)
     }
@@ -259,6 +324,7 @@ impl Miner {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use curvevm::{Opcode, VestingSchedule};
 
     #[test]
     fn mempool_nonce_and_prune() {
@@ -266,6 +332,9 @@ mod tests {
         let program = vec![Instruction {
             opcode: Opcode::Buy,
             operand: 1,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
         }];
         let mut old = Tx::new("A".into(), 0, program.clone(), "fast".into());
         old.timestamp = SystemTime::now() - Duration::from_secs(90_000);
@@ -283,6 +352,9 @@ mod tests {
         let program = vec![Instruction {
             opcode: Opcode::Buy,
             operand: 1,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
         }];
         mp.add_tx(Tx::new("A".into(), 0, program.clone(), "fast".into()));
         let poster = BatchPoster::new(FakeSolanaClient::new());
@@ -305,25 +377,25 @@ mod tests {
         let program = vec![Instruction {
             opcode: Opcode::Buy,
             operand: 1,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
         }];
         let poster = BatchPoster::new(FakeSolanaClient::new());
         let mut consensus =
             Consensus::new(vec!["A".into(), "B".into(), "C".into()], poster).unwrap();
         let block = Block {
-            program: program.clone(),
+            txs: vec![Tx::new("A".into(), 0, program.clone(), "fast".into())],
             kind: "fast".into(),
+            timestamp: 0,
         };
         let tx = consensus.propose_and_commit(block).unwrap();
         let sent = consensus.poster.client.sent.last().unwrap();
         assert_eq!(tx, *sent);
         let data = general_purpose::STANDARD.decode(tx).unwrap();
-        let v: serde_json::Value = serde_json::from_slice(&data).unwrap();
-        let instr_list = v.get("program").unwrap();
-        let encoded = serde_json::to_string(instr_list).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(encoded.as_bytes());
-        let expected_root = format!("{:x}", hasher.finalize());
-        assert_eq!(v.get("root").unwrap().as_str().unwrap(), expected_root);
+        let (header, posted) = bytecode::decode(&data).unwrap();
+        assert_eq!(posted, program);
+        assert_eq!(header.code_hash, bytecode::code_hash(&program));
     }
 
     #[test]
@@ -338,6 +410,9 @@ mod tests {
         let program = vec![Instruction {
             opcode: Opcode::Buy,
             operand: 1,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
         }];
         let poster = BatchPoster::new(FakeSolanaClient::new());
         let mut consensus = Consensus::new(vec!["A".into(), "B".into()], poster).unwrap();
@@ -348,9 +423,211 @@ mod tests {
             if c == 0 { "a".into() } else { "b".into() }
         });
         let block = Block {
-            program,
+            txs: vec![Tx::new("A".into(), 0, program, "fast".into())],
             kind: "fast".into(),
+            timestamp: 0,
         };
         assert!(consensus.propose_and_commit(block).is_err());
     }
+
+    #[test]
+    fn state_root_tracks_senders() {
+        let buy = vec![Instruction {
+            opcode: Opcode::Buy,
+            operand: 1,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
+        }];
+        let root = |txs: &[Tx]| state_root(&apply_block(&genesis_state(), txs, Clock::default()).0);
+        let alice = Tx::new("Alice".into(), 0, buy.clone(), "fast".into());
+        let bob = Tx::new("Bob".into(), 0, buy.clone(), "fast".into());
+        assert_ne!(
+            root(std::slice::from_ref(&alice)),
+            root(std::slice::from_ref(&bob))
+        );
+        assert_eq!(
+            root(&[alice.clone(), bob.clone()]),
+            root(&[alice.clone(), bob])
+        );
+        let oversell = Tx::new(
+            "Carol".into(),
+            0,
+            vec![Instruction {
+                opcode: Opcode::Sell,
+                operand: 1,
+                curve: CurveId::default(),
+                limit: 0,
+                expiry: 0,
+            }],
+            "fast".into(),
+        );
+        assert_eq!(root(&[alice.clone(), oversell]), root(&[alice]));
+    }
+
+    #[test]
+    fn mempool_packs_by_compute() {
+        let mut mp = Mempool::new();
+        let buy = Instruction {
+            opcode: Opcode::Buy,
+            operand: 1,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
+        };
+        let migrate = Instruction {
+            opcode: Opcode::MigrateToAmm,
+            operand: 1,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
+        };
+        mp.add_tx(Tx::new("A".into(), 0, vec![buy; 2], "fast".into()));
+        mp.add_tx(Tx::new("B".into(), 0, vec![migrate], "fast".into()));
+        mp.add_tx(Tx::new("C".into(), 0, vec![buy], "fast".into()));
+        let first = Tx::new("A".into(), 0, vec![buy; 2], "fast".into()).compute_units();
+        let txs = mp.get_txs_by_compute("fast", first + 1);
+        assert_eq!(txs.len(), 1);
+        let block = Block {
+            txs,
+            kind: "fast".into(),
+            timestamp: 0,
+        };
+        assert_eq!(block.compute_units(), first);
+        assert_eq!(block.fee(2), first * 2);
+        assert_eq!(mp.get_txs_by_compute("fast", u64::MAX).len(), 2);
+    }
+
+    #[test]
+    fn state_persists_across_blocks_and_curves() {
+        let dog = CurveId::derive("Alice", "dog");
+        let poster = BatchPoster::new(FakeSolanaClient::new());
+        let mut consensus = Consensus::new(vec!["A".into()], poster).unwrap();
+        consensus.state.create(dog, CurveVM::new()).unwrap();
+        for (nonce, curve) in [CurveId::default(), dog].into_iter().enumerate() {
+            let program = vec![Instruction {
+                opcode: Opcode::Buy,
+                operand: 2,
+                curve,
+                limit: 0,
+                expiry: 0,
+            }];
+            let block = Block {
+                txs: vec![Tx::new("Bob".into(), nonce as u64, program, "fast".into())],
+                kind: "fast".into(),
+                timestamp: 0,
+            };
+            consensus.propose_and_commit(block).unwrap();
+        }
+        let state = &consensus.state;
+        assert_eq!(state.get(&CurveId::default()).unwrap().balance_of("Bob"), 2);
+        assert_eq!(state.get(&dog).unwrap().balance_of("Bob"), 2);
+    }
+
+    #[test]
+    fn block_logs_keep_receipts_and_events() {
+        let program = vec![
+            Instruction {
+                opcode: Opcode::Buy,
+                operand: 3,
+                curve: CurveId::default(),
+                limit: 0,
+                expiry: 0,
+            },
+            Instruction {
+                opcode: Opcode::Sell,
+                operand: 1,
+                curve: CurveId::default(),
+                limit: 0,
+                expiry: 0,
+            },
+        ];
+        let oversell = vec![Instruction {
+            opcode: Opcode::Sell,
+            operand: 5,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
+        }];
+        let poster = BatchPoster::new(FakeSolanaClient::new());
+        let mut consensus = Consensus::new(vec!["A".into()], poster).unwrap();
+        let block = Block {
+            txs: vec![
+                Tx::new("Alice".into(), 0, program, "fast".into()),
+                Tx::new("Bob".into(), 0, oversell, "fast".into()),
+            ],
+            kind: "fast".into(),
+            timestamp: 0,
+        };
+        consensus.propose_and_commit(block).unwrap();
+        let log = consensus.logs.last().unwrap();
+        assert_eq!(log.height, 1);
+        assert_eq!(log.root, state_root(&consensus.state));
+        assert_eq!(log.receipts[0].as_ref().unwrap().trace.len(), 2);
+        assert!(log.receipts[1].is_err());
+        assert_eq!(log.events().count(), 2);
+
+        let mut replay = genesis_state();
+        log.diff.apply(&mut replay).unwrap();
+        assert_eq!(state_root(&replay), log.root);
+        assert_eq!(log.diff.curves[0].1.accounts.len(), 1);
+    }
+
+    #[test]
+    fn claims_follow_block_timestamps() {
+        let claim = vec![Instruction {
+            opcode: Opcode::Claim,
+            operand: 500,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 0,
+        }];
+        let poster = BatchPoster::new(FakeSolanaClient::new());
+        let mut consensus = Consensus::new(vec!["A".into()], poster).unwrap();
+        let vm = consensus.state.curves.get_mut(&CurveId::default()).unwrap();
+        vm.add_vesting("Creator", VestingSchedule::new(1_000, 100, 50, 100))
+            .unwrap();
+        // Vesting is only released once the curve trades in its pool.
+        let open =
+            [Opcode::Buy, Opcode::MigrateToAmm].map(|opcode| Instruction { opcode, ..claim[0] });
+        vm.execute("Alice", &open).unwrap();
+        for (timestamp, vested) in [(120, false), (150, true)] {
+            let block = Block {
+                txs: vec![Tx::new("Creator".into(), 0, claim.clone(), "fast".into())],
+                kind: "fast".into(),
+                timestamp,
+            };
+            consensus.propose_and_commit(block).unwrap();
+            let receipt = &consensus.logs.last().unwrap().receipts[0];
+            assert_eq!(receipt.is_ok(), vested, "{:?}", receipt);
+        }
+        let vm = consensus.state.get(&CurveId::default()).unwrap();
+        assert_eq!(vm.balance_of("Creator"), 500);
+    }
+
+    #[test]
+    fn expired_trades_are_dropped_at_their_height() {
+        let buy = vec![Instruction {
+            opcode: Opcode::Buy,
+            operand: 10,
+            curve: CurveId::default(),
+            limit: 0,
+            expiry: 2,
+        }];
+        let poster = BatchPoster::new(FakeSolanaClient::new());
+        let mut consensus = Consensus::new(vec!["A".into()], poster).unwrap();
+        for (height, valid) in [(1, true), (2, true), (3, false)] {
+            let block = Block {
+                txs: vec![Tx::new("Alice".into(), 0, buy.clone(), "fast".into())],
+                kind: "fast".into(),
+                timestamp: 0,
+            };
+            consensus.propose_and_commit(block).unwrap();
+            let log = consensus.logs.last().unwrap();
+            assert_eq!(log.height, height);
+            assert_eq!(log.receipts[0].is_ok(), valid, "{:?}", log.receipts[0]);
+        }
+        let vm = consensus.state.get(&CurveId::default()).unwrap();
+        assert_eq!(vm.balance_of("Alice"), 20);
+    }
 }
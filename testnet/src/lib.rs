@@ -1,6 +1,6 @@
 use assetscript::{emit_manifest, manifest_to_json, parse};
 use assetvm::{AssetVM, Instruction, Opcode};
-use compiler::{compile_program, parse as parse_curve_program};
+use compiler::{compile_program, parse as parse_curve_program, render};
 use sequencer::{BatchPoster, Consensus, FakeSolanaClient, Mempool, Miner, Tx};
 use std::error::Error;
 
@@ -55,7 +55,8 @@ pub fn run_demo() -> Result<(i64, String), Box<dyn Error>> {
     asset_vm.execute(&asset_prog);
 
     let curve_script = "BUY 5\nSELL 2\nADD_LIQUIDITY 3\nMIGRATE_TO_AMM 1";
-    let curve_cmds = parse_curve_program(curve_script)?;
+    let curve_cmds =
+        parse_curve_program(curve_script).map_err(|errors| render(curve_script, &errors))?;
     let curve_prog = compile_program(&curve_cmds)?;
 
     let poster = BatchPoster::new(FakeSolanaClient::new());
@@ -141,13 +142,15 @@ mod tests {
         let poster = BatchPoster::new(FakeSolanaClient::new());
         let mut consensus = Consensus::new(vec!["A".into()], poster).unwrap();
         let block = sequencer::Block {
-            program: Vec::new(),
+            txs: Vec::new(),
             kind: "fast".into(),
+            timestamp: 0,
         };
         let sig1 = consensus.propose_and_commit(block).unwrap();
         let block = sequencer::Block {
-            program: Vec::new(),
+            txs: Vec::new(),
             kind: "fast".into(),
+            timestamp: 0,
         };
         let sig2 = consensus.propose_and_commit(block).unwrap();
         assert!(!sig1.is_empty() && !sig2.is_empty());